@@ -0,0 +1,51 @@
+/*
+ * C interface of the idoc library (libidoc.so, idoc.dll, libidoc.dylib),
+ * built with `cargo build --release`. Matches src/ffi.rs.
+ *
+ * Every conversion returns 0 and fills *out with a buffer to release with
+ * idoc_free_buffer, or returns -1 and leaves the reason in
+ * idoc_last_error().
+ */
+#ifndef IDOC_H
+#define IDOC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#ifndef IDOC_BUFFER_DEFINED
+#define IDOC_BUFFER_DEFINED
+typedef struct IdocBuffer {
+    uint8_t *data;
+    size_t len;
+} IdocBuffer;
+#endif
+
+/* Decompile a whole .ido file: XML payloads come back as UTF-8 XML with
+ * the metadata comments that compile it back, shop databases as CSV, and
+ * anything else as the raw payload. */
+int idoc_decompile_buffer(const uint8_t *data, size_t len, IdocBuffer *out);
+
+/* Compile decompiled UTF-8 XML, with its metadata comments, back into a
+ * .ido file. */
+int idoc_compile_buffer(const uint8_t *data, size_t len, IdocBuffer *out);
+
+/* Parse a stored shop database into a JSON array with one object per
+ * record, keyed by field name. */
+int idoc_parse_shopdb(const uint8_t *data, size_t len, IdocBuffer *out);
+
+/* Release a buffer returned by one of the conversions. */
+void idoc_free_buffer(IdocBuffer buffer);
+
+/* Why the last failed call on this thread failed, as UTF-8. Valid until
+ * the next failing call on the same thread. */
+const char *idoc_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IDOC_H */
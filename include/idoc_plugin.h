@@ -0,0 +1,65 @@
+/*
+ * Interface of an idoc payload plugin: a dynamic library in a plugins
+ * directory that decompiles and compiles a payload type idoc doesn't
+ * know. Matches src/plugin.rs.
+ *
+ * idoc asks each plugin about every decompressed payload, before its own
+ * detection, so plugins can also take over payloads idoc would misread;
+ * the first to say yes converts it. The plugin's name
+ * is recorded in the .meta file, so compiling the output calls the same
+ * plugin to turn it back into the payload.
+ *
+ * Conversions return 0 and fill *out with a buffer idoc hands back to
+ * idoc_plugin_free, or return -1, with the reason in
+ * idoc_plugin_last_error() if the plugin exports it.
+ */
+#ifndef IDOC_PLUGIN_H
+#define IDOC_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define IDOC_PLUGIN_API 1
+
+#ifndef IDOC_BUFFER_DEFINED
+#define IDOC_BUFFER_DEFINED
+typedef struct IdocBuffer {
+    uint8_t *data;
+    size_t len;
+} IdocBuffer;
+#endif
+
+/* The IDOC_PLUGIN_API the plugin was written against. */
+uint32_t idoc_plugin_api(void);
+
+/* Short name, recorded in .meta files; letters, digits, '-' and '_'. */
+const char *idoc_plugin_name(void);
+
+/* Extension of decompiled output, without the dot; not empty, and without
+ * path separators or "..". */
+const char *idoc_plugin_extension(void);
+
+/* Nonzero when the plugin handles this decompressed payload. */
+int idoc_plugin_detect(const uint8_t *data, size_t len);
+
+/* Payload to its decompiled form. */
+int idoc_plugin_decompile(const uint8_t *data, size_t len, IdocBuffer *out);
+
+/* Decompiled form back to the payload. */
+int idoc_plugin_compile(const uint8_t *data, size_t len, IdocBuffer *out);
+
+/* Release a buffer returned by a conversion. */
+void idoc_plugin_free(IdocBuffer buffer);
+
+/* Optional: why the last failed conversion on this thread failed. */
+const char *idoc_plugin_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IDOC_PLUGIN_H */
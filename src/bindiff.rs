@@ -0,0 +1,83 @@
+//! `.idopatch` files: a bsdiff of the decompressed payloads plus the
+//! metadata needed to rebuild the new file around the patched payload.
+//!
+//! Layout: `IDOPATCH` magic, a version byte, SHA-256 of the old and new
+//! payloads, a little-endian u32 length and that many bytes of `.meta`
+//! sidecar text (empty for files stored uncompressed), then the
+//! zlib-compressed bsdiff stream.
+
+use crate::compression::{self, Compression};
+use crate::ido::{self, IdoFile};
+use crate::meta::Meta;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+
+const MAGIC: &[u8; 8] = b"IDOPATCH";
+const VERSION: u8 = 1;
+
+/// Build a patch that turns `old` into `new`.
+pub fn diff(old: &IdoFile, new: &IdoFile) -> io::Result<Vec<u8>> {
+    let mut delta = Vec::new();
+    bsdiff::diff(&old.data, &new.data, &mut delta)?;
+
+    let meta = new.meta.as_ref().map(Meta::to_sidecar).unwrap_or_default();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&Sha256::digest(&old.data));
+    out.extend_from_slice(&Sha256::digest(&new.data));
+    out.write_u32::<LittleEndian>(meta.len() as u32)?;
+    out.extend_from_slice(meta.as_bytes());
+    out.extend_from_slice(&compression::compress(&delta, Compression::Zlib)?);
+    Ok(out)
+}
+
+/// Apply a patch to `old`, returning the bytes of the new file.
+pub fn apply(old: &IdoFile, patch: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut reader = patch
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or_else(|| invalid("Not an .idopatch file"))?;
+    let version = reader.read_u8()?;
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "Unsupported .idopatch version {}",
+            version
+        )));
+    }
+
+    let mut old_hash = [0u8; 32];
+    let mut new_hash = [0u8; 32];
+    reader.read_exact(&mut old_hash)?;
+    reader.read_exact(&mut new_hash)?;
+    if Sha256::digest(&old.data).as_slice() != old_hash {
+        return Err(invalid(
+            "Patch was made against a different version of this file",
+        ));
+    }
+
+    let meta_len = reader.read_u32::<LittleEndian>()? as usize;
+    if reader.len() < meta_len {
+        return Err(invalid("Truncated .idopatch file"));
+    }
+    let (meta, delta) = reader.split_at(meta_len);
+    let meta = std::str::from_utf8(meta).map_err(|_| invalid("Corrupt metadata in patch"))?;
+
+    let delta = compression::decompress_with(delta, Compression::Zlib)?.data;
+    let mut data = Vec::new();
+    bsdiff::patch(&old.data, &mut delta.as_slice(), &mut data)?;
+    if Sha256::digest(&data).as_slice() != new_hash {
+        return Err(invalid(
+            "Patched payload does not match the expected checksum",
+        ));
+    }
+
+    if meta.is_empty() {
+        Ok(data)
+    } else {
+        ido::encode_payload(&Meta::parse_sidecar(meta)?, &data)
+    }
+}
@@ -0,0 +1,410 @@
+//! `idoc browse`: a terminal UI showing a database as a record table or
+//! XML as a collapsible tree, with search and a panel inspecting the
+//! selected record or element.
+
+use crate::ido::{DecodeOptions, IdoFile, PayloadKind};
+use crate::layout::{Layout, Value};
+use crate::xml::{Document, Node};
+use encoding_rs::EUC_KR;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout as Split, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, List, ListItem, ListState, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashSet;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+/// One XML node in document order.
+struct TreeNode {
+    depth: usize,
+    label: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    parent: Option<usize>,
+    /// Index just past this node's descendants.
+    end: usize,
+}
+
+enum View {
+    Records {
+        layout: Layout,
+        records: Vec<Vec<Value>>,
+    },
+    Tree {
+        nodes: Vec<TreeNode>,
+        collapsed: HashSet<usize>,
+    },
+}
+
+struct App {
+    title: String,
+    view: View,
+    /// Index of the selected record or node.
+    selected: usize,
+    /// Search text being typed, if the search prompt is open.
+    input: Option<String>,
+    query: String,
+    message: String,
+}
+
+pub fn browse(path: &Path, options: &DecodeOptions, layout: Layout) -> io::Result<()> {
+    let file = IdoFile::read(path, options)?;
+    let view = match file.kind {
+        PayloadKind::ShopDb => View::Records {
+            records: layout.read_records(&file.data),
+            layout,
+        },
+        PayloadKind::Xml => {
+            let document = Document::parse(&EUC_KR.decode(&file.data).0)?;
+            let mut nodes = Vec::new();
+            flatten(&Node::Element(document.root), 0, None, &mut nodes);
+            View::Tree {
+                nodes,
+                collapsed: HashSet::new(),
+            }
+        }
+        kind => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("browse shows databases and XML, not {}", kind.description()),
+            ));
+        }
+    };
+    let mut app = App {
+        title: format!("{} ({})", path.display(), file.kind.description()),
+        view,
+        selected: 0,
+        input: None,
+        query: String::new(),
+        message: String::new(),
+    };
+
+    if !io::stdout().is_terminal() {
+        return Err(io::Error::other("browse needs a terminal"));
+    }
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+fn flatten(node: &Node, depth: usize, parent: Option<usize>, nodes: &mut Vec<TreeNode>) {
+    let index = nodes.len();
+    let (label, attributes, text) = match node {
+        Node::Element(element) => {
+            let text = element
+                .children
+                .iter()
+                .filter_map(|c| match c {
+                    Node::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            let attributes = element
+                .attributes
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            (element.name.clone(), attributes, text)
+        }
+        Node::Text(text) => ("#text".to_string(), Vec::new(), text.clone()),
+        Node::Comment { comment } => ("#comment".to_string(), Vec::new(), comment.clone()),
+    };
+    nodes.push(TreeNode {
+        depth,
+        label,
+        attributes,
+        text,
+        parent,
+        end: index + 1,
+    });
+    if let Node::Element(element) = node {
+        // Text is shown on its element rather than as a node of its own.
+        for child in element
+            .children
+            .iter()
+            .filter(|c| !matches!(c, Node::Text(_)))
+        {
+            flatten(child, depth + 1, Some(index), nodes);
+        }
+    }
+    nodes[index].end = nodes.len();
+}
+
+impl TreeNode {
+    fn summary(&self) -> String {
+        let mut line = self.label.clone();
+        for (key, value) in &self.attributes {
+            line.push_str(&format!(" {}=\"{}\"", key, value));
+        }
+        if !self.text.is_empty() {
+            line.push_str(&format!(": {}", self.text));
+        }
+        line
+    }
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(input) = &mut self.input {
+                match key.code {
+                    KeyCode::Enter => {
+                        self.query = self.input.take().unwrap_or_default();
+                        self.find(true);
+                    }
+                    KeyCode::Esc => self.input = None,
+                    KeyCode::Backspace => {
+                        input.pop();
+                    }
+                    KeyCode::Char(c) => input.push(c),
+                    _ => {}
+                }
+                continue;
+            }
+            self.message.clear();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.step(1),
+                KeyCode::Up | KeyCode::Char('k') => self.step(-1),
+                KeyCode::PageDown => self.step(20),
+                KeyCode::PageUp => self.step(-20),
+                KeyCode::Home | KeyCode::Char('g') => self.step(isize::MIN),
+                KeyCode::End | KeyCode::Char('G') => self.step(isize::MAX),
+                KeyCode::Enter | KeyCode::Char(' ') => self.toggle(),
+                KeyCode::Char('/') => self.input = Some(String::new()),
+                KeyCode::Char('n') => self.find(true),
+                KeyCode::Char('N') => self.find(false),
+                _ => {}
+            }
+        }
+    }
+
+    /// Indices of the records or nodes currently listed.
+    fn visible(&self) -> Vec<usize> {
+        match &self.view {
+            View::Records { records, .. } => (0..records.len()).collect(),
+            View::Tree { nodes, collapsed } => {
+                let mut visible = Vec::new();
+                let mut i = 0;
+                while i < nodes.len() {
+                    visible.push(i);
+                    i = if collapsed.contains(&i) {
+                        nodes[i].end
+                    } else {
+                        i + 1
+                    };
+                }
+                visible
+            }
+        }
+    }
+
+    fn step(&mut self, by: isize) {
+        let visible = self.visible();
+        let Some(position) = visible.iter().position(|&i| i == self.selected) else {
+            return;
+        };
+        // `visible` holds `selected`, so it is not empty here.
+        let target = position.saturating_add_signed(by).min(visible.len() - 1);
+        self.selected = visible[target];
+    }
+
+    fn toggle(&mut self) {
+        if let View::Tree { nodes, collapsed } = &mut self.view
+            && nodes[self.selected].end > self.selected + 1
+            && !collapsed.remove(&self.selected)
+        {
+            collapsed.insert(self.selected);
+        }
+    }
+
+    fn text_of(&self, index: usize) -> String {
+        match &self.view {
+            View::Records { records, .. } => records[index]
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<_>>()
+                .join("\t"),
+            View::Tree { nodes, .. } => nodes[index].summary(),
+        }
+    }
+
+    /// Select the next (or previous) match of the query, wrapping around.
+    fn find(&mut self, forward: bool) {
+        if self.query.is_empty() {
+            return;
+        }
+        let count = match &self.view {
+            View::Records { records, .. } => records.len(),
+            View::Tree { nodes, .. } => nodes.len(),
+        };
+        let query = self.query.to_lowercase();
+        let found = (1..=count)
+            .map(|offset| {
+                if forward {
+                    (self.selected + offset) % count
+                } else {
+                    (self.selected + count - offset) % count
+                }
+            })
+            .find(|&i| self.text_of(i).to_lowercase().contains(&query));
+        let Some(index) = found else {
+            self.message = format!("No match for '{}'", self.query);
+            return;
+        };
+        if let View::Tree { nodes, collapsed } = &mut self.view {
+            let mut parent = nodes[index].parent;
+            while let Some(p) = parent {
+                collapsed.remove(&p);
+                parent = nodes[p].parent;
+            }
+        }
+        self.selected = index;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, footer] =
+            Split::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list, inspector] =
+            Split::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(main);
+
+        match &self.view {
+            View::Records { layout, records } => {
+                self.draw_records(frame, list, inspector, layout, records)
+            }
+            View::Tree { nodes, collapsed } => {
+                self.draw_tree(frame, list, inspector, nodes, collapsed)
+            }
+        }
+
+        let footer_text = match &self.input {
+            Some(input) => format!("/{}", input),
+            None if !self.message.is_empty() => self.message.clone(),
+            None => {
+                "q quit  ↑↓ move  / search  n/N next/previous  Enter expand/collapse".to_string()
+            }
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    fn draw_records(
+        &self,
+        frame: &mut Frame,
+        list: Rect,
+        inspector: Rect,
+        layout: &Layout,
+        records: &[Vec<Value>],
+    ) {
+        let header = Row::new(
+            std::iter::once("#".to_string()).chain(layout.fields.iter().map(|f| f.name.clone())),
+        )
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let rows = records.iter().enumerate().map(|(i, record)| {
+            Row::new(std::iter::once(i.to_string()).chain(record.iter().map(Value::to_string)))
+        });
+        let widths = std::iter::once(Constraint::Length(5))
+            .chain(layout.fields.iter().map(|_| Constraint::Min(8)));
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(self.title.as_str()))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(table, list, &mut state);
+
+        let Some(record) = records.get(self.selected) else {
+            return;
+        };
+        let rows = layout.fields.iter().zip(record).map(|(field, value)| {
+            Row::new([
+                Cell::from(field.name.clone()),
+                Cell::from(format!("0x{:03X} {}", field.offset, field.kind.name())),
+                Cell::from(value.to_string()),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(35),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+            ],
+        )
+        .block(Block::bordered().title(format!("Record {}", self.selected)));
+        frame.render_widget(table, inspector);
+    }
+
+    fn draw_tree(
+        &self,
+        frame: &mut Frame,
+        list: Rect,
+        inspector: Rect,
+        nodes: &[TreeNode],
+        collapsed: &HashSet<usize>,
+    ) {
+        let visible = self.visible();
+        let items = visible.iter().map(|&i| {
+            let node = &nodes[i];
+            let marker = if node.end == i + 1 {
+                "  "
+            } else if collapsed.contains(&i) {
+                "+ "
+            } else {
+                "- "
+            };
+            ListItem::new(format!(
+                "{}{}{}",
+                "  ".repeat(node.depth),
+                marker,
+                node.summary()
+            ))
+        });
+        let tree = List::new(items)
+            .block(Block::bordered().title(self.title.as_str()))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state =
+            ListState::default().with_selected(visible.iter().position(|&i| i == self.selected));
+        frame.render_stateful_widget(tree, list, &mut state);
+
+        let node = &nodes[self.selected];
+        let mut path = vec![node.label.as_str()];
+        let mut parent = node.parent;
+        while let Some(p) = parent {
+            path.push(&nodes[p].label);
+            parent = nodes[p].parent;
+        }
+        path.reverse();
+        let mut lines = vec![Line::from(path.join("/")), Line::from("")];
+        lines.extend(
+            node.attributes
+                .iter()
+                .map(|(key, value)| Line::from(format!("{} = {}", key, value))),
+        );
+        if !node.text.is_empty() {
+            lines.push(Line::from(""));
+            lines.push(Line::from(node.text.clone()));
+        }
+        let children = nodes[self.selected + 1..node.end]
+            .iter()
+            .filter(|n| n.parent == Some(self.selected))
+            .count();
+        lines.push(Line::from(""));
+        lines.push(Line::from(format!("{} children", children)));
+        frame.render_widget(
+            Paragraph::new(lines)
+                .wrap(ratatui::widgets::Wrap { trim: false })
+                .block(Block::bordered().title(node.label.as_str())),
+            inspector,
+        );
+    }
+}
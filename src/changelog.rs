@@ -0,0 +1,219 @@
+//! Markdown summary of what changed between two data directories.
+
+use crate::ido::{DecodeOptions, IdoFile, PayloadKind};
+use crate::layout::{Layout, Value};
+use crate::merge;
+use crate::walk;
+use crate::xml::{self, Node};
+use encoding_rs::EUC_KR;
+use indexmap::IndexMap;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+pub fn write(old_dir: &Path, new_dir: &Path) -> io::Result<String> {
+    let relative = |dir: &Path| -> io::Result<BTreeSet<String>> {
+        Ok(walk::ido_files(dir)?
+            .iter()
+            .map(|p| walk::relative(p, dir))
+            .collect())
+    };
+    let old_files = relative(old_dir)?;
+    let new_files = relative(new_dir)?;
+
+    let mut out = String::new();
+    writeln!(out, "# Changelog\n").unwrap();
+    writeln!(
+        out,
+        "Comparing `{}` to `{}`.",
+        old_dir.display(),
+        new_dir.display()
+    )
+    .unwrap();
+
+    let added: Vec<_> = new_files.difference(&old_files).collect();
+    let removed: Vec<_> = old_files.difference(&new_files).collect();
+    for (title, files) in [("Added files", &added), ("Removed files", &removed)] {
+        if !files.is_empty() {
+            writeln!(out, "\n## {} ({})\n", title, files.len()).unwrap();
+            for file in files.iter() {
+                writeln!(out, "- `{}`", file).unwrap();
+            }
+        }
+    }
+
+    let options = DecodeOptions::default();
+    let mut changed = Vec::new();
+    for name in old_files.intersection(&new_files) {
+        let old = IdoFile::read(&old_dir.join(name), &options);
+        let new = IdoFile::read(&new_dir.join(name), &options);
+        let lines = match (old, new) {
+            (Ok(old), Ok(new)) => describe(&old, &new),
+            (Err(e), _) | (_, Err(e)) => vec![format!("Could not decode: {}", e)],
+        };
+        if !lines.is_empty() {
+            changed.push((name, lines));
+        }
+    }
+
+    if added.is_empty() && removed.is_empty() && changed.is_empty() {
+        writeln!(out, "\nNo changes.").unwrap();
+    }
+    if !changed.is_empty() {
+        writeln!(out, "\n## Changed files ({})", changed.len()).unwrap();
+        for (name, lines) in changed {
+            writeln!(out, "\n### `{}`\n", name).unwrap();
+            for line in lines {
+                writeln!(out, "- {}", line).unwrap();
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Changes between two versions of a file; empty when they are the same.
+fn describe(old: &IdoFile, new: &IdoFile) -> Vec<String> {
+    let mut lines = Vec::new();
+    let header = |f: &IdoFile| f.meta.as_ref().and_then(|m| m.header.clone());
+    if header(old) != header(new) {
+        lines.push("Header changed".to_string());
+    }
+    if old.data == new.data {
+        return lines;
+    }
+
+    match (old.kind, new.kind) {
+        (PayloadKind::ShopDb, PayloadKind::ShopDb) => {
+            let layout = Layout::builtin("shop").expect("shop layout is built in");
+            lines.extend(describe_records(&layout, &old.data, &new.data));
+        }
+        (PayloadKind::Xml, PayloadKind::Xml) => {
+            let parse = |f: &IdoFile| xml::Document::parse(&EUC_KR.decode(&f.data).0);
+            match (parse(old), parse(new)) {
+                (Ok(old), Ok(new)) => {
+                    lines.extend(describe_elements(&old.root.children, &new.root.children))
+                }
+                _ => lines.push("XML changed (not well-formed, so not compared)".to_string()),
+            }
+        }
+        (old_kind, new_kind) if old_kind != new_kind => lines.push(format!(
+            "Type changed from {} to {}",
+            old_kind.description(),
+            new_kind.description()
+        )),
+        (kind, _) => lines.push(format!(
+            "{} changed ({} -> {} bytes)",
+            kind.description(),
+            old.data.len(),
+            new.data.len()
+        )),
+    }
+    lines
+}
+
+fn describe_records(layout: &Layout, old: &[u8], new: &[u8]) -> Vec<String> {
+    let old = keyed_records(layout, old);
+    let new = keyed_records(layout, new);
+
+    let mut lines = Vec::new();
+    for (key, record) in &new {
+        match old.get(key) {
+            None => lines.push(format!("Added record {}", key)),
+            Some(before) if before != record => {
+                let fields: Vec<String> = layout
+                    .fields
+                    .iter()
+                    .zip(before.iter().zip(record))
+                    .filter(|(_, (a, b))| a != b)
+                    .map(|(field, (a, b))| format!("{} `{}` -> `{}`", field.name, a, b))
+                    .collect();
+                lines.push(format!("Changed record {}: {}", key, fields.join(", ")));
+            }
+            Some(_) => {}
+        }
+    }
+    for key in old.keys().filter(|k| !new.contains_key(*k)) {
+        lines.push(format!("Removed record {}", key));
+    }
+    lines
+}
+
+/// Records by their key fields, or by index when the layout has no key
+/// or it isn't unique.
+fn keyed_records(layout: &Layout, data: &[u8]) -> IndexMap<String, Vec<Value>> {
+    let records = layout.read_records(data);
+    let key_of = |record: &[Value]| {
+        let parts: Vec<String> = layout
+            .fields
+            .iter()
+            .zip(record)
+            .filter(|(f, _)| layout.key.contains(&f.name))
+            .map(|(f, v)| format!("{}={}", f.name, v))
+            .collect();
+        format!("`{}`", parts.join(", "))
+    };
+
+    let mut keyed = IndexMap::new();
+    if !layout.key.is_empty() {
+        for record in &records {
+            keyed.insert(key_of(record), record.clone());
+        }
+        if keyed.len() == records.len() {
+            return keyed;
+        }
+        keyed.clear();
+    }
+    for (i, record) in records.into_iter().enumerate() {
+        keyed.insert(format!("#{}", i), record);
+    }
+    keyed
+}
+
+fn describe_elements(old: &[Node], new: &[Node]) -> Vec<String> {
+    let old = merge::keyed(old);
+    let new = merge::keyed(new);
+    let name = |(tag, id): &(String, String)| format!("`{}{}`", tag, id);
+
+    let mut lines = Vec::new();
+    for (key, node) in &new {
+        if key.0.starts_with('#') {
+            continue;
+        }
+        match old.get(key) {
+            None => lines.push(format!("Added {}", name(key))),
+            Some(before) if before != node => {
+                let detail = match (before, node) {
+                    (Node::Element(a), Node::Element(b)) if a.children == b.children => {
+                        let attrs: Vec<String> = b
+                            .attributes
+                            .iter()
+                            .filter(|(k, v)| a.attributes.get(*k) != Some(*v))
+                            .map(|(k, v)| match a.attributes.get(k) {
+                                Some(was) => format!("{} `{}` -> `{}`", k, was, v),
+                                None => format!("{} = `{}`", k, v),
+                            })
+                            .chain(
+                                a.attributes
+                                    .keys()
+                                    .filter(|k| !b.attributes.contains_key(*k))
+                                    .map(|k| format!("{} removed", k)),
+                            )
+                            .collect();
+                        attrs.join(", ")
+                    }
+                    _ => "contents changed".to_string(),
+                };
+                lines.push(format!("Changed {}: {}", name(key), detail));
+            }
+            Some(_) => {}
+        }
+    }
+    for key in old
+        .keys()
+        .filter(|k| !k.0.starts_with('#') && !new.contains_key(*k))
+    {
+        lines.push(format!("Removed {}", name(key)));
+    }
+    lines
+}
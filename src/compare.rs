@@ -0,0 +1,92 @@
+//! Directory comparison by decompressed payload, so files that were only
+//! recompressed count as identical.
+
+use crate::ido::{DecodeOptions, IdoFile};
+use crate::walk;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub identical: Vec<String>,
+    pub changed: Vec<String>,
+    /// Same payload, different header.
+    pub header_only: Vec<String>,
+    pub only_a: Vec<String>,
+    pub only_b: Vec<String>,
+}
+
+/// What a file's content is, for comparison purposes.
+#[derive(PartialEq)]
+struct Fingerprint {
+    payload: String,
+    header: Option<Vec<u8>>,
+}
+
+fn fingerprint(path: &Path) -> io::Result<Fingerprint> {
+    match IdoFile::read(path, &DecodeOptions::default()) {
+        Ok(file) => Ok(Fingerprint {
+            payload: hex::encode(Sha256::digest(&file.data)),
+            header: file.meta.and_then(|m| m.header),
+        }),
+        // Undecodable files are compared byte for byte.
+        Err(_) => Ok(Fingerprint {
+            payload: hex::encode(Sha256::digest(fs::read(path)?)),
+            header: None,
+        }),
+    }
+}
+
+pub fn compare(a: &Path, b: &Path) -> io::Result<Report> {
+    let relative = |dir: &Path| -> io::Result<BTreeSet<String>> {
+        Ok(walk::ido_files(dir)?
+            .iter()
+            .map(|p| walk::relative(p, dir))
+            .collect())
+    };
+    let files_a = relative(a)?;
+    let files_b = relative(b)?;
+
+    let mut report = Report {
+        only_a: files_a.difference(&files_b).cloned().collect(),
+        only_b: files_b.difference(&files_a).cloned().collect(),
+        ..Default::default()
+    };
+    for name in files_a.intersection(&files_b) {
+        let fa = fingerprint(&a.join(name))?;
+        let fb = fingerprint(&b.join(name))?;
+        let list = if fa == fb {
+            &mut report.identical
+        } else if fa.payload == fb.payload {
+            &mut report.header_only
+        } else {
+            &mut report.changed
+        };
+        list.push(name.clone());
+    }
+    Ok(report)
+}
+
+impl Report {
+    pub fn to_text(&self, a: &Path, b: &Path) -> String {
+        let mut out = String::new();
+        writeln!(out, "Identical: {}", self.identical.len()).unwrap();
+        let sections = [
+            ("Changed", &self.changed),
+            ("Header only", &self.header_only),
+            (&format!("Only in {}", a.display()), &self.only_a),
+            (&format!("Only in {}", b.display()), &self.only_b),
+        ];
+        for (title, files) in sections {
+            writeln!(out, "{}: {}", title, files.len()).unwrap();
+            for file in files {
+                writeln!(out, "  {}", file).unwrap();
+            }
+        }
+        out
+    }
+}
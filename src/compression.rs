@@ -0,0 +1,501 @@
+use crate::memory;
+use crate::warnings;
+use clap::ValueEnum;
+use flate2::bufread::GzDecoder;
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+use std::io::{self, Read, Write};
+
+/// Stream wrapper around the deflate-compressed payload.
+/// Regional client builds differ in which one they expect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Zlib,
+    Deflate,
+    Gzip,
+    /// Zstandard frames, needs the 'zstd' feature
+    Zstd,
+    /// LZ4 frames, needs the 'lz4' feature
+    Lz4,
+}
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Zlib => "zlib",
+            Compression::Deflate => "deflate",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Compression::from_str(name.trim(), true).ok()
+    }
+}
+
+/// CMF/FLG check from RFC 1950: deflate method, window <= 32K, FCHECK valid.
+pub fn is_zlib_header(data: &[u8]) -> bool {
+    data.len() >= 2
+        && data[0] & 0x0F == 8
+        && data[0] >> 4 <= 7
+        && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0
+}
+
+pub fn is_gzip_header(data: &[u8]) -> bool {
+    data.starts_with(&[0x1F, 0x8B])
+}
+
+pub fn is_zstd_header(data: &[u8]) -> bool {
+    data.starts_with(&[0x28, 0xB5, 0x2F, 0xFD])
+}
+
+pub fn is_lz4_header(data: &[u8]) -> bool {
+    data.starts_with(&[0x04, 0x22, 0x4D, 0x18])
+}
+
+/// Whether `data` starts with the header or magic of any wrapper. Raw
+/// deflate has none, so it never counts.
+pub fn starts_any_stream(data: &[u8]) -> bool {
+    is_zlib_header(data) || is_gzip_header(data) || is_zstd_header(data) || is_lz4_header(data)
+}
+
+/// A decompressed payload together with how it was laid out on disk.
+#[derive(Debug, Clone)]
+pub struct Decompressed {
+    pub kind: Compression,
+    pub data: Vec<u8>,
+    /// Decompressed size of each back-to-back stream, in order.
+    pub segments: Vec<usize>,
+    /// Bytes after the last stream that did not start another one.
+    pub trailing: usize,
+}
+
+/// Decompress a payload, detecting which wrapper it uses.
+///
+/// Zstd and lz4 are only tried when their frame magic is present. Zlib is
+/// tried first when the stream carries a valid zlib header, then gzip,
+/// then raw deflate. Streams following the first one are decompressed as
+/// well, as long as they use the same wrapper.
+pub fn decompress(data: &[u8]) -> io::Result<Decompressed> {
+    let mut candidates = Vec::with_capacity(4);
+    if is_zstd_header(data) {
+        candidates.push(Compression::Zstd);
+    }
+    if is_lz4_header(data) {
+        candidates.push(Compression::Lz4);
+    }
+    if is_zlib_header(data) {
+        candidates.push(Compression::Zlib);
+    }
+    if is_gzip_header(data) {
+        candidates.push(Compression::Gzip);
+    }
+    for kind in [Compression::Zlib, Compression::Gzip, Compression::Deflate] {
+        if !candidates.contains(&kind) {
+            candidates.push(kind);
+        }
+    }
+
+    let mut first_error = None;
+    for kind in candidates {
+        match decompress_with(data, kind) {
+            Ok(out) => return Ok(out),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    let e = first_error.expect("at least one candidate was tried");
+    if matches!(
+        e.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::OutOfMemory
+    ) {
+        return Err(e);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Payload is not a zlib, gzip or deflate stream: {}", e),
+    ))
+}
+
+/// Decompress every back-to-back stream of the given wrapper.
+pub fn decompress_with(data: &[u8], kind: Compression) -> io::Result<Decompressed> {
+    let (mut out, mut rest) = decompress_stream(data, kind)?;
+    let mut segments = vec![out.len()];
+
+    while !rest.is_empty() && starts_stream(rest, kind) {
+        let (chunk, next) = match decompress_stream(rest, kind) {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::OutOfMemory => return Err(e),
+            Err(_) => break,
+        };
+        if next.len() == rest.len() {
+            break;
+        }
+        segments.push(chunk.len());
+        out.extend_from_slice(&chunk);
+        memory::check("Decompressing the payload", out.len() as u64)?;
+        rest = next;
+    }
+
+    Ok(Decompressed {
+        kind,
+        data: out,
+        segments,
+        trailing: rest.len(),
+    })
+}
+
+/// Where a damaged stream stopped decoding.
+#[derive(Debug, Clone)]
+pub struct Damage {
+    /// Offset into the compressed data.
+    pub offset: usize,
+    pub error: String,
+}
+
+/// Input fed to the decoder at a time, so output up to any damage is
+/// kept.
+const INFLATE_CHUNK: usize = 4096;
+
+/// Inflate one zlib or raw deflate stream, returning its output and how
+/// much input it used. A damaged or truncated stream returns what was
+/// decoded before the damage.
+fn inflate(data: &[u8], zlib: bool) -> Result<(Vec<u8>, usize), (Vec<u8>, Damage)> {
+    if !zlib {
+        return inflate_raw(data);
+    }
+    let damage = |offset: usize, error: String| Damage { offset, error };
+    if !is_zlib_header(data) {
+        return Err((Vec::new(), damage(0, "no zlib header".to_string())));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err((
+            Vec::new(),
+            damage(0, "stream needs a preset dictionary".to_string()),
+        ));
+    }
+    // The deflate data is inflated raw so the Adler-32 after it can be
+    // checked, and reported, here.
+    let (out, used) =
+        inflate_raw(&data[2..]).map_err(|(out, d)| (out, damage(d.offset + 2, d.error)))?;
+    let end = 2 + used;
+    let Some(trailer) = data.get(end..end + 4) else {
+        return Err((
+            out,
+            damage(data.len(), "stream ends before its Adler-32".to_string()),
+        ));
+    };
+    let stored = u32::from_be_bytes(trailer.try_into().expect("4 bytes"));
+    let computed = adler32(&out);
+    if stored != computed {
+        let error = format!(
+            "Adler-32 mismatch: stored {:08x}, computed {:08x}",
+            stored, computed
+        );
+        return Err((out, damage(end, error)));
+    }
+    Ok((out, end + 4))
+}
+
+fn inflate_raw(data: &[u8]) -> Result<(Vec<u8>, usize), (Vec<u8>, Damage)> {
+    let mut decoder = flate2::Decompress::new(false);
+    let mut out = Vec::new();
+    loop {
+        let consumed = decoder.total_in() as usize;
+        let produced = decoder.total_out();
+        let input = &data[consumed..data.len().min(consumed + INFLATE_CHUNK)];
+        if !memory::fits(out.len() as u64) {
+            let damage = Damage {
+                offset: consumed,
+                error: "over the memory budget".to_string(),
+            };
+            return Err((out, damage));
+        }
+        out.reserve(INFLATE_CHUNK * 4);
+        match decoder.decompress_vec(input, &mut out, flate2::FlushDecompress::None) {
+            Ok(flate2::Status::StreamEnd) => return Ok((out, decoder.total_in() as usize)),
+            Ok(_) if decoder.total_in() as usize == consumed && decoder.total_out() == produced => {
+                let damage = Damage {
+                    offset: consumed,
+                    error: "stream ends early".to_string(),
+                };
+                return Err((out, damage));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let damage = Damage {
+                    offset: decoder.total_in() as usize,
+                    error: e.to_string(),
+                };
+                return Err((out, damage));
+            }
+        }
+    }
+}
+
+/// Adler-32 of `data` (RFC 1950), as stored at the end of a zlib stream.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // The most bytes that can be summed before `b` could overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+/// CRC-32 of `data`, as stored at the end of a gzip stream.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// The integrity check a wrapper carries; decompression fails when it
+/// doesn't match.
+pub fn check_name(kind: Compression) -> Option<&'static str> {
+    match kind {
+        Compression::Zlib => Some("Adler-32"),
+        Compression::Gzip => Some("CRC-32"),
+        Compression::Zstd => Some("XXH64, if the frame has one,"),
+        Compression::Lz4 => Some("XXH32, if the frame has one,"),
+        Compression::Deflate => None,
+    }
+}
+
+/// Checksum of each stream's decompressed data, for wrappers that always
+/// carry one; empty for the others.
+pub fn checksums(kind: Compression, data: &[u8], segments: &[usize]) -> Vec<u32> {
+    let checksum = match kind {
+        Compression::Zlib => adler32,
+        Compression::Gzip => crc32,
+        _ => return Vec::new(),
+    };
+    if segments.iter().sum::<usize>() != data.len() {
+        return vec![checksum(data)];
+    }
+    let mut offset = 0;
+    segments
+        .iter()
+        .map(|&size| {
+            offset += size;
+            checksum(&data[offset - size..offset])
+        })
+        .collect()
+}
+
+/// One line on how a decompressed payload's integrity was checked.
+pub fn check_summary(kind: Compression, data: &[u8], segments: &[usize]) -> String {
+    let Some(name) = check_name(kind) else {
+        return "none; raw deflate carries no checksum, so corruption can go unnoticed".to_string();
+    };
+    match checksums(kind, data, segments).as_slice() {
+        [] => format!("{} verified", name),
+        [single] => format!("{} verified ({:08x})", name, single),
+        all => format!("{} verified for all {} streams", name, all.len()),
+    }
+}
+
+/// Decompress as much of a damaged or truncated zlib (or raw deflate)
+/// stream as possible. `None` when nothing decodes, or when the stream
+/// turns out to be intact.
+pub fn recover(data: &[u8]) -> Option<(Decompressed, Damage)> {
+    let kind = if is_zlib_header(data) {
+        Compression::Zlib
+    } else {
+        Compression::Deflate
+    };
+    match inflate(data, kind == Compression::Zlib) {
+        Err((out, damage)) if !out.is_empty() && memory::fits(out.len() as u64) => {
+            let decompressed = Decompressed {
+                kind,
+                segments: vec![out.len()],
+                data: out,
+                trailing: 0,
+            };
+            Some((decompressed, damage))
+        }
+        _ => None,
+    }
+}
+
+fn starts_stream(data: &[u8], kind: Compression) -> bool {
+    match kind {
+        Compression::Zlib => is_zlib_header(data),
+        Compression::Gzip => is_gzip_header(data),
+        Compression::Zstd => is_zstd_header(data),
+        Compression::Lz4 => is_lz4_header(data),
+        Compression::Deflate => true,
+    }
+}
+
+/// Decompress a single stream, returning its output and the unread input.
+fn decompress_stream(data: &[u8], kind: Compression) -> io::Result<(Vec<u8>, &[u8])> {
+    let mut out = Vec::new();
+    let rest = match kind {
+        // Inflated by hand: the read adapters take a truncated stream for
+        // a complete one.
+        Compression::Zlib | Compression::Deflate => {
+            let (inflated, used) =
+                inflate(data, kind == Compression::Zlib).map_err(|(out, damage)| {
+                    if !memory::fits(out.len() as u64) {
+                        return memory::exceeded("Decompressing the payload", out.len() as u64);
+                    }
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} at byte {}", damage.error, damage.offset),
+                    )
+                })?;
+            out = inflated;
+            &data[used..]
+        }
+        Compression::Gzip => {
+            let mut decoder = GzDecoder::new(data);
+            read_bounded(&mut decoder, &mut out)?;
+            decoder.into_inner()
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut decoder = zstd::stream::read::Decoder::with_buffer(data)?.single_frame();
+            read_bounded(&mut decoder, &mut out)?;
+            decoder.finish()
+        }
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => {
+            // The frame decoder reports end of input at each frame end marker.
+            let mut decoder = lz4_flex::frame::FrameDecoder::new(data);
+            read_bounded(&mut decoder, &mut out)?;
+            decoder.into_inner()
+        }
+        #[cfg(not(all(feature = "zstd", feature = "lz4")))]
+        #[allow(unreachable_patterns)]
+        kind @ (Compression::Zstd | Compression::Lz4) => return Err(unsupported(kind)),
+    };
+    Ok((out, rest))
+}
+
+/// Read a decoder to its end, failing once the output goes over the
+/// memory budget.
+fn read_bounded<R: Read>(decoder: &mut R, out: &mut Vec<u8>) -> io::Result<()> {
+    match memory::limit() {
+        Some(limit) => {
+            decoder.take(limit + 1).read_to_end(out)?;
+            memory::check("Decompressing the payload", out.len() as u64)
+        }
+        None => decoder.read_to_end(out).map(|_| ()),
+    }
+}
+
+/// Compress `data` as consecutive streams of the given decompressed sizes.
+///
+/// When the sizes no longer add up (the payload was edited), the data is
+/// re-chunked using the largest recorded segment size, which is what the
+/// client's chunked reader expects for every stream but the last.
+pub fn compress_segments(
+    data: &[u8],
+    kind: Compression,
+    segments: &[usize],
+) -> io::Result<Vec<u8>> {
+    let sizes: Vec<usize> = if segments.iter().sum::<usize>() == data.len() {
+        segments.to_vec()
+    } else {
+        let chunk = segments.iter().copied().max().unwrap_or(0).max(1);
+        warnings::warn(format!(
+            "Payload size changed, re-chunking into {}-byte segments.",
+            chunk
+        ));
+        data.chunks(chunk).map(<[u8]>::len).collect()
+    };
+
+    let mut out = Vec::new();
+    let mut offset = 0;
+    for size in sizes {
+        out.extend_from_slice(&compress(&data[offset..offset + size], kind)?);
+        offset += size;
+    }
+    Ok(out)
+}
+
+pub fn compress(data: &[u8], kind: Compression) -> io::Result<Vec<u8>> {
+    compress_level(data, kind, flate2::Compression::default())
+}
+
+/// Guess the deflate level the first stream of `payload` was written with
+/// by recompressing it at every level. Returns the level and whether the
+/// output matched exactly; otherwise the level with the closest size.
+/// Small inputs often come out the same at several levels, in which case
+/// the default level 6 is preferred. `None` for non-deflate wrappers or
+/// undecodable payloads.
+pub fn estimate_level(payload: &[u8], kind: Compression) -> Option<(u32, bool)> {
+    if matches!(kind, Compression::Zstd | Compression::Lz4) {
+        return None;
+    }
+    let (data, rest) = decompress_stream(payload, kind).ok()?;
+    let stream = &payload[..payload.len() - rest.len()];
+    // Gzip headers carry a timestamp and OS byte that say nothing about the level.
+    let skip = if kind == Compression::Gzip { 10 } else { 0 };
+
+    let mut closest = None;
+    for level in [6, 0, 1, 2, 3, 4, 5, 7, 8, 9] {
+        let out = compress_level(&data, kind, flate2::Compression::new(level)).ok()?;
+        if out.get(skip..) == stream.get(skip..) {
+            return Some((level, true));
+        }
+        let distance = out.len().abs_diff(stream.len());
+        if closest.is_none_or(|(_, d)| distance < d) {
+            closest = Some((level, distance));
+        }
+    }
+    closest.map(|(level, _)| (level, false))
+}
+
+fn compress_level(
+    data: &[u8],
+    kind: Compression,
+    level: flate2::Compression,
+) -> io::Result<Vec<u8>> {
+    match kind {
+        Compression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            encoder.write_all(data)?;
+            encoder.finish().map_err(io::Error::other)
+        }
+        #[cfg(not(all(feature = "zstd", feature = "lz4")))]
+        #[allow(unreachable_patterns)]
+        kind @ (Compression::Zstd | Compression::Lz4) => Err(unsupported(kind)),
+    }
+}
+
+#[cfg(not(all(feature = "zstd", feature = "lz4")))]
+fn unsupported(kind: Compression) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("idoc was built without the '{}' feature", kind.name()),
+    )
+}
@@ -0,0 +1,280 @@
+//! Optional settings from an `idoc.toml`, looked up in the working
+//! directory first and then in the user's config directory.
+
+use crate::signature::Signature;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const FILE_NAME: &str = "idoc.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Layout file describing the header, used instead of the built-in
+    /// `header` layout. Relative to the config file.
+    pub header_layout: Option<PathBuf>,
+    /// Known-good headers, checked before any built into idoc (so far
+    /// there are none).
+    pub headers: Vec<KnownHeader>,
+    /// Payload signatures, checked before the built-in ones.
+    pub signatures: Vec<Signature>,
+    /// Path patterns of game installations, which outputs are not
+    /// written into without `--allow-game-dir`.
+    pub game_dirs: Vec<String>,
+    /// Names (patterns, any case) of files or folders only a client
+    /// installation holds, such as its executable; a directory holding
+    /// one counts as a game installation.
+    pub game_markers: Vec<String>,
+    /// Back up files overwritten inside a game installation, as if
+    /// `--backup` was given.
+    pub backup_in_game_dirs: bool,
+    /// rhai scripts run on every decompile and compile, before any
+    /// given with `--script`. Relative to the config file.
+    pub scripts: Vec<PathBuf>,
+    /// Directories searched for plugins, before `plugins` in the user's
+    /// config directory. Relative to the config file.
+    pub plugin_dirs: Vec<PathBuf>,
+    /// Commands run before and after each compile and decompile.
+    pub hooks: Hooks,
+    /// Extension of decompiled outputs by detected type name (`shopdb`,
+    /// `dds`, a signature or plugin name), instead of its natural one.
+    pub extensions: HashMap<String, String>,
+    /// Types forced on files by name instead of detecting them; the
+    /// first rule matching a file applies.
+    pub handlers: Vec<HandlerRule>,
+    /// Values of command-line options, by option name (`output_dir`,
+    /// `format`), used when neither the command line nor an `IDOC_`
+    /// environment variable gives one.
+    pub defaults: toml::Table,
+}
+
+/// A type, and for databases a layout, for every file matching some
+/// patterns, e.g. ShopList files of a client that changed the layout.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HandlerRule {
+    /// File name patterns (`*`, `?`, `[...]`) the rule is for.
+    pub files: Vec<String>,
+    /// What the files are decompiled as: a type name (`shopdb`, `xml`,
+    /// `dds`, `gamebryo`, `unknown`, ...), a signature's or a plugin's.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Layout of a `shopdb`, by name or path; also used to compile
+    /// databases to matching outputs when `--layout` isn't given.
+    pub layout: Option<String>,
+}
+
+impl HandlerRule {
+    pub fn applies_to(&self, path: &Path) -> bool {
+        matches_name(&self.files, path)
+    }
+}
+
+/// Shell commands for each stage of a conversion, with `{input}` and
+/// `{output}` replaced by the paths, and for each batch of them. See
+/// [`crate::hooks`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub before_compile: Vec<String>,
+    pub after_compile: Vec<String>,
+    pub before_decompile: Vec<String>,
+    pub after_decompile: Vec<String>,
+    /// Run once per `-c`/`-d` run, before its first file.
+    pub before_batch: Vec<String>,
+    /// Run once per `-c`/`-d` run, after its last file, if none failed.
+    pub after_batch: Vec<String>,
+}
+
+/// A header known to work for some set of files, e.g. one client
+/// version's ShopList.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KnownHeader {
+    pub name: String,
+    /// File name patterns (`*`, `?`, `[...]`) the header is meant for.
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(deserialize_with = "from_hex")]
+    pub header: Vec<u8>,
+}
+
+impl KnownHeader {
+    /// Whether `path`'s file name matches one of the patterns,
+    /// ignoring case.
+    pub fn applies_to(&self, path: &Path) -> bool {
+        matches_name(&self.files, path)
+    }
+}
+
+/// Whether `path`'s file name matches one of `patterns`, ignoring case.
+fn matches_name(patterns: &[String], path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    patterns
+        .iter()
+        .any(|pattern| glob::Pattern::new(pattern).is_ok_and(|p| p.matches_with(name, options)))
+}
+
+/// Hex bytes, with any whitespace between them ignored.
+pub fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let digits: String = text.split_whitespace().collect();
+    hex::decode(digits).map_err(serde::de::Error::custom)
+}
+
+impl Config {
+    pub fn parse(content: &str) -> io::Result<Config> {
+        toml::from_str(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let mut config = Config::parse(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Invalid config file {}: {}", path.display(), e),
+            )
+        })?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        config.header_layout = config.header_layout.map(|layout| dir.join(layout));
+        config.scripts = config
+            .scripts
+            .iter()
+            .map(|script| dir.join(script))
+            .collect();
+        for extension in config.extensions.values_mut() {
+            *extension = extension.trim_start_matches('.').to_string();
+        }
+        config.plugin_dirs = config
+            .plugin_dirs
+            .iter()
+            .map(|plugins| dir.join(plugins))
+            .collect();
+        for rule in &mut config.handlers {
+            // Layout files are relative to the config, like other paths.
+            if let Some(layout) = &rule.layout
+                && layout.ends_with(".toml")
+            {
+                rule.layout = Some(dir.join(layout).to_string_lossy().into_owned());
+            }
+            if let Some(bad) = rule.files.iter().find(|p| glob::Pattern::new(p).is_err()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid config file {}: bad file pattern '{}' for handler '{}'",
+                        path.display(),
+                        bad,
+                        rule.kind
+                    ),
+                ));
+            }
+        }
+        for known in &config.headers {
+            if let Some(bad) = known.files.iter().find(|p| glob::Pattern::new(p).is_err()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid config file {}: bad file pattern '{}' for header '{}'",
+                        path.display(),
+                        bad,
+                        known.name
+                    ),
+                ));
+            }
+        }
+        if let Some(bad) = config
+            .game_dirs
+            .iter()
+            .find(|p| glob::Pattern::new(p).is_err())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid config file {}: bad game directory pattern '{}'",
+                    path.display(),
+                    bad
+                ),
+            ));
+        }
+        if let Some(bad) = config
+            .game_markers
+            .iter()
+            .find(|p| glob::Pattern::new(p).is_err())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid config file {}: bad game marker pattern '{}'",
+                    path.display(),
+                    bad
+                ),
+            ));
+        }
+        if let Some(signature) = config.signatures.iter().find(|s| !s.is_valid()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid config file {}: signature '{}' needs magic or footer bytes",
+                    path.display(),
+                    signature.name
+                ),
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// idoc's directory in the user's config directory, if there is one.
+pub fn user_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("idoc"))
+}
+
+/// Where a config file is looked for, in order.
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(FILE_NAME)];
+    if let Some(dir) = user_dir() {
+        paths.push(dir.join(FILE_NAME));
+    }
+    paths
+}
+
+/// Load the first config file found. Call once at startup; without it
+/// [`get`] returns the defaults.
+pub fn init() -> io::Result<()> {
+    let config = match search_paths().into_iter().find(|p| p.is_file()) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// The first `[[handlers]]` rule for `path`'s file name.
+pub fn handler_for(path: &Path) -> Option<&'static HandlerRule> {
+    get().handlers.iter().find(|rule| rule.applies_to(path))
+}
+
+/// The configured output extension for the type called `name`.
+pub fn extension_for(name: &str) -> Option<&'static str> {
+    get().extensions.get(name).map(String::as_str)
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
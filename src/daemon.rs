@@ -0,0 +1,179 @@
+//! `idoc daemon`: a long-running converter on a local socket (a Unix
+//! socket, or a named pipe on Windows), so editor plugins can compile on
+//! save without paying for process startup, config and layout parsing on
+//! every file.
+//!
+//! The protocol is one JSON object per line each way. A request names a
+//! `command` and may carry an `id`, which is echoed back:
+//!
+//! ```text
+//! {"id": 1, "command": "compile", "input": "/mod/Item.xml", "output": "/mod/Item.ido"}
+//! {"id": 1, "ok": true, "warnings": []}
+//! {"id": 2, "command": "decompile", "input": "/mod/Bad.ido", "output": "/mod/Bad.xml"}
+//! {"id": 2, "ok": false, "error": "...", "warnings": []}
+//! ```
+//!
+//! Relative paths resolve against the daemon's working directory. Any
+//! number of clients may stay connected; their requests run one at a
+//! time, so each response carries only its own warnings.
+
+use crate::warnings;
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{GenericFilePath, ListenerOptions, Name};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// What a client asks for.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Decompile {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    Compile {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    /// Answered without doing anything, to see whether the daemon is up.
+    Ping,
+    /// Answered, then the daemon exits.
+    Shutdown,
+}
+
+#[derive(Serialize)]
+struct Response {
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    id: serde_json::Value,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    warnings: Vec<String>,
+}
+
+/// The socket used when none is given: in the runtime directory on
+/// Unix, a per-user temporary one otherwise.
+pub fn default_socket() -> PathBuf {
+    if cfg!(windows) {
+        return PathBuf::from(r"\\.\pipe\idoc");
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("idoc.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("idoc-{}.sock", user))
+        }
+    }
+}
+
+fn name(socket: &Path) -> io::Result<Name<'_>> {
+    socket.as_os_str().to_fs_name::<GenericFilePath>()
+}
+
+/// Listen on `socket` and run each [`Request::Compile`] and
+/// [`Request::Decompile`] through `convert` until a client asks for
+/// [`Request::Shutdown`].
+pub fn run<F>(socket: &Path, convert: F) -> io::Result<()>
+where
+    F: Fn(&Request) -> io::Result<()> + Sync,
+{
+    // A socket left by a daemon that was killed is taken over; one that
+    // still answers is not.
+    if LocalSocketStream::connect(name(socket)?).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("A daemon is already listening on {}", socket.display()),
+        ));
+    }
+    let listener = ListenerOptions::new()
+        .name(name(socket)?)
+        .try_overwrite(true)
+        .create_sync()
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Cannot listen on {}: {}", socket.display(), e),
+            )
+        })?;
+    eprintln!("Listening on {}", socket.display());
+
+    let running = Mutex::new(());
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (convert, running) = (&convert, &running);
+                    scope.spawn(move || {
+                        if let Err(e) = serve(stream, socket, convert, running) {
+                            eprintln!("Warning: client connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Warning: could not accept a client: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer one client's requests until it disconnects.
+fn serve<F>(
+    stream: LocalSocketStream,
+    socket: &Path,
+    convert: &F,
+    running: &Mutex<()>,
+) -> io::Result<()>
+where
+    F: Fn(&Request) -> io::Result<()>,
+{
+    let (receive, mut send) = stream.split();
+    for line in BufReader::new(receive).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = serde_json::from_str::<serde_json::Value>(&line);
+        let id = match &message {
+            Ok(message) => message.get("id").cloned().unwrap_or_default(),
+            Err(_) => serde_json::Value::Null,
+        };
+        let request = message
+            .and_then(serde_json::from_value::<Request>)
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid request: {}", e),
+                )
+            });
+        let shutdown = matches!(request, Ok(Request::Shutdown));
+        // Held until the response is sent, so no other request's warnings
+        // mix in and a shutdown waits for nothing half done.
+        let _running = running.lock().unwrap_or_else(|e| e.into_inner());
+        warnings::take();
+        let result = request.and_then(|request| match request {
+            Request::Ping | Request::Shutdown => Ok(()),
+            request => convert(&request),
+        });
+        let response = Response {
+            id,
+            ok: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+            warnings: warnings::take(),
+        };
+        let mut line = serde_json::to_string(&response)?;
+        line.push('\n');
+        send.write_all(line.as_bytes())?;
+        send.flush()?;
+        if shutdown {
+            eprintln!("Shutting down");
+            if cfg!(unix) {
+                let _ = std::fs::remove_file(socket);
+            }
+            std::process::exit(0);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! Partial updates of a binary database from a CSV "patch file".
+//!
+//! Each row names one record, either by a `record_index` column or by the
+//! layout's key fields, and carries only the columns to change. Empty
+//! cells leave the field as it is. Bytes outside the layout are kept.
+
+use crate::export::{self, CsvDialect};
+use crate::layout::{Field, Layout, Value};
+use std::io;
+
+const INDEX_COLUMN: &str = "record_index";
+
+/// Apply patch rows from `csv` to the records in `data`, returning the
+/// number of records changed.
+pub fn apply(
+    data: &mut [u8],
+    layout: &Layout,
+    csv: &[u8],
+    dialect: &CsvDialect,
+    key: &[String],
+) -> io::Result<usize> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut rdr = export::csv::reader_for(csv, dialect);
+    let headers: Vec<String> = rdr
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_string())
+        .collect();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+
+    let index_column = column(INDEX_COLUMN);
+    let key_columns = if index_column.is_some() {
+        Vec::new()
+    } else {
+        if key.is_empty() {
+            return Err(invalid(format!(
+                "Patch needs a '{}' column, or --key since layout '{}' defines no key",
+                INDEX_COLUMN, layout.name
+            )));
+        }
+        key.iter()
+            .map(|name| {
+                let field = layout
+                    .field(name)
+                    .ok_or_else(|| invalid(format!("Unknown key field '{}'", name)))?;
+                let col = column(name)
+                    .ok_or_else(|| invalid(format!("Patch is missing key column '{}'", name)))?;
+                Ok((field, col))
+            })
+            .collect::<io::Result<Vec<(&Field, usize)>>>()?
+    };
+
+    let updates: Vec<(&Field, usize)> = layout
+        .fields
+        .iter()
+        .filter(|f| !key.contains(&f.name))
+        .filter_map(|f| column(&f.name).map(|col| (f, col)))
+        .collect();
+    if let Some(unknown) = headers
+        .iter()
+        .find(|h| *h != INDEX_COLUMN && layout.field(h).is_none())
+    {
+        return Err(invalid(format!(
+            "Column '{}' is not a field of layout '{}'",
+            unknown, layout.name
+        )));
+    }
+
+    let count = data.len() / layout.record_size;
+    let mut changed = 0;
+    for (row, result) in rdr.records().enumerate() {
+        let cells = result?;
+        // Row numbers count the header as line 1.
+        let line = row + 2;
+        let cell = |col: usize| cells.get(col).unwrap_or("").trim();
+        let parse = |field: &Field, col: usize| {
+            field
+                .parse(cell(col))
+                .map_err(|e| invalid(format!("row {}, column '{}': {}", line, field.name, e)))
+        };
+
+        let index = match index_column {
+            Some(col) => {
+                let index: usize = cell(col).parse().map_err(|_| {
+                    invalid(format!(
+                        "row {}: '{}' is not a record index",
+                        line,
+                        cell(col)
+                    ))
+                })?;
+                if index >= count {
+                    return Err(invalid(format!(
+                        "row {}: record {} is past the last record ({})",
+                        line,
+                        index,
+                        count.saturating_sub(1)
+                    )));
+                }
+                index
+            }
+            None => {
+                let wanted = key_columns
+                    .iter()
+                    .map(|&(field, col)| parse(field, col))
+                    .collect::<io::Result<Vec<Value>>>()?;
+                find_record(data, layout, &key_columns, &wanted)
+                    .map_err(|e| invalid(format!("row {}: {}", line, e)))?
+            }
+        };
+
+        let record = &mut data[index * layout.record_size..(index + 1) * layout.record_size];
+        let before = record.to_vec();
+        for &(field, col) in &updates {
+            if cell(col).is_empty() {
+                continue;
+            }
+            let value = parse(field, col)?;
+            field
+                .write(&value, record)
+                .map_err(|e| invalid(format!("row {}, column '{}': {}", line, field.name, e)))?;
+        }
+        if record != before.as_slice() {
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Index of the only record whose key fields equal `wanted`.
+fn find_record(
+    data: &[u8],
+    layout: &Layout,
+    key: &[(&Field, usize)],
+    wanted: &[Value],
+) -> Result<usize, String> {
+    let mut matches = data
+        .chunks_exact(layout.record_size)
+        .enumerate()
+        .filter(|(_, record)| {
+            key.iter()
+                .zip(wanted)
+                .all(|(&(field, _), value)| field.read(record) == *value)
+        })
+        .map(|(i, _)| i);
+
+    let describe = || {
+        key.iter()
+            .zip(wanted)
+            .map(|((field, _), value)| format!("{}={}", field.name, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match (matches.next(), matches.next()) {
+        (Some(index), None) => Ok(index),
+        (None, _) => Err(format!("no record with {}", describe())),
+        (Some(_), Some(_)) => Err(format!(
+            "more than one record with {}; use {} instead",
+            describe(),
+            INDEX_COLUMN
+        )),
+    }
+}
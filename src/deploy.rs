@@ -0,0 +1,170 @@
+//! `idoc deploy`: copy compiled .ido files into a game installation,
+//! keeping what they replace so the installation can be put back.
+//!
+//! Everything deploy keeps is in `.idoc-deploy` in the target: the
+//! originals under `backup/`, `deploy.json` listing the files it replaced
+//! and the ones it added, and `restore.sh` and `restore.bat`, which put
+//! the originals back, remove the added files and then `.idoc-deploy`.
+//! Deploying again adds to the same record, so the backups stay the
+//! files from before the first deploy.
+
+use crate::guard::write_atomic;
+use crate::walk;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory in the target holding backups, record and restore scripts.
+pub const DIR: &str = ".idoc-deploy";
+
+/// What every deploy into a target has changed there, as relative paths
+/// with forward slashes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Record {
+    /// Files that existed before, backed up under `backup/`.
+    replaced: Vec<String>,
+    /// Files that did not exist before.
+    added: Vec<String>,
+}
+
+/// What a deploy did.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub files: usize,
+    /// Files copied over an original, backed up first.
+    pub replaced: usize,
+    /// Files that weren't in the target before.
+    pub added: usize,
+    /// Files already identical in the target, not rewritten.
+    pub unchanged: usize,
+}
+
+/// Copy every .ido below `from` to the same relative path in `target`.
+pub fn deploy(from: &Path, target: &Path) -> io::Result<Summary> {
+    if !target.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Target {} is not a directory", target.display()),
+        ));
+    }
+    if fs::canonicalize(from)? == fs::canonicalize(target)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--from and --target are the same directory",
+        ));
+    }
+    let dir = target.join(DIR);
+    let record_path = dir.join("deploy.json");
+    let mut record = if record_path.is_file() {
+        serde_json::from_slice(&fs::read(&record_path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Corrupt deploy record {}: {}", record_path.display(), e),
+            )
+        })?
+    } else {
+        Record::default()
+    };
+
+    // Back up and record everything before touching the target, so an
+    // interrupted deploy can still be restored.
+    let files = walk::ido_files(from)?;
+    let mut summary = Summary {
+        files: files.len(),
+        ..Default::default()
+    };
+    let mut copies = Vec::new();
+    for source in &files {
+        let relative = walk::relative(source, from);
+        let destination = target.join(&relative);
+        let content = fs::read(source)?;
+        let known = record.replaced.contains(&relative) || record.added.contains(&relative);
+        match fs::read(&destination) {
+            Ok(current) if current == content => {
+                summary.unchanged += 1;
+                continue;
+            }
+            Ok(current) => {
+                if !known {
+                    write_atomic(&dir.join("backup").join(&relative), &current)?;
+                    record.replaced.push(relative.clone());
+                }
+                summary.replaced += 1;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if !known {
+                    record.added.push(relative.clone());
+                }
+                summary.added += 1;
+            }
+            Err(e) => return Err(e),
+        }
+        copies.push((relative, destination, content));
+    }
+    write_atomic(&record_path, &serde_json::to_vec_pretty(&record)?)?;
+    write_atomic(&dir.join("restore.sh"), restore_sh(&record).as_bytes())?;
+    write_atomic(&dir.join("restore.bat"), restore_bat(&record).as_bytes())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir.join("restore.sh"), fs::Permissions::from_mode(0o755))?;
+    }
+
+    for (relative, destination, content) in copies {
+        write_atomic(&destination, &content)?;
+        println!("Deployed {}", relative);
+    }
+    Ok(summary)
+}
+
+/// The restore script for this platform, in `target`.
+pub fn restore_script(target: &Path) -> PathBuf {
+    let name = if cfg!(windows) {
+        "restore.bat"
+    } else {
+        "restore.sh"
+    };
+    target.join(DIR).join(name)
+}
+
+fn restore_sh(record: &Record) -> String {
+    let quote = |path: &str| format!("'{}'", path.replace('\'', r"'\''"));
+    let mut script = String::from(
+        "#!/bin/sh\n# Written by idoc deploy: puts back the files it replaced and removes the\n# ones it added.\nset -e\ncd \"$(dirname \"$0\")/..\"\n",
+    );
+    for path in &record.replaced {
+        let backup = format!("{}/backup/{}", DIR, path);
+        script.push_str(&format!("cp {} {}\n", quote(&backup), quote(path)));
+    }
+    for path in &record.added {
+        script.push_str(&format!("rm -f {}\n", quote(path)));
+    }
+    script.push_str(&format!("rm -rf {}\necho Restored\n", DIR));
+    script
+}
+
+fn restore_bat(record: &Record) -> String {
+    // Windows paths cannot contain quotes; percent signs would expand.
+    let quote = |path: &str| format!("\"{}\"", path.replace('/', "\\").replace('%', "%%"));
+    let mut script = String::from(
+        "@echo off\r\nrem Written by idoc deploy: puts back the files it replaced and removes the\r\nrem ones it added.\r\nchcp 65001 >nul\r\ncd /d \"%~dp0..\"\r\n",
+    );
+    for path in &record.replaced {
+        let backup = format!("{}/backup/{}", DIR, path);
+        script.push_str(&format!(
+            "copy /y {} {} >nul || exit /b 1\r\n",
+            quote(&backup),
+            quote(path)
+        ));
+    }
+    for path in &record.added {
+        script.push_str(&format!("if exist {0} del /f /q {0}\r\n", quote(path)));
+    }
+    // On one line, which cmd has read before the script is removed.
+    script.push_str(&format!(
+        "echo Restored & rmdir /s /q {} & exit /b 0\r\n",
+        DIR
+    ));
+    script
+}
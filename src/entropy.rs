@@ -0,0 +1,300 @@
+//! Block entropy and region classification, for files idoc does not
+//! recognise.
+
+use crate::compression::{self, Decompressed};
+use crate::ido::{self, DecodeOptions, IdoFile};
+use crate::layout::infer;
+use encoding_rs::{EUC_KR, UTF_16BE, UTF_16LE};
+use std::fmt::Write;
+
+pub const DEFAULT_BLOCK_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Nearly a single byte value, e.g. zero padding.
+    Constant,
+    Text,
+    Binary,
+    /// Close to random: compressed or encrypted.
+    Random,
+}
+
+impl Class {
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Constant => "constant/padding",
+            Class::Text => "text",
+            Class::Binary => "binary/structured",
+            Class::Random => "compressed or encrypted",
+        }
+    }
+}
+
+/// Adjacent blocks of the same class.
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub class: Class,
+    /// Mean entropy of the blocks, in bits per byte.
+    pub entropy: f64,
+}
+
+/// Shannon entropy in bits per byte.
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[usize::from(b)] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+pub fn classify(block: &[u8]) -> (Class, f64) {
+    let entropy = entropy(block);
+    // Short blocks cannot reach 8 bits, so the threshold scales with the
+    // most a block of this length could show.
+    let most = (block.len().min(256) as f64).log2();
+    let controls = block
+        .iter()
+        .filter(|&&b| (b < 0x20 && !b"\t\r\n".contains(&b)) || b == 0x7F)
+        .count();
+    let class = if entropy < 1.0 {
+        Class::Constant
+    } else if controls * 100 <= block.len() && entropy < 6.5 {
+        Class::Text
+    } else if entropy >= 0.85 * most {
+        Class::Random
+    } else {
+        Class::Binary
+    };
+    (class, entropy)
+}
+
+/// Block boundaries, with a short tail folded into the last full block
+/// since a few bytes say little on their own.
+fn blocks(len: usize, block_size: usize) -> Vec<(usize, usize)> {
+    let mut blocks: Vec<(usize, usize)> = (0..len)
+        .step_by(block_size)
+        .map(|start| (start, len.min(start + block_size)))
+        .collect();
+    if blocks.len() > 1 && len % block_size < block_size / 2 {
+        let (_, end) = blocks.pop().expect("more than one block");
+        blocks.last_mut().expect("more than one block").1 = end;
+    }
+    blocks
+}
+
+pub fn regions(data: &[u8], block_size: usize) -> Vec<Region> {
+    let mut regions: Vec<(Region, usize)> = Vec::new();
+    for (start, end) in blocks(data.len(), block_size) {
+        let block = &data[start..end];
+        let (class, entropy) = classify(block);
+        match regions.last_mut() {
+            Some((region, blocks)) if region.class == class => {
+                region.end = end;
+                region.entropy += entropy;
+                *blocks += 1;
+            }
+            _ => regions.push((
+                Region {
+                    start,
+                    end,
+                    class,
+                    entropy,
+                },
+                1,
+            )),
+        }
+    }
+    regions
+        .into_iter()
+        .map(|(region, blocks)| Region {
+            entropy: region.entropy / blocks as f64,
+            ..region
+        })
+        .collect()
+}
+
+/// Offsets where a compressed stream (or run of back-to-back streams)
+/// starts and decodes, with what it decoded to.
+pub fn find_streams(data: &[u8]) -> Vec<(usize, Decompressed)> {
+    let mut streams = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let rest = &data[offset..];
+        let candidate = compression::is_zlib_header(rest)
+            || compression::is_gzip_header(rest)
+            || compression::is_zstd_header(rest)
+            || compression::is_lz4_header(rest);
+        if candidate
+            && let Ok(decompressed) = compression::decompress(rest)
+            && !decompressed.data.is_empty()
+        {
+            let next = data.len() - decompressed.trailing;
+            streams.push((offset, decompressed));
+            offset = next;
+            continue;
+        }
+        offset += 1;
+    }
+    streams
+}
+
+/// The report printed by `idoc entropy`.
+pub fn report(bytes: &[u8], options: &DecodeOptions, block_size: usize) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Raw file: {} bytes, {}-byte blocks",
+        bytes.len(),
+        block_size
+    )
+    .unwrap();
+    write_regions(&mut out, &regions(bytes, block_size));
+
+    let streams = find_streams(bytes);
+    if !streams.is_empty() {
+        writeln!(out, "Compressed streams:").unwrap();
+    }
+    for (offset, stream) in &streams {
+        let count = if stream.segments.len() > 1 {
+            format!(" ({} streams)", stream.segments.len())
+        } else {
+            String::new()
+        };
+        writeln!(
+            out,
+            "  0x{:08X}-0x{:08X}  {}{}, {} bytes decompressed",
+            offset,
+            bytes.len() - stream.trailing,
+            stream.kind.name(),
+            count,
+            stream.data.len()
+        )
+        .unwrap();
+    }
+
+    match IdoFile::decode(bytes.to_vec(), options) {
+        Ok(file) if file.meta.is_some() => {
+            writeln!(
+                out,
+                "Decompressed payload: {} bytes ({})",
+                file.data.len(),
+                file.kind.description()
+            )
+            .unwrap();
+            write_regions(&mut out, &regions(&file.data, block_size));
+        }
+        Ok(file) => writeln!(
+            out,
+            "Stored uncompressed ({}); no payload to decompress",
+            file.kind.description()
+        )
+        .unwrap(),
+        Err(e) if bytes.len() >= ido::HEADER_SIZE => {
+            writeln!(out, "Payload after the header does not decode: {}", e).unwrap()
+        }
+        Err(_) => {}
+    }
+    out
+}
+
+fn write_regions(out: &mut String, regions: &[Region]) {
+    for region in regions {
+        writeln!(
+            out,
+            "  0x{:08X}-0x{:08X}  {:>5.2} bits/byte  {}",
+            region.start,
+            region.end,
+            region.entropy,
+            region.class.name()
+        )
+        .unwrap();
+    }
+}
+
+/// One-line observations about a payload idoc could not classify.
+pub fn hints(data: &[u8]) -> Vec<String> {
+    if data.is_empty() {
+        return vec!["Payload is empty".to_string()];
+    }
+    let mut hints = Vec::new();
+    let (class, entropy) = classify(data);
+    hints.push(format!(
+        "Entropy {:.2} bits/byte: {}",
+        entropy,
+        class.name()
+    ));
+    let printable = data
+        .iter()
+        .filter(|&&b| b.is_ascii_graphic() || b" \t\r\n".contains(&b))
+        .count();
+    hints.push(format!(
+        "{:.0}% printable ASCII",
+        printable as f64 * 100.0 / data.len() as f64
+    ));
+
+    // NULs and other control bytes decode in every encoding, so only data
+    // that is mostly free of them counts as text.
+    let controls = data
+        .iter()
+        .filter(|&&b| b < 0x20 && !b"\t\r\n".contains(&b))
+        .count();
+    let mut encodings = Vec::new();
+    if controls * 10 <= data.len() {
+        if std::str::from_utf8(data).is_ok() {
+            encodings.push("UTF-8");
+        }
+        if !EUC_KR.decode_without_bom_handling(data).1 {
+            encodings.push("EUC-KR");
+        }
+    }
+    // Mostly-ASCII UTF-16 has a zero in one byte of each code unit and
+    // text in the other.
+    let zeros = |start: usize| {
+        data.iter()
+            .skip(start)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    if data.len().is_multiple_of(2) {
+        let half = data.len() / 2;
+        let lanes = |zero: usize, text: usize| zeros(zero) * 3 >= half && zeros(text) * 3 <= half;
+        if lanes(1, 0) && !UTF_16LE.decode_without_bom_handling(data).1 {
+            encodings.push("UTF-16LE");
+        }
+        if lanes(0, 1) && !UTF_16BE.decode_without_bom_handling(data).1 {
+            encodings.push("UTF-16BE");
+        }
+    }
+    hints.push(if encodings.is_empty() {
+        "No text encoding decodes it cleanly".to_string()
+    } else {
+        format!("Decodes cleanly as {}", encodings.join(", "))
+    });
+
+    if let Some(stride) = infer::detect_stride(data) {
+        hints.push(format!(
+            "Repeats every 0x{:X} bytes, like {} records; try idoc infer-layout",
+            stride,
+            data.len() / stride
+        ));
+    }
+    for (offset, stream) in find_streams(data) {
+        hints.push(format!(
+            "Holds a {} stream at 0x{:X} ({} bytes decompressed)",
+            stream.kind.name(),
+            offset,
+            stream.data.len()
+        ));
+    }
+    hints
+}
@@ -0,0 +1,359 @@
+use crate::layout::{Layout, Value};
+use crate::meta::Meta;
+use crate::xml::Document;
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::io::{self, Write};
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+pub mod sql;
+
+pub use csv::{CsvDialect, Quoting};
+pub use sql::SqlDialect;
+
+/// Output formats for parsed databases and structured XML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Csv,
+    Sql,
+    Json,
+    Yaml,
+    /// MessagePack, with records as field-name maps
+    Msgpack,
+    Cbor,
+    /// Arrow IPC / Feather v2, databases only
+    Arrow,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Sql => "sql",
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Msgpack => "msgpack",
+            Format::Cbor => "cbor",
+            Format::Arrow => "arrow",
+        }
+    }
+
+    /// Format implied by a file extension, if any.
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        match ext.to_ascii_lowercase().as_str() {
+            "csv" | "tsv" => Some(Format::Csv),
+            "sql" => Some(Format::Sql),
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "msgpack" | "mpk" => Some(Format::Msgpack),
+            "cbor" => Some(Format::Cbor),
+            "arrow" | "feather" => Some(Format::Arrow),
+            _ => None,
+        }
+    }
+
+    /// Whether records can be written as they are read, with
+    /// [`Streamed`]; the other formats need every record at once.
+    pub fn streams(self) -> bool {
+        matches!(self, Format::Csv | Format::Sql)
+    }
+}
+
+/// Format-specific settings, ignored by formats they don't apply to.
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub csv: CsvDialect,
+    pub sql_dialect: SqlDialect,
+}
+
+/// Serialize decoded records of `layout` in the requested format.
+pub fn write_records<W: Write>(
+    writer: W,
+    layout: &Layout,
+    records: &[Vec<Value>],
+    format: Format,
+    options: &ExportOptions,
+) -> io::Result<()> {
+    match format {
+        Format::Csv => csv::write(writer, layout, records, &options.csv),
+        Format::Sql => sql::write(writer, layout, records, options.sql_dialect),
+        Format::Json | Format::Yaml | Format::Msgpack | Format::Cbor => {
+            write_serde(writer, &Rows { layout, records }, format)
+        }
+        #[cfg(feature = "arrow")]
+        Format::Arrow => arrow::write(writer, layout, records),
+        #[cfg(not(feature = "arrow"))]
+        Format::Arrow => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "idoc was built without the 'arrow' feature",
+        )),
+    }
+}
+
+/// A record export written a record at a time, so a database is never
+/// held whole. For the formats [`Format::streams`] allows.
+pub struct Streamed<'a, W: Write> {
+    sink: Sink<W>,
+    layout: &'a Layout,
+    options: &'a ExportOptions,
+    written: u64,
+}
+
+enum Sink<W: Write> {
+    Csv(Box<csv::Stream<W>>),
+    /// Records wait until there are enough for an `INSERT`.
+    Sql {
+        writer: W,
+        pending: Vec<Vec<Value>>,
+    },
+}
+
+impl<'a, W: Write> Streamed<'a, W> {
+    /// Start the export, writing what comes before the records.
+    pub fn new(
+        mut writer: W,
+        layout: &'a Layout,
+        format: Format,
+        options: &'a ExportOptions,
+    ) -> io::Result<Streamed<'a, W>> {
+        let sink = match format {
+            Format::Csv => Sink::Csv(Box::new(csv::Stream::new(writer, layout, &options.csv)?)),
+            Format::Sql => {
+                sql::begin(&mut writer, layout, options.sql_dialect)?;
+                Sink::Sql {
+                    writer,
+                    pending: Vec::with_capacity(sql::ROWS_PER_INSERT),
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{} can't be written as it is read", format.name()),
+                ));
+            }
+        };
+        Ok(Streamed {
+            sink,
+            layout,
+            options,
+            written: 0,
+        })
+    }
+
+    /// Write the next record.
+    pub fn write(&mut self, record: Vec<Value>) -> io::Result<()> {
+        self.written += 1;
+        match &mut self.sink {
+            Sink::Csv(stream) => stream.write(&record),
+            Sink::Sql { pending, .. } => {
+                pending.push(record);
+                if pending.len() < sql::ROWS_PER_INSERT {
+                    return Ok(());
+                }
+                self.write_pending()
+            }
+        }
+    }
+
+    /// Pass what has been written on to the writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Csv(stream) => stream.flush(),
+            Sink::Sql { writer, .. } => writer.flush(),
+        }
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if let Sink::Sql { writer, pending } = &mut self.sink {
+            let first_index = self.written - pending.len() as u64;
+            sql::append(
+                writer,
+                self.layout,
+                pending,
+                first_index,
+                self.options.sql_dialect,
+            )?;
+            pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Write what comes after the records, returning how many there were.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.write_pending()?;
+        match self.sink {
+            Sink::Csv(mut stream) => stream.flush()?,
+            Sink::Sql { writer, .. } => sql::finish(writer)?,
+        }
+        Ok(self.written)
+    }
+}
+
+/// Serialize a decompiled XML document together with the metadata needed
+/// to compile it back.
+pub fn write_document<W: Write>(
+    writer: W,
+    document: &Document,
+    meta: &Meta,
+    format: Format,
+) -> io::Result<()> {
+    let structured = StructuredIdo {
+        ido: meta.to_map(),
+        document,
+    };
+    write_serde(writer, &structured, format)
+}
+
+/// Write any serializable value in one of the serde-backed formats.
+fn write_serde<W: Write, T: Serialize>(mut writer: W, value: &T, format: Format) -> io::Result<()> {
+    let result = match format {
+        Format::Json => serde_json::to_writer_pretty(&mut writer, value).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml_ng::to_writer(&mut writer, value).map_err(|e| e.to_string()),
+        Format::Msgpack => {
+            rmp_serde::encode::write_named(&mut writer, value).map_err(|e| e.to_string())
+        }
+        Format::Cbor => ciborium::into_writer(value, &mut writer).map_err(|e| e.to_string()),
+        Format::Csv | Format::Sql | Format::Arrow => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("--format {} only applies to databases", format.name()),
+            ));
+        }
+    };
+    result.map_err(io::Error::other)?;
+    writer.flush()
+}
+
+/// A structured export read back in: either database records or an XML
+/// document with its metadata.
+pub enum Structured {
+    Records(Vec<Vec<Value>>),
+    Document(Box<Meta>, Document),
+}
+
+/// Read a json, yaml, msgpack or cbor export. A top-level sequence is taken
+/// as records of `layout`, anything else as a document.
+pub fn read_structured(content: &[u8], format: Format, layout: &Layout) -> io::Result<Structured> {
+    let value: serde_json::Value = read_serde(content, format)?;
+    match value {
+        serde_json::Value::Array(rows) => Ok(Structured::Records(
+            rows.iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    read_row(row, layout).map_err(|e| invalid(format!("record {}: {}", i, e)))
+                })
+                .collect::<io::Result<_>>()?,
+        )),
+        value => {
+            let owned: OwnedStructuredIdo = serde_json::from_value(value)
+                .map_err(|e| invalid(format!("Not a structured .ido export: {}", e)))?;
+            Ok(Structured::Document(
+                Box::new(Meta::from_map(&owned.ido)?),
+                owned.document,
+            ))
+        }
+    }
+}
+
+fn read_serde<T: DeserializeOwned>(content: &[u8], format: Format) -> io::Result<T> {
+    let result = match format {
+        Format::Json => serde_json::from_slice(content).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml_ng::from_slice(content).map_err(|e| e.to_string()),
+        Format::Msgpack => rmp_serde::from_slice(content).map_err(|e| e.to_string()),
+        Format::Cbor => ciborium::from_reader(content).map_err(|e| e.to_string()),
+        Format::Csv | Format::Sql | Format::Arrow => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} files can't be read as structured data", format.name()),
+            ));
+        }
+    };
+    result.map_err(|e| invalid(format!("Failed to parse {}: {}", format.name(), e)))
+}
+
+/// Values of one `field: value` mapping, in layout order.
+fn read_row(row: &serde_json::Value, layout: &Layout) -> Result<Vec<Value>, String> {
+    let row = row
+        .as_object()
+        .ok_or("expected a mapping of field names to values")?;
+    layout
+        .fields
+        .iter()
+        .map(|field| {
+            let cell = match row.get(&field.name) {
+                Some(serde_json::Value::String(s)) => s.clone(),
+                Some(serde_json::Value::Number(n)) => n.to_string(),
+                Some(serde_json::Value::Bool(b)) => u8::from(*b).to_string(),
+                Some(serde_json::Value::Null) => String::new(),
+                Some(_) => return Err(format!("field '{}' must be a scalar", field.name)),
+                None => return Err(format!("missing field '{}'", field.name)),
+            };
+            field
+                .parse(&cell)
+                .map_err(|e| format!("field '{}': {}", field.name, e))
+        })
+        .collect()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(serde::Deserialize)]
+struct OwnedStructuredIdo {
+    ido: IndexMap<String, String>,
+    #[serde(flatten)]
+    document: Document,
+}
+
+#[derive(serde::Serialize)]
+struct StructuredIdo<'a> {
+    ido: IndexMap<String, String>,
+    #[serde(flatten)]
+    document: &'a Document,
+}
+
+/// Records serialized as a sequence of `field: value` mappings.
+struct Rows<'a> {
+    layout: &'a Layout,
+    records: &'a [Vec<Value>],
+}
+
+impl Serialize for Rows<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.records.iter().map(|values| Row {
+            layout: self.layout,
+            values,
+        }))
+    }
+}
+
+struct Row<'a> {
+    layout: &'a Layout,
+    values: &'a [Value],
+}
+
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (field, value) in self.layout.fields.iter().zip(self.values) {
+            map.serialize_entry(&field.name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Int(v) => serializer.serialize_i64(*v),
+            Value::Float(v) => serializer.serialize_f32(*v),
+            Value::Text(v) => serializer.serialize_str(v),
+            Value::Bytes(v) => serializer.serialize_str(&hex::encode(v)),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::layout::{Field, FieldType, Layout, Value};
+use arrow_array::{
+    ArrayRef, BinaryArray, Float32Array, Int8Array, Int16Array, Int32Array, RecordBatch,
+    StringArray, UInt8Array, UInt16Array, UInt32Array,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field as ArrowField, Schema};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Rows per record batch; readers can memory-map and skip whole batches.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// Write records as an Arrow IPC file (Feather v2).
+pub fn write<W: Write>(writer: W, layout: &Layout, records: &[Vec<Value>]) -> io::Result<()> {
+    let schema = Arc::new(Schema::new(
+        layout
+            .fields
+            .iter()
+            .map(|f| ArrowField::new(&f.name, data_type(f.kind), false))
+            .collect::<Vec<_>>(),
+    ));
+
+    let mut ipc = FileWriter::try_new(writer, &schema).map_err(io::Error::other)?;
+    for batch in records.chunks(BATCH_ROWS) {
+        let columns = layout
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| column(field, batch, i))
+            .collect();
+        let batch = RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)?;
+        ipc.write(&batch).map_err(io::Error::other)?;
+    }
+    ipc.finish().map_err(io::Error::other)
+}
+
+fn data_type(kind: FieldType) -> DataType {
+    match kind {
+        FieldType::U8 => DataType::UInt8,
+        FieldType::I8 => DataType::Int8,
+        FieldType::U16 => DataType::UInt16,
+        FieldType::I16 => DataType::Int16,
+        FieldType::U32 => DataType::UInt32,
+        FieldType::I32 => DataType::Int32,
+        FieldType::F32 => DataType::Float32,
+        FieldType::Utf16 | FieldType::Euckr => DataType::Utf8,
+        FieldType::Bytes => DataType::Binary,
+    }
+}
+
+fn column(field: &Field, rows: &[Vec<Value>], i: usize) -> ArrayRef {
+    let ints = || {
+        rows.iter().map(move |r| match r[i] {
+            Value::Int(v) => v,
+            _ => 0,
+        })
+    };
+    match field.kind {
+        FieldType::U8 => Arc::new(UInt8Array::from_iter_values(ints().map(|v| v as u8))),
+        FieldType::I8 => Arc::new(Int8Array::from_iter_values(ints().map(|v| v as i8))),
+        FieldType::U16 => Arc::new(UInt16Array::from_iter_values(ints().map(|v| v as u16))),
+        FieldType::I16 => Arc::new(Int16Array::from_iter_values(ints().map(|v| v as i16))),
+        FieldType::U32 => Arc::new(UInt32Array::from_iter_values(ints().map(|v| v as u32))),
+        FieldType::I32 => Arc::new(Int32Array::from_iter_values(ints().map(|v| v as i32))),
+        FieldType::F32 => Arc::new(Float32Array::from_iter_values(rows.iter().map(
+            |r| match r[i] {
+                Value::Float(v) => v,
+                _ => 0.0,
+            },
+        ))),
+        FieldType::Utf16 | FieldType::Euckr => Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|r| match &r[i] {
+                Value::Text(v) => v.as_str(),
+                _ => "",
+            }),
+        )),
+        FieldType::Bytes => Arc::new(BinaryArray::from_iter_values(rows.iter().map(
+            |r| match &r[i] {
+                Value::Bytes(v) => v.as_slice(),
+                _ => &[],
+            },
+        ))),
+    }
+}
@@ -0,0 +1,362 @@
+use crate::layout::{Layout, Value};
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// When fields get wrapped in quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Quoting {
+    /// Only fields containing the delimiter, quotes or newlines
+    #[default]
+    Necessary,
+    Always,
+    /// Every field that isn't a number
+    NonNumeric,
+    /// Never quote; fields holding the delimiter, quotes or newlines are refused
+    Never,
+}
+
+/// Delimiter, quoting and BOM settings, shared by export and import.
+#[derive(Debug, Clone, Copy)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quoting: Quoting,
+    /// Prefix exports with a UTF-8 BOM so Excel detects the encoding.
+    pub bom: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: b',',
+            quoting: Quoting::default(),
+            bom: false,
+        }
+    }
+}
+
+/// Parse a `--delimiter` value: a single ASCII character, or `tab`/`\t`.
+pub fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!(
+            "delimiter must be a single ASCII character, got '{}'",
+            s
+        )),
+    }
+}
+
+pub fn write<W: Write>(
+    writer: W,
+    layout: &Layout,
+    records: &[Vec<Value>],
+    dialect: &CsvDialect,
+) -> io::Result<()> {
+    let mut stream = Stream::new(writer, layout, dialect)?;
+    for record in records {
+        stream.write(record)?;
+    }
+    stream.flush()
+}
+
+/// A CSV export written a record at a time, for databases too large to
+/// hold: the same output as [`write`].
+pub struct Stream<W: Write> {
+    writer: csv::Writer<W>,
+    dialect: CsvDialect,
+    columns: Vec<String>,
+    /// CSV line of the next record, counting the header as 1.
+    row: u64,
+}
+
+impl<W: Write> Stream<W> {
+    /// Start the CSV with its BOM, if asked for, and header row.
+    pub fn new(mut writer: W, layout: &Layout, dialect: &CsvDialect) -> io::Result<Stream<W>> {
+        if dialect.bom {
+            writer.write_all(BOM)?;
+        }
+        let mut writer = writer_for(writer, dialect);
+        writer.write_record(layout.field_names())?;
+        Ok(Stream {
+            writer,
+            dialect: *dialect,
+            columns: layout.field_names().into_iter().map(String::from).collect(),
+            row: 2,
+        })
+    }
+
+    /// Write one record. Without quoting, a cell holding the delimiter, a
+    /// quote or a line break would read back as something else, so it is
+    /// refused instead.
+    pub fn write(&mut self, record: &[Value]) -> io::Result<()> {
+        let cells: Vec<String> = record.iter().map(Value::to_string).collect();
+        if self.dialect.quoting == Quoting::Never
+            && let Some(column) = cells.iter().position(|cell| {
+                cell.bytes()
+                    .any(|b| b == self.dialect.delimiter || b"\"\r\n".contains(&b))
+            })
+        {
+            let name = self.columns.get(column).map_or("", String::as_str);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                RowError::cell(
+                    self.row,
+                    name,
+                    "holds the delimiter, a quote or a line break, which --quote never can't write"
+                        .to_string(),
+                )
+                .to_string(),
+            ));
+        }
+        self.row += 1;
+        Ok(self.writer.write_record(&cells)?)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn writer_for<W: Write>(writer: W, dialect: &CsvDialect) -> csv::Writer<W> {
+    let style = match dialect.quoting {
+        Quoting::Necessary => csv::QuoteStyle::Necessary,
+        Quoting::Always => csv::QuoteStyle::Always,
+        Quoting::NonNumeric => csv::QuoteStyle::NonNumeric,
+        Quoting::Never => csv::QuoteStyle::Never,
+    };
+    csv::WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quote_style(style)
+        .from_writer(writer)
+}
+
+/// A CSV reader over `content` in the given dialect, skipping any BOM.
+pub fn reader_for<'a>(content: &'a [u8], dialect: &CsvDialect) -> csv::Reader<&'a [u8]> {
+    csv::ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quoting(dialect.quoting != Quoting::Never)
+        .from_reader(content.strip_prefix(BOM).unwrap_or(content))
+}
+
+/// Read records exported by [`write`]. Columns are matched to fields by
+/// header name, so they may be reordered; unknown columns are ignored.
+/// A leading BOM is skipped whether or not the dialect asks for one.
+pub fn read<R: Read>(
+    reader: R,
+    layout: &Layout,
+    dialect: &CsvDialect,
+) -> io::Result<Vec<Vec<Value>>> {
+    let mut records = Vec::new();
+    read_each(reader, layout, dialect, |record| {
+        records.push(record);
+        Ok(())
+    })?;
+    Ok(records)
+}
+
+/// Like [`read`], but hand each record to `f` as it is parsed instead of
+/// collecting them, so a CSV of any size is read in constant memory.
+/// Returns the number of records.
+pub fn read_each<R: Read>(
+    reader: R,
+    layout: &Layout,
+    dialect: &CsvDialect,
+    mut f: impl FnMut(Vec<Value>) -> io::Result<()>,
+) -> io::Result<u64> {
+    let (mut rdr, columns) = open(reader, layout, dialect)?;
+    let mut count = 0;
+    let mut row_values = csv::StringRecord::new();
+    while rdr.read_record(&mut row_values)? {
+        let row = line(&mut rdr, row_values.position());
+        let mut values = Vec::with_capacity(columns.len());
+        for (field, &column) in layout.fields.iter().zip(&columns) {
+            let cell = row_values.get(column).unwrap_or("");
+            values.push(field.parse(cell).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    RowError::cell(row, &field.name, e).to_string(),
+                )
+            })?);
+        }
+        f(values)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// A cell that doesn't fit its field, or a row that can't be imported.
+#[derive(Debug, Clone)]
+pub struct RowError {
+    /// Line of the CSV the row starts on, counting the header as 1; a
+    /// quoted cell may hold line breaks, so rows can span several.
+    pub row: u64,
+    /// Column of the cell, for errors in a single cell.
+    pub column: Option<String>,
+    pub message: String,
+}
+
+impl RowError {
+    fn cell(row: u64, column: &str, message: String) -> RowError {
+        RowError {
+            row,
+            column: Some(column.to_string()),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "row {}, column '{}': {}", self.row, column, self.message),
+            None => write!(f, "row {}: {}", self.row, self.message),
+        }
+    }
+}
+
+/// Check a CSV against `layout` without stopping at the first problem:
+/// every cell must parse as its field's type, be in range and fit the
+/// field's width once encoded. Records whose cells all fit are handed to
+/// `check`, with their index, for what can only be checked on a whole
+/// record. Returns the number of records and every error found.
+pub fn validate<R: Read>(
+    reader: R,
+    layout: &Layout,
+    dialect: &CsvDialect,
+    mut check: impl FnMut(u64, Vec<Value>) -> Result<(), String>,
+) -> io::Result<(u64, Vec<RowError>)> {
+    let (mut rdr, columns) = open(reader, layout, dialect)?;
+    let mut scratch = vec![0u8; layout.record_size];
+    let mut errors = Vec::new();
+    let mut count = 0;
+    let mut row_values = csv::StringRecord::new();
+    loop {
+        let record = count;
+        match rdr.read_record(&mut row_values) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                count += 1;
+                let row = line(&mut rdr, e.position());
+                errors.push(RowError {
+                    row,
+                    column: None,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        }
+        count += 1;
+        let row = line(&mut rdr, row_values.position());
+        let mut values = Vec::with_capacity(columns.len());
+        for (field, &column) in layout.fields.iter().zip(&columns) {
+            let cell = row_values.get(column).unwrap_or("");
+            match field
+                .parse(cell)
+                .and_then(|value| field.write(&value, &mut scratch).map(|()| value))
+            {
+                Ok(value) => values.push(value),
+                Err(e) => errors.push(RowError::cell(row, &field.name, e)),
+            }
+        }
+        if values.len() == columns.len()
+            && let Err(message) = check(record, values)
+        {
+            errors.push(RowError {
+                row,
+                column: None,
+                message,
+            });
+        }
+    }
+    Ok((count, errors))
+}
+
+/// The line of the CSV a row at `position` starts on. The csv crate's own
+/// line numbers count rows, not the line breaks inside quoted cells.
+fn line<R: Read>(rdr: &mut csv::Reader<Lines<R>>, position: Option<&csv::Position>) -> u64 {
+    let byte = position.map_or(rdr.position().byte(), csv::Position::byte);
+    rdr.get_mut().line_at(byte)
+}
+
+/// A reader that notes the line breaks passing through it, so rows can be
+/// placed on the line they start on. Only breaks the CSV reader has read
+/// ahead of the current row are kept.
+struct Lines<R> {
+    inner: R,
+    read: u64,
+    breaks: VecDeque<u64>,
+    passed: u64,
+}
+
+impl<R> Lines<R> {
+    /// The line, counting from 1, of a row starting at byte `offset`;
+    /// offsets must not go backwards. After a `\r\n` the csv crate can put
+    /// the start of the next row on the `\n`, which still ends the line
+    /// before.
+    fn line_at(&mut self, offset: u64) -> u64 {
+        while self.breaks.front().is_some_and(|&at| at <= offset) {
+            self.breaks.pop_front();
+            self.passed += 1;
+        }
+        self.passed + 1
+    }
+}
+
+impl<R: Read> Read for Lines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        for (i, _) in buf[..n].iter().enumerate().filter(|(_, b)| **b == b'\n') {
+            self.breaks.push_back(self.read + i as u64);
+        }
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+type ExportReader<R> = csv::Reader<Lines<io::BufReader<R>>>;
+
+/// A reader past the BOM and header of a CSV export, and the column of
+/// each of `layout`'s fields.
+fn open<R: Read>(
+    reader: R,
+    layout: &Layout,
+    dialect: &CsvDialect,
+) -> io::Result<(ExportReader<R>, Vec<usize>)> {
+    let mut reader = io::BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(BOM) {
+        reader.consume(BOM.len());
+    }
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quoting(dialect.quoting != Quoting::Never)
+        .from_reader(Lines {
+            inner: reader,
+            read: 0,
+            breaks: VecDeque::new(),
+            passed: 0,
+        });
+
+    let headers = rdr.headers()?.clone();
+    let columns = layout
+        .fields
+        .iter()
+        .map(|field| {
+            headers
+                .iter()
+                .position(|h| h.trim() == field.name)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("CSV is missing column '{}'", field.name),
+                    )
+                })
+        })
+        .collect::<io::Result<Vec<usize>>>()?;
+    Ok((rdr, columns))
+}
@@ -0,0 +1,146 @@
+use crate::layout::{FieldType, Layout, Value, identifier};
+use clap::ValueEnum;
+use std::io::{self, Write};
+
+/// Rows per INSERT statement; keeps statements well under MySQL's
+/// default max_allowed_packet.
+pub const ROWS_PER_INSERT: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SqlDialect {
+    #[default]
+    Sqlite,
+    Mysql,
+}
+
+impl SqlDialect {
+    fn quote_ident(self, name: &str) -> String {
+        match self {
+            SqlDialect::Sqlite => format!("\"{}\"", name.replace('"', "\"\"")),
+            SqlDialect::Mysql => format!("`{}`", name.replace('`', "``")),
+        }
+    }
+
+    fn quote_str(self, value: &str) -> String {
+        let escaped = value.replace('\'', "''");
+        match self {
+            SqlDialect::Sqlite => format!("'{}'", escaped),
+            // MySQL treats backslash as an escape character by default.
+            SqlDialect::Mysql => format!("'{}'", escaped.replace('\\', "\\\\")),
+        }
+    }
+
+    fn column_type(self, kind: FieldType, size: usize) -> String {
+        match self {
+            SqlDialect::Sqlite => match kind {
+                FieldType::F32 => "REAL",
+                FieldType::Utf16 | FieldType::Euckr => "TEXT",
+                FieldType::Bytes => "BLOB",
+                _ => "INTEGER",
+            }
+            .to_string(),
+            SqlDialect::Mysql => match kind {
+                FieldType::U8 => "TINYINT UNSIGNED".to_string(),
+                FieldType::I8 => "TINYINT".to_string(),
+                FieldType::U16 => "SMALLINT UNSIGNED".to_string(),
+                FieldType::I16 => "SMALLINT".to_string(),
+                FieldType::U32 => "INT UNSIGNED".to_string(),
+                FieldType::I32 => "INT".to_string(),
+                FieldType::F32 => "FLOAT".to_string(),
+                FieldType::Utf16 => format!("VARCHAR({})", size / 2),
+                FieldType::Euckr => format!("VARCHAR({})", size),
+                FieldType::Bytes => format!("VARBINARY({})", size),
+            },
+        }
+    }
+
+    fn literal(self, value: &Value) -> String {
+        match value {
+            Value::Int(v) => v.to_string(),
+            Value::Float(v) if v.is_finite() => v.to_string(),
+            Value::Float(_) => "NULL".to_string(),
+            Value::Text(v) => self.quote_str(v),
+            Value::Bytes(v) => format!("X'{}'", hex::encode(v)),
+        }
+    }
+}
+
+/// Emit `CREATE TABLE` plus batched `INSERT`s inside a single transaction.
+/// Rows get a `record_index` primary key matching their position in the file.
+pub fn write<W: Write>(
+    mut writer: W,
+    layout: &Layout,
+    records: &[Vec<Value>],
+    dialect: SqlDialect,
+) -> io::Result<()> {
+    begin(&mut writer, layout, dialect)?;
+    append(&mut writer, layout, records, 0, dialect)?;
+    finish(writer)
+}
+
+/// The `CREATE TABLE` and the start of the transaction, for exports done
+/// in parts: [`append`] each part, then [`finish`].
+pub fn begin<W: Write>(mut writer: W, layout: &Layout, dialect: SqlDialect) -> io::Result<()> {
+    let table = dialect.quote_ident(&identifier(&layout.name));
+    let index_column = dialect.quote_ident("record_index");
+
+    writeln!(writer, "-- {} database (generated by idoc)", layout.name)?;
+    writeln!(writer, "CREATE TABLE IF NOT EXISTS {} (", table)?;
+    writeln!(writer, "  {} INTEGER NOT NULL PRIMARY KEY,", index_column)?;
+    let columns: Vec<String> = layout
+        .fields
+        .iter()
+        .map(|f| {
+            format!(
+                "  {} {} NOT NULL",
+                dialect.quote_ident(&f.name),
+                dialect.column_type(f.kind, f.size())
+            )
+        })
+        .collect();
+    writeln!(writer, "{}", columns.join(",\n"))?;
+    match dialect {
+        SqlDialect::Sqlite => writeln!(writer, ");")?,
+        SqlDialect::Mysql => writeln!(writer, ") DEFAULT CHARSET=utf8mb4;")?,
+    }
+    writeln!(writer)?;
+
+    match dialect {
+        SqlDialect::Sqlite => writeln!(writer, "BEGIN TRANSACTION;")?,
+        SqlDialect::Mysql => writeln!(writer, "START TRANSACTION;")?,
+    }
+    Ok(())
+}
+
+/// `INSERT`s for `records`, whose `record_index` counts on from
+/// `first_index`.
+pub fn append<W: Write>(
+    mut writer: W,
+    layout: &Layout,
+    records: &[Vec<Value>],
+    first_index: u64,
+    dialect: SqlDialect,
+) -> io::Result<()> {
+    let table = dialect.quote_ident(&identifier(&layout.name));
+    let mut names = vec![dialect.quote_ident("record_index")];
+    names.extend(layout.fields.iter().map(|f| dialect.quote_ident(&f.name)));
+    let insert = format!("INSERT INTO {} ({}) VALUES", table, names.join(", "));
+
+    for (batch_index, batch) in records.chunks(ROWS_PER_INSERT).enumerate() {
+        writeln!(writer, "{}", insert)?;
+        for (i, record) in batch.iter().enumerate() {
+            let index = first_index + (batch_index * ROWS_PER_INSERT + i) as u64;
+            let mut values = vec![index.to_string()];
+            values.extend(record.iter().map(|v| dialect.literal(v)));
+            let separator = if i + 1 == batch.len() { ";" } else { "," };
+            writeln!(writer, "  ({}){}", values.join(", "), separator)?;
+        }
+    }
+    Ok(())
+}
+
+/// Commit the transaction opened by [`begin`].
+pub fn finish<W: Write>(mut writer: W) -> io::Result<()> {
+    writeln!(writer, "COMMIT;")?;
+    writer.flush()
+}
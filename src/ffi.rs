@@ -0,0 +1,154 @@
+//! C ABI, for launchers and tools in C, C++ or C# that link the
+//! converter instead of running the binary. Built as the `idoc` cdylib;
+//! the declarations are in `include/idoc.h`.
+//!
+//! Every conversion returns 0 and fills `out` with a buffer to release
+//! with [`idoc_free_buffer`], or returns -1 and leaves the reason in
+//! [`idoc_last_error`].
+
+use crate::text;
+use std::cell::RefCell;
+use std::ffi::{CString, c_char, c_int};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Bytes handed to the caller, owned by the library until released with
+/// [`idoc_free_buffer`].
+#[repr(C)]
+pub struct IdocBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+/// Run `convert` on the input buffer and hand its output over in `out`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes (or be null with `len` 0),
+/// and `out` must point to writable memory for an [`IdocBuffer`].
+unsafe fn run(
+    data: *const u8,
+    len: usize,
+    out: *mut IdocBuffer,
+    convert: impl FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+) -> c_int {
+    if out.is_null() || (data.is_null() && len > 0) {
+        set_error("null pointer passed".to_string());
+        return -1;
+    }
+    let input = if len == 0 {
+        &[][..]
+    } else {
+        // SAFETY: the caller guarantees `len` readable bytes at `data`.
+        unsafe { std::slice::from_raw_parts(data, len) }
+    };
+    // A panic must not unwind into the caller's frames.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| convert(input)))
+        .unwrap_or_else(|_| Err(io::Error::other("idoc panicked")));
+    let output = match result {
+        Ok(output) => output.into_boxed_slice(),
+        Err(e) => {
+            set_error(e.to_string());
+            return -1;
+        }
+    };
+    let len = output.len();
+    let data = Box::into_raw(output).cast::<u8>();
+    // SAFETY: the caller guarantees `out` is writable.
+    unsafe { out.write(IdocBuffer { data, len }) };
+    0
+}
+
+/// Decompile a whole .ido file: XML payloads come back as UTF-8 XML
+/// with the metadata comments that compile it back, shop databases as
+/// CSV, and anything else as the raw payload.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable
+/// [`IdocBuffer`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idoc_decompile_buffer(
+    data: *const u8,
+    len: usize,
+    out: *mut IdocBuffer,
+) -> c_int {
+    // SAFETY: passed on from the caller.
+    unsafe {
+        run(data, len, out, |input| {
+            text::decompile(input.to_vec()).map(|(_, output)| output)
+        })
+    }
+}
+
+/// Compile decompiled UTF-8 XML, with the metadata comments
+/// [`idoc_decompile_buffer`] adds, back into a .ido file.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable
+/// [`IdocBuffer`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idoc_compile_buffer(
+    data: *const u8,
+    len: usize,
+    out: *mut IdocBuffer,
+) -> c_int {
+    // SAFETY: passed on from the caller.
+    unsafe {
+        run(data, len, out, |input| {
+            let xml = std::str::from_utf8(input)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            text::compile(xml)
+        })
+    }
+}
+
+/// Parse a stored shop database into a JSON array with one object per
+/// record, keyed by field name.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable
+/// [`IdocBuffer`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idoc_parse_shopdb(
+    data: *const u8,
+    len: usize,
+    out: *mut IdocBuffer,
+) -> c_int {
+    // SAFETY: passed on from the caller.
+    unsafe { run(data, len, out, text::shopdb_json) }
+}
+
+/// Release a buffer returned by one of the conversions. A buffer with a
+/// null `data` is ignored.
+///
+/// # Safety
+///
+/// `buffer` must have come from this library and not been freed before.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idoc_free_buffer(buffer: IdocBuffer) {
+    if buffer.data.is_null() {
+        return;
+    }
+    // SAFETY: the caller guarantees it is a buffer made by `run`.
+    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)) });
+}
+
+/// Why the last failed call on this thread failed, as UTF-8. Valid until
+/// the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn idoc_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
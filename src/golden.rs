@@ -0,0 +1,174 @@
+//! `idoc golden`: hashes of what idoc makes of a corpus, recorded once
+//! and checked later, to catch behavior changes between releases.
+
+use crate::ido::{DecodeOptions, IdoFile, PayloadKind};
+use crate::selftest;
+use crate::text;
+use crate::walk;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the hashes are kept when no file is given.
+pub const DEFAULT_FILE: &str = ".idoc-golden.json";
+
+/// What one file decoded to. Steps that fail record their error instead,
+/// so a file that starts (or stops) failing counts as a change too.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expected {
+    pub kind: String,
+    /// SHA-256 of the decoded payload.
+    pub payload: String,
+    /// SHA-256 of the decompiled output: text for XML and databases, the
+    /// payload otherwise.
+    pub output: String,
+    /// SHA-256 of the file compiled back from that output.
+    pub rebuilt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Golden {
+    /// idoc version that recorded the hashes.
+    pub version: String,
+    pub files: BTreeMap<String, Expected>,
+}
+
+pub fn default_path(dir: &Path) -> PathBuf {
+    dir.join(DEFAULT_FILE)
+}
+
+fn hash(result: io::Result<impl AsRef<[u8]>>) -> String {
+    match result {
+        Ok(bytes) => hex::encode(Sha256::digest(bytes)),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+fn expected(path: &Path) -> Expected {
+    // Decoded without `IdoFile::read`, whose errors name the path, which
+    // differs between machines.
+    let file = fs::read(path).and_then(|bytes| IdoFile::decode(bytes, &DecodeOptions::default()));
+    match file {
+        Ok(file) => {
+            let output = match file.kind {
+                PayloadKind::Xml | PayloadKind::ShopDb => hash(text::render(&file)),
+                _ => hash(Ok(&file.data)),
+            };
+            Expected {
+                kind: file.kind.name().to_string(),
+                payload: hash(Ok(&file.data)),
+                output,
+                rebuilt: hash(selftest::rebuild(&file)),
+            }
+        }
+        Err(e) => {
+            let error = format!("error: {}", e);
+            Expected {
+                kind: "error".to_string(),
+                payload: error.clone(),
+                output: error.clone(),
+                rebuilt: error,
+            }
+        }
+    }
+}
+
+/// Hash every .ido below `dir`.
+pub fn record(dir: &Path) -> io::Result<Golden> {
+    let files = walk::ido_files(dir)?
+        .iter()
+        .map(|path| (walk::relative(path, dir), expected(path)))
+        .collect();
+    Ok(Golden {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        files,
+    })
+}
+
+pub fn load(path: &Path) -> io::Result<Golden> {
+    serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Differences between the recorded hashes and the corpus now.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub unchanged: usize,
+    /// Files whose hashes differ, with the ones that did.
+    pub changed: Vec<(String, Vec<&'static str>)>,
+    pub missing: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty() && self.added.is_empty()
+    }
+}
+
+pub fn check(dir: &Path, golden: &Golden) -> io::Result<Report> {
+    let now = record(dir)?;
+    let mut report = Report {
+        missing: golden
+            .files
+            .keys()
+            .filter(|name| !now.files.contains_key(*name))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    for (name, current) in &now.files {
+        let Some(recorded) = golden.files.get(name) else {
+            report.added.push(name.clone());
+            continue;
+        };
+        let changed: Vec<&'static str> = [
+            ("kind", &recorded.kind, &current.kind),
+            ("payload", &recorded.payload, &current.payload),
+            ("output", &recorded.output, &current.output),
+            ("rebuilt", &recorded.rebuilt, &current.rebuilt),
+        ]
+        .into_iter()
+        .filter(|(_, a, b)| a != b)
+        .map(|(what, _, _)| what)
+        .collect();
+        if changed.is_empty() {
+            report.unchanged += 1;
+        } else {
+            report.changed.push((name.clone(), changed));
+        }
+    }
+    Ok(report)
+}
+
+pub fn text(report: &Report, golden: &Golden) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Checked against hashes recorded by idoc {}: {} unchanged, {} changed, {} missing, {} new",
+        golden.version,
+        report.unchanged,
+        report.changed.len(),
+        report.missing.len(),
+        report.added.len()
+    )
+    .unwrap();
+    for (name, what) in &report.changed {
+        writeln!(out, "  changed  {} ({})", name, what.join(", ")).unwrap();
+    }
+    for name in &report.missing {
+        writeln!(out, "  missing  {}", name).unwrap();
+    }
+    for name in &report.added {
+        writeln!(out, "  new      {}", name).unwrap();
+    }
+    out
+}
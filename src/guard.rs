@@ -0,0 +1,358 @@
+//! Safe mode: outputs are not written into a game installation unless
+//! asked to, since overwriting live client files with half-finished
+//! edits is the easiest way to break a client. Outputs are also locked
+//! while written, so two runs can't interleave their writes. Each is
+//! written to a temporary file beside it and renamed into place once
+//! complete; the temporary file is removed when the run fails or is
+//! interrupted, so the previous output stays as it was.
+
+use crate::config;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+static GUARD: OnceLock<Guard> = OnceLock::new();
+/// Temporary files of outputs opened by [`create`] and not yet finished.
+static WRITING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// Numbers the temporary files of one process.
+static TEMPORARY: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Guard {
+    /// Write into game installations anyway.
+    pub allow_game_dir: bool,
+    /// Copy files to `<file>.bak` before overwriting them.
+    pub backup: bool,
+    /// Wait for another process writing the same output instead of
+    /// failing.
+    pub wait_for_lock: bool,
+}
+
+/// Set the flags for this run. Call once at startup; without it outputs
+/// into game installations are refused and nothing is backed up.
+pub fn init(guard: Guard) {
+    let _ = GUARD.set(guard);
+}
+
+fn get() -> Guard {
+    GUARD.get().copied().unwrap_or_default()
+}
+
+/// The game installation `path` lies in, and why it counts as one: a
+/// directory matching a `game_dirs` pattern, or one holding a file or
+/// folder matching a `game_markers` pattern. An executable alone doesn't
+/// make one, so idoc.exe can sit next to the folders it works in.
+pub fn game_dir(path: &Path) -> Option<(PathBuf, String)> {
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    // Not canonicalized: Windows would turn C:\ into \\?\C:\, which no
+    // pattern matches.
+    let dir = std::path::absolute(parent.unwrap_or(Path::new("."))).ok()?;
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let compile = |patterns: &[String]| -> Vec<glob::Pattern> {
+        patterns
+            .iter()
+            .filter_map(|p| glob::Pattern::new(p).ok())
+            .collect()
+    };
+    let patterns = compile(&config::get().game_dirs);
+    let markers = compile(&config::get().game_markers);
+    dir.ancestors().find_map(|ancestor| {
+        if let Some(pattern) = patterns
+            .iter()
+            .find(|p| p.matches_path_with(ancestor, options))
+        {
+            return Some((
+                ancestor.to_path_buf(),
+                format!("matches game_dirs pattern '{}'", pattern),
+            ));
+        }
+        if markers.is_empty() {
+            return None;
+        }
+        let marker = fs::read_dir(ancestor).ok()?.flatten().find(|entry| {
+            let name = entry_name(&entry.path());
+            markers.iter().any(|m| m.matches_with(&name, options))
+        })?;
+        Some((
+            ancestor.to_path_buf(),
+            format!("holds {}", entry_name(&marker.path())),
+        ))
+    })
+}
+
+fn entry_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |n| n.to_string_lossy().into_owned())
+}
+
+/// Refuse `path` if it is inside a game installation and that wasn't
+/// allowed; otherwise whether what it would overwrite is backed up.
+fn check(path: &Path) -> io::Result<bool> {
+    let guard = get();
+    let game_dir = game_dir(path);
+    if let Some((dir, reason)) = &game_dir
+        && !guard.allow_game_dir
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Refusing to write {}: {} looks like a game installation ({}). Pass --allow-game-dir to write there anyway",
+                path.display(),
+                dir.display(),
+                reason
+            ),
+        ));
+    }
+    Ok(guard.backup || (game_dir.is_some() && config::get().backup_in_game_dirs))
+}
+
+/// Copy `path`, read through its locked handle `file`, to `<path>.bak`.
+/// An existing backup is kept, so it stays the copy from before idoc
+/// first touched the file.
+fn backup_file(path: &Path, file: &mut File) -> io::Result<()> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    let backup = PathBuf::from(name);
+    if !backup.exists() {
+        // Read through the handle: on Windows the lock keeps other
+        // handles from reading.
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        fs::write(&backup, content)?;
+        eprintln!("Backed up {} to {}", path.display(), backup.display());
+    }
+    Ok(())
+}
+
+/// [`File::create`], after [`check`], holding an exclusive advisory lock
+/// on `path` until the output is finished or dropped. The file is only
+/// backed up once the lock is held, so a run that has to wait doesn't
+/// copy a file mid-write.
+///
+/// What is written goes to a temporary file beside `path`, which
+/// [`Output::finish`] renames over it; dropped unfinished, after an
+/// error, the temporary file is removed and `path` is left alone. The
+/// list of temporary files stays locked from creating one until it is on
+/// the list, so an interrupt either finds it there or finds it untouched.
+pub fn create(path: &Path) -> io::Result<Output> {
+    let backup = check(path)? && path.is_file();
+    let existed = path.exists();
+    let mut list = writing();
+    let mut lock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if get().wait_for_lock => {
+            eprintln!("Waiting for another process writing {}...", path.display());
+            drop(list);
+            lock.lock()?;
+            list = writing();
+        }
+        Err(TryLockError::WouldBlock) => {
+            return Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                format!(
+                    "{} is being written by another process; pass --wait-for-lock to wait for it",
+                    path.display()
+                ),
+            ));
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    if backup {
+        backup_file(path, &mut lock)?;
+    }
+    // Devices such as /dev/null are written as they are.
+    let metadata = lock.metadata()?;
+    if !metadata.is_file() {
+        return Ok(Output {
+            file: lock,
+            replace: None,
+        });
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(
+        ".{}-{}.idoc-tmp",
+        std::process::id(),
+        TEMPORARY.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temporary = PathBuf::from(temporary);
+    let file = File::create(&temporary)?;
+    list.push(temporary.clone());
+    // An interrupt removes the empty file made for the lock too.
+    if !existed {
+        list.push(path.to_path_buf());
+    }
+    if existed {
+        fs::set_permissions(&temporary, metadata.permissions())?;
+    }
+    Ok(Output {
+        file,
+        replace: Some(Replace {
+            temporary,
+            path: path.to_path_buf(),
+            created: !existed,
+            _lock: lock,
+        }),
+    })
+}
+
+fn writing() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    WRITING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// An output being written. It replaces the file at its path once
+/// [finished](Output::finish); dropped before that, nothing is replaced.
+#[must_use = "an output replaces its file only once finished"]
+pub struct Output {
+    file: File,
+    /// `None` for devices, which are written directly.
+    replace: Option<Replace>,
+}
+
+/// The temporary file an [`Output`] is written to, and the file it is
+/// renamed over.
+struct Replace {
+    temporary: PathBuf,
+    path: PathBuf,
+    /// `path` was created to hold the lock, and goes if nothing replaces it.
+    created: bool,
+    _lock: File,
+}
+
+impl Output {
+    /// Put the written output in place of the file at its path.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let Some(replace) = self.replace.take() else {
+            return Ok(());
+        };
+        let mut list = writing();
+        let renamed = fs::rename(&replace.temporary, &replace.path);
+        list.retain(|p| *p != replace.temporary && *p != replace.path);
+        drop(list);
+        if renamed.is_err() {
+            discard(&replace);
+        }
+        renamed
+    }
+}
+
+/// Remove an unfinished output's temporary file, and the empty file made
+/// to lock its path if there was none before.
+fn discard(replace: &Replace) {
+    writing().retain(|p| *p != replace.temporary && *p != replace.path);
+    let _ = fs::remove_file(&replace.temporary);
+    if replace.created {
+        let _ = fs::remove_file(&replace.path);
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some(replace) = &self.replace {
+            discard(replace);
+        }
+    }
+}
+
+/// Remove the temporary files of outputs being written when the run is
+/// interrupted (Ctrl-C or a termination request), then exit; the files
+/// they would have replaced are left as they were. A second Ctrl-C exits
+/// at once.
+#[cfg(not(target_family = "wasm"))]
+pub fn handle_interrupts() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        let registered =
+            signal_hook::flag::register_conditional_default(signal, Arc::clone(&interrupted))
+                .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&interrupted)));
+        if registered.is_err() {
+            return;
+        }
+    }
+    // Files can't be removed from inside a signal handler, so a thread
+    // watches for the flag instead.
+    std::thread::spawn(move || {
+        while !interrupted.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        eprintln!("\nInterrupted");
+        // Held until exit, so nothing more is created meanwhile.
+        let mut list = writing();
+        for path in list.drain(..) {
+            match fs::remove_file(&path) {
+                Ok(()) => eprintln!("Removed unfinished output {}", path.display()),
+                Err(e) => eprintln!(
+                    "{} is unfinished and could not be removed ({})",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        std::process::exit(130);
+    });
+}
+
+/// [`fs::write`], through [`create`].
+pub fn write(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut output = create(path)?;
+    output.write_all(content)?;
+    output.finish()
+}
+
+/// [`fs::copy`], through [`create`] of the destination.
+pub fn copy(from: &Path, to: &Path) -> io::Result<u64> {
+    let mut output = create(to)?;
+    let copied = io::copy(&mut File::open(from)?, &mut output)?;
+    output.finish()?;
+    Ok(copied)
+}
+
+/// Create the directories above `path`, checked like [`create`] so a
+/// refused output leaves no folders behind in a game installation.
+pub fn create_dirs(path: &Path) -> io::Result<()> {
+    check(path)?;
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Write via a temporary file, creating the directories above `path`, so
+/// an interrupted write never leaves a truncated file behind. Not checked
+/// like [`create`]: deploys and snapshot restores write into game
+/// installations on purpose.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".idoc-tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
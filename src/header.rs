@@ -0,0 +1,263 @@
+//! Interpretation of the header in front of compressed payloads, driven
+//! by the built-in `header` layout, and the registry of known-good headers.
+
+use crate::compression::{self, Compression};
+use crate::config::{self, KnownHeader};
+use crate::ido;
+use crate::layout::{Endian, Layout, Value, endian};
+use crate::xor::XorKey;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const COMPRESSED_SIZE: &str = "compressed_size";
+const DECOMPRESSED_SIZE: &str = "decompressed_size";
+
+const KNOWN_HEADERS: &str = include_str!("headers.toml");
+
+/// The header layout from the config file, or the built-in one.
+pub fn layout() -> io::Result<Layout> {
+    match &config::get().header_layout {
+        Some(path) => Layout::load(path),
+        None => Ok(Layout::builtin("header").expect("header layout is built in")),
+    }
+}
+
+#[derive(Deserialize)]
+struct Registry {
+    headers: Vec<KnownHeader>,
+}
+
+/// Known headers, those from the config file first. The built-in list
+/// in `headers.toml` is still empty.
+pub fn known() -> impl Iterator<Item = &'static KnownHeader> {
+    static BUILT_IN: OnceLock<Vec<KnownHeader>> = OnceLock::new();
+    let built_in = BUILT_IN.get_or_init(|| {
+        toml::from_str::<Registry>(KNOWN_HEADERS)
+            .expect("built-in header registry is valid")
+            .headers
+    });
+    config::get().headers.iter().chain(built_in)
+}
+
+/// The known header meant for files named like `path`.
+pub fn known_for(path: &Path) -> Option<&'static KnownHeader> {
+    known().find(|k| k.applies_to(path))
+}
+
+/// Offsets where `header` differs from `known`, leaving out the layout's
+/// size fields since those change with every payload.
+pub fn differences(layout: &Layout, known: &[u8], header: &[u8]) -> Vec<usize> {
+    let sizes: Vec<_> = [COMPRESSED_SIZE, DECOMPRESSED_SIZE]
+        .iter()
+        .filter_map(|name| layout.field(name))
+        .map(|f| f.offset..f.end())
+        .collect();
+    (0..known.len().max(header.len()))
+        .filter(|i| !sizes.iter().any(|r| r.contains(i)))
+        .filter(|&i| known.get(i) != header.get(i))
+        .collect()
+}
+
+/// The known header `header` matches, if any.
+pub fn identify(layout: &Layout, header: &[u8]) -> Option<&'static KnownHeader> {
+    known().find(|k| differences(layout, &k.header, header).is_empty())
+}
+
+/// Named header fields and their values. Fields past the end of a short
+/// header are left out.
+pub fn fields(layout: &Layout, header: &[u8]) -> Vec<(String, Value)> {
+    layout
+        .fields
+        .iter()
+        .filter(|f| f.end() <= header.len())
+        .map(|f| (f.name.clone(), f.read(header)))
+        .collect()
+}
+
+/// Offsets of u32s in the header, in the run's byte order, that equal the
+/// payload's compressed or decompressed size: likely size fields not yet
+/// named in the layout.
+pub fn find_sizes(
+    header: &[u8],
+    compressed: usize,
+    decompressed: usize,
+) -> Vec<(usize, &'static str)> {
+    header
+        .windows(4)
+        .enumerate()
+        .filter_map(|(offset, bytes)| {
+            let value = match endian() {
+                Endian::Little => LittleEndian::read_u32(bytes),
+                Endian::Big => BigEndian::read_u32(bytes),
+            } as usize;
+            if value == compressed && compressed != 0 {
+                Some((offset, COMPRESSED_SIZE))
+            } else if value == decompressed && decompressed != 0 {
+                Some((offset, DECOMPRESSED_SIZE))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Problems with a header about to be written in front of a compressed
+/// payload, as warnings.
+pub fn validate(header: &[u8], expected: usize) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if header.len() != expected {
+        warnings.push(format!(
+            "Header is {} bytes; clients expect {}",
+            header.len(),
+            expected
+        ));
+    }
+    if let Some(kind) = ido::sniff_header(header) {
+        warnings.push(format!(
+            "Header type tag looks like a {}; the file will be read back as uncompressed",
+            kind.description()
+        ));
+    }
+    warnings
+}
+
+/// Largest header length tried when looking for where the stream starts.
+const MAX_HEADER_SIZE: usize = 0x400;
+
+/// For a payload that failed to decode: a warning when no stream even
+/// starts after the `header_size`-byte header, suggesting the header
+/// lengths after which a zlib stream does decode, nearest first.
+pub fn stream_warning(file: &[u8], header_size: usize, xor_key: Option<&XorKey>) -> Option<String> {
+    let payload_at = |start: usize| {
+        let mut payload = file[start..].to_vec();
+        if let Some(key) = xor_key {
+            key.apply(&mut payload);
+        }
+        payload
+    };
+    if header_size > file.len() || compression::starts_any_stream(&payload_at(header_size)) {
+        return None;
+    }
+    let mut sizes: Vec<usize> = (0..=file.len().min(MAX_HEADER_SIZE))
+        .filter(|&start| start != header_size)
+        .filter(|&start| {
+            let payload = payload_at(start);
+            compression::is_zlib_header(&payload)
+                && compression::decompress_with(&payload, Compression::Zlib).is_ok()
+        })
+        .collect();
+    sizes.sort_by_key(|&size| size.abs_diff(header_size));
+    let suggestion = match sizes.as_slice() {
+        [] => format!(
+            "none starts within the first 0x{:X} bytes either",
+            MAX_HEADER_SIZE
+        ),
+        [size, ..] => {
+            let others: Vec<String> = sizes[1..]
+                .iter()
+                .take(2)
+                .map(|size| format!("0x{:X}", size))
+                .collect();
+            let mut suggestion = format!(
+                "one decodes after 0x{:X} bytes (try --header-size 0x{:X})",
+                size, size
+            );
+            if !others.is_empty() {
+                write!(suggestion, ", also after {}", others.join(", ")).unwrap();
+            }
+            suggestion
+        }
+    };
+    Some(format!(
+        "No zlib stream starts after the assumed 0x{:X}-byte header; {}",
+        header_size, suggestion
+    ))
+}
+
+/// Fill the layout's size fields, if it has any, for the given payload.
+pub fn fill_sizes(layout: &Layout, header: &mut [u8], compressed: usize, decompressed: usize) {
+    for (name, size) in [
+        (COMPRESSED_SIZE, compressed),
+        (DECOMPRESSED_SIZE, decompressed),
+    ] {
+        if let Some(field) = layout.field(name).filter(|f| f.end() <= header.len()) {
+            // Out-of-range sizes are left as they were.
+            let _ = field.write(&Value::Int(size as i64), header);
+        }
+    }
+}
+
+/// Size fields of the layout that disagree with the payload, as
+/// `(field, recorded, actual)`. A decompressed size that isn't known is
+/// not checked.
+pub fn size_mismatches(
+    layout: &Layout,
+    header: &[u8],
+    compressed: usize,
+    decompressed: Option<usize>,
+) -> Vec<(&'static str, i64, usize)> {
+    [
+        (COMPRESSED_SIZE, Some(compressed)),
+        (DECOMPRESSED_SIZE, decompressed),
+    ]
+    .into_iter()
+    .filter_map(|(name, actual)| {
+        let actual = actual?;
+        let field = layout.field(name).filter(|f| f.end() <= header.len())?;
+        match field.read(header) {
+            Value::Int(recorded) if recorded != actual as i64 => Some((name, recorded, actual)),
+            _ => None,
+        }
+    })
+    .collect()
+}
+
+/// Byte range of `length` header bytes from `offset` in `file`, cut off
+/// at the end of the `header_size`-byte header.
+pub fn range(
+    file: &[u8],
+    header_size: usize,
+    offset: usize,
+    length: usize,
+) -> io::Result<Range<usize>> {
+    let end = file.len().min(header_size);
+    if offset >= end {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Offset 0x{:02X} is past the end of the {}-byte header",
+                offset, end
+            ),
+        ));
+    }
+    Ok(offset..end.min(offset.saturating_add(length)))
+}
+
+/// Parse a header offset given in decimal or `0x` hex.
+pub fn parse_offset(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => usize::from_str_radix(digits, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not an offset", text))
+}
+
+/// Raw bytes taken as a single argument (clap would read a `Vec<u8>`
+/// as one value per argument).
+pub type Bytes = Vec<u8>;
+
+/// Parse hex bytes, allowing spaces between them.
+pub fn parse_bytes(text: &str) -> Result<Bytes, String> {
+    let digits: String = text.split_whitespace().collect();
+    match hex::decode(&digits) {
+        Ok(bytes) if !bytes.is_empty() => Ok(bytes),
+        Ok(_) => Err("no bytes given".to_string()),
+        Err(e) => Err(format!("'{}' is not hex: {}", text, e)),
+    }
+}
@@ -0,0 +1,126 @@
+//! Hex dumps annotated with what idoc knows about the bytes: header
+//! fields, where the compressed stream starts, and database records.
+
+use crate::header;
+use crate::ido::{self, DecodeOptions, IdoFile, PayloadKind};
+use crate::layout::{Layout, Value};
+use std::fmt::Write;
+use std::io;
+
+const ROW: usize = 16;
+
+/// Dump of the file as stored.
+pub fn file(bytes: &[u8], options: &DecodeOptions, layout: &Layout) -> io::Result<String> {
+    ido::check_length(bytes.len() as u64, options.header_size())?;
+    let mut header = bytes[..options.header_size()].to_vec();
+    options.decode_header(&mut header);
+    if let Some(kind) = ido::sniff_header(&header) {
+        return Ok(dump(bytes, &stored(kind, bytes, layout)));
+    }
+
+    let mut notes = vec![(0, "header".to_string())];
+    for field in &header::layout()?.fields {
+        if field.end() <= header.len() {
+            notes.push((field.offset, note(&field.name, &field.read(&header))));
+        }
+    }
+    let decoded = ido::decode_payload(&header, bytes[options.header_size()..].to_vec(), options)?;
+    let xor = match &decoded.meta.xor_key {
+        Some(key) => format!(", XOR key {}", key),
+        None => String::new(),
+    };
+    notes.push((
+        options.header_size(),
+        format!(
+            "{} stream{} ({} bytes decompressed)",
+            decoded.compression().name(),
+            xor,
+            decoded.data.len()
+        ),
+    ));
+    if decoded.trailing > 0 {
+        notes.push((
+            bytes.len() - decoded.trailing,
+            format!("{} trailing bytes", decoded.trailing),
+        ));
+    }
+    Ok(dump(bytes, &notes))
+}
+
+/// Dump of the decompressed payload.
+pub fn payload(bytes: Vec<u8>, options: &DecodeOptions, layout: &Layout) -> io::Result<String> {
+    let file = IdoFile::decode(bytes, options)?;
+    Ok(dump(&file.data, &stored(file.kind, &file.data, layout)))
+}
+
+/// Notes for a payload of `kind`: one per record and field for
+/// databases, otherwise just what it is.
+fn stored(kind: PayloadKind, data: &[u8], layout: &Layout) -> Vec<(usize, String)> {
+    if kind != PayloadKind::ShopDb {
+        return vec![(0, kind.description().to_string())];
+    }
+    let mut notes = Vec::new();
+    for (index, record) in data.chunks_exact(layout.record_size).enumerate() {
+        let start = index * layout.record_size;
+        notes.push((start, format!("{} record {}", layout.name, index)));
+        for field in &layout.fields {
+            notes.push((start + field.offset, note(&field.name, &field.read(record))));
+        }
+    }
+    let rest = data.len() % layout.record_size;
+    if rest > 0 {
+        notes.push((
+            data.len() - rest,
+            format!("{} bytes past the last record", rest),
+        ));
+    }
+    notes
+}
+
+fn note(name: &str, value: &Value) -> String {
+    match value {
+        Value::Text(text) => format!("{}={:?}", name, text),
+        value => format!("{}={}", name, value),
+    }
+}
+
+/// xxd-style rows, each followed by the notes for offsets within it.
+pub fn dump(data: &[u8], notes: &[(usize, String)]) -> String {
+    let mut notes: Vec<&(usize, String)> = notes.iter().collect();
+    notes.sort_by_key(|(offset, _)| *offset);
+    let mut notes = notes.into_iter().peekable();
+
+    let mut out = String::new();
+    for (index, row) in data.chunks(ROW).enumerate() {
+        let start = index * ROW;
+        write!(out, "{:08x}: ", start).unwrap();
+        for i in 0..ROW {
+            match row.get(i) {
+                Some(byte) => write!(out, "{:02x}", byte).unwrap(),
+                None => out.push_str("  "),
+            }
+            if i % 2 == 1 {
+                out.push(' ');
+            }
+        }
+        out.push(' ');
+        out.extend(row.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+
+        let row_notes: Vec<String> =
+            std::iter::from_fn(|| notes.next_if(|(offset, _)| *offset < start + ROW))
+                .map(|(offset, text)| format!("{:x}: {}", offset, text))
+                .collect();
+        if !row_notes.is_empty() {
+            out.push_str(&" ".repeat(ROW - row.len()));
+            write!(out, "  ; {}", row_notes.join("; ")).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,128 @@
+//! Commands from `[hooks]` in `idoc.toml`, run before and after each
+//! compile and decompile, and around each batch of them: a validator on
+//! the input, a copy of the output into the client, a game restart,
+//! without wrapping idoc in a script.
+//!
+//! Each command runs through the shell (`sh -c`, or `cmd /C` on
+//! Windows), in per-file hooks with `{input}` and `{output}` replaced by
+//! the quoted paths.
+//! A command that fails stops the run: before the conversion, nothing is
+//! converted.
+
+use crate::config;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// When, relative to a conversion, a hook runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    BeforeCompile,
+    AfterCompile,
+    BeforeDecompile,
+    AfterDecompile,
+    BeforeBatch,
+    AfterBatch,
+}
+
+impl Stage {
+    /// Name of the stage's list under `[hooks]`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::BeforeCompile => "before_compile",
+            Stage::AfterCompile => "after_compile",
+            Stage::BeforeDecompile => "before_decompile",
+            Stage::AfterDecompile => "after_decompile",
+            Stage::BeforeBatch => "before_batch",
+            Stage::AfterBatch => "after_batch",
+        }
+    }
+
+    fn commands(self) -> &'static [String] {
+        let hooks = &config::get().hooks;
+        match self {
+            Stage::BeforeCompile => &hooks.before_compile,
+            Stage::AfterCompile => &hooks.after_compile,
+            Stage::BeforeDecompile => &hooks.before_decompile,
+            Stage::AfterDecompile => &hooks.after_decompile,
+            Stage::BeforeBatch => &hooks.before_batch,
+            Stage::AfterBatch => &hooks.after_batch,
+        }
+    }
+}
+
+/// Run the commands of `stage` for converting `input` to `output`, in
+/// order, stopping at the first that fails.
+pub fn run(stage: Stage, input: &Path, output: &Path) -> io::Result<()> {
+    execute(stage, |command| {
+        command
+            .replace("{input}", &quote(input))
+            .replace("{output}", &quote(output))
+    })
+}
+
+/// Run the commands of a batch `stage`, which have no placeholders.
+pub fn run_batch(stage: Stage) -> io::Result<()> {
+    execute(stage, str::to_string)
+}
+
+fn execute(stage: Stage, line: impl Fn(&str) -> String) -> io::Result<()> {
+    for command in stage.commands() {
+        let line = line(command);
+        let status = shell(&line).status().map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Cannot run {} hook '{}': {}", stage.name(), command, e),
+            )
+        })?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} hook '{}' failed ({})",
+                stage.name(),
+                command,
+                status
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Run `convert` between the `before` and `after` hooks, returning the
+/// path it returns. The after hooks only run when the conversion
+/// succeeded, with that path as `{output}`: where the output was written,
+/// which may differ from the one asked for.
+pub fn around(
+    before: Stage,
+    after: Stage,
+    input: &Path,
+    output: &Path,
+    convert: impl FnOnce() -> io::Result<PathBuf>,
+) -> io::Result<PathBuf> {
+    run(before, input, output)?;
+    let written = convert()?;
+    run(after, input, &written)?;
+    Ok(written)
+}
+
+fn shell(line: &str) -> Command {
+    let mut command;
+    if cfg!(windows) {
+        command = Command::new("cmd");
+        command.arg("/C").arg(line);
+    } else {
+        command = Command::new("sh");
+        command.arg("-c").arg(line);
+    }
+    command
+}
+
+/// `path` as one shell word.
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        // Windows paths cannot contain quotes.
+        format!("\"{}\"", path)
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,419 @@
+use crate::compression::{self, Compression, Damage};
+use crate::config;
+use crate::header;
+use crate::layout::{self, Endian};
+use crate::meta::Meta;
+use crate::signature::{self, Handler, Signature};
+use crate::timings::{self, Stage};
+use crate::xor::{self, XorKey};
+use encoding_rs::EUC_KR;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Size of the header preceding the payload.
+pub const HEADER_SIZE: usize = 0x5F;
+
+/// Fail when a file of `len` bytes cannot even hold a header.
+pub fn check_length(len: u64, header_size: usize) -> io::Result<()> {
+    if len < header_size as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("file too small to be a valid .ido ({} bytes)", len),
+        ));
+    }
+    Ok(())
+}
+
+/// A file read for inspection. Stored files are not held whole: a
+/// multi-gigabyte database is only hashed as it streams past.
+pub struct Opened {
+    pub len: u64,
+    /// The header as it is on disk; shorter than a header when the file
+    /// is.
+    pub header: Vec<u8>,
+    /// Everything after the header; empty for stored files.
+    pub payload: Vec<u8>,
+    /// What a stored file holds, from its decoded header.
+    pub stored: Option<PayloadKind>,
+    pub sha256: String,
+}
+
+impl Opened {
+    /// The file's bytes, for a compressed file.
+    pub fn bytes(&self) -> Vec<u8> {
+        [self.header.as_slice(), &self.payload].concat()
+    }
+}
+
+pub fn open(path: &Path, options: &DecodeOptions) -> io::Result<Opened> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut header = Vec::new();
+    (&mut file)
+        .take(options.header_size() as u64)
+        .read_to_end(&mut header)?;
+    let mut hasher = Sha256::new();
+    hasher.update(&header);
+
+    let mut decoded = header.clone();
+    options.decode_header(&mut decoded);
+    let stored = (header.len() == options.header_size())
+        .then(|| sniff_header(&decoded))
+        .flatten();
+    let mut payload = Vec::new();
+    if stored.is_some() {
+        let mut buffer = vec![0u8; 1 << 16];
+        loop {
+            match file.read(&mut buffer)? {
+                0 => break,
+                n => hasher.update(&buffer[..n]),
+            }
+        }
+    } else {
+        file.read_to_end(&mut payload)?;
+        hasher.update(&payload);
+    }
+    Ok(Opened {
+        len,
+        header,
+        payload,
+        stored,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+/// What an .ido file turned out to contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    Gamebryo,
+    ShopDb,
+    Dds,
+    Tga,
+    Bmp,
+    Png,
+    Xml,
+    /// Neither a known signature nor text.
+    Unknown,
+    /// A type from a user signature in idoc.toml.
+    Custom(&'static Signature),
+}
+
+impl PayloadKind {
+    /// Short identifier used in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            PayloadKind::Gamebryo => "gamebryo",
+            PayloadKind::ShopDb => "shopdb",
+            PayloadKind::Dds => "dds",
+            PayloadKind::Tga => "tga",
+            PayloadKind::Bmp => "bmp",
+            PayloadKind::Png => "png",
+            PayloadKind::Xml => "xml",
+            PayloadKind::Unknown => "unknown",
+            PayloadKind::Custom(signature) => &signature.name,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            PayloadKind::Gamebryo => "Gamebryo State Block (Binary)",
+            PayloadKind::ShopDb => "Shop Database (Binary Structs)",
+            PayloadKind::Dds => "DDS Texture",
+            PayloadKind::Tga => "TGA Texture",
+            PayloadKind::Bmp => "BMP Texture",
+            PayloadKind::Png => "PNG Texture",
+            PayloadKind::Xml => "XML",
+            PayloadKind::Unknown => "Unknown Binary",
+            PayloadKind::Custom(signature) => &signature.description,
+        }
+    }
+
+    /// Natural extension of the decompiled output.
+    pub fn extension(self) -> &'static str {
+        match self {
+            PayloadKind::Gamebryo => "gb",
+            PayloadKind::ShopDb => "csv",
+            PayloadKind::Dds => "dds",
+            PayloadKind::Tga => "tga",
+            PayloadKind::Bmp => "bmp",
+            PayloadKind::Png => "png",
+            PayloadKind::Xml => "xml",
+            PayloadKind::Unknown => "bin",
+            PayloadKind::Custom(signature) => &signature.extension,
+        }
+    }
+
+    /// Extension given to the decompiled output: the one set for the type
+    /// under `extensions` in `idoc.toml`, or its natural one.
+    pub fn output_extension(self) -> &'static str {
+        config::extension_for(self.name()).unwrap_or(self.extension())
+    }
+
+    /// Whether the payload is decompiled by saving it as it is, rather
+    /// than as text.
+    pub fn is_raw(self) -> bool {
+        match self {
+            PayloadKind::Dds
+            | PayloadKind::Tga
+            | PayloadKind::Bmp
+            | PayloadKind::Png
+            | PayloadKind::Unknown => true,
+            PayloadKind::Custom(signature) => signature.handler == Handler::Raw,
+            _ => false,
+        }
+    }
+
+    /// The kind called `name`, built in or a signature's.
+    pub fn from_name(name: &str) -> Option<PayloadKind> {
+        let kind = match name {
+            "gamebryo" => PayloadKind::Gamebryo,
+            "shopdb" => PayloadKind::ShopDb,
+            "xml" => PayloadKind::Xml,
+            "unknown" => PayloadKind::Unknown,
+            _ => {
+                return signature::all()
+                    .find(|s| s.name == name)
+                    .map(PayloadKind::from_signature);
+            }
+        };
+        Some(kind)
+    }
+
+    /// The built-in kind a signature stands for, or a custom one.
+    fn from_signature(signature: &'static Signature) -> PayloadKind {
+        match signature.name.as_str() {
+            "dds" => PayloadKind::Dds,
+            "tga" => PayloadKind::Tga,
+            "bmp" => PayloadKind::Bmp,
+            "png" => PayloadKind::Png,
+            "xml" => PayloadKind::Xml,
+            _ => PayloadKind::Custom(signature),
+        }
+    }
+}
+
+/// Kinds recognisable from the header alone. These files are stored
+/// without compression and the "header" is really the start of the data.
+pub fn sniff_header(header: &[u8]) -> Option<PayloadKind> {
+    // A shop database starts with category 1, item type 1 as u16s.
+    let shop_db: &[u8] = match layout::endian() {
+        Endian::Little => &[0x01, 0x00, 0x01, 0x00],
+        Endian::Big => &[0x00, 0x01, 0x00, 0x01],
+    };
+    if header.len() >= 8 && header[0] == 0x14 && &header[4..7] == b"_gb" {
+        Some(PayloadKind::Gamebryo)
+    } else if header.starts_with(shop_db) {
+        Some(PayloadKind::ShopDb)
+    } else {
+        None
+    }
+}
+
+/// Classify a decompressed payload by its signature. Anything without
+/// one is taken to be XML if it reads as text.
+pub fn detect_payload(data: &[u8]) -> PayloadKind {
+    match signature::detect(data) {
+        Some(signature) => PayloadKind::from_signature(signature),
+        None if is_text(data) => PayloadKind::Xml,
+        None => PayloadKind::Unknown,
+    }
+}
+
+/// Valid EUC-KR or UTF-8 with next to no control characters.
+fn is_text(data: &[u8]) -> bool {
+    let controls = data
+        .iter()
+        .filter(|&&b| b < 0x20 && !b"\t\r\n".contains(&b))
+        .count();
+    controls * 100 <= data.len()
+        && (std::str::from_utf8(data).is_ok() || !EUC_KR.decode_without_bom_handling(data).1)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    pub xor_key: Option<XorKey>,
+    pub xor_header: bool,
+    /// Keep what decompresses from a damaged stream instead of failing.
+    pub recover: bool,
+    /// Header length, for file families that don't use [`HEADER_SIZE`].
+    pub header_size: Option<usize>,
+}
+
+impl DecodeOptions {
+    pub fn header_size(&self) -> usize {
+        self.header_size.unwrap_or(HEADER_SIZE)
+    }
+
+    /// Undo header obfuscation in place, if requested.
+    pub fn decode_header(&self, header: &mut [u8]) {
+        if let Some(key) = self.xor_key.as_ref().filter(|_| self.xor_header) {
+            key.apply(header);
+        }
+    }
+}
+
+/// A decompressed payload with the metadata to rebuild its file.
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub kind: PayloadKind,
+    pub meta: Meta,
+    pub data: Vec<u8>,
+    /// Whether the XOR key in `meta` was found by brute force.
+    pub found_key: bool,
+    /// Bytes after the last compressed stream, kept in `meta`.
+    pub trailing: usize,
+    /// Where the stream broke, when `data` is only what came before.
+    pub damage: Option<Damage>,
+}
+
+/// Decompress the payload following an (already XOR-decoded) header.
+pub fn decode_payload(
+    header: &[u8],
+    mut payload: Vec<u8>,
+    options: &DecodeOptions,
+) -> io::Result<Decoded> {
+    let mut found_key = None;
+    let mut damage = None;
+    let decompress = timings::start(Stage::Decompress);
+    if let Some(key) = &options.xor_key {
+        key.apply(&mut payload);
+    }
+    let decompressed = match compression::decompress(&payload) {
+        Ok(decompressed) => decompressed,
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => return Err(e),
+        Err(e) => {
+            let brute_forced = options
+                .xor_key
+                .is_none()
+                .then(|| xor::brute_force(&payload))
+                .flatten();
+            match brute_forced {
+                Some((key, decompressed)) => {
+                    found_key = Some(key);
+                    decompressed
+                }
+                None if options.recover => {
+                    let (decompressed, at) = compression::recover(&payload).ok_or(e)?;
+                    damage = Some(at);
+                    decompressed
+                }
+                None => return Err(e),
+            }
+        }
+    };
+
+    decompress.done(payload.len());
+
+    if let Some(key) = &found_key {
+        key.apply(&mut payload);
+    }
+    let trailing = payload.split_off(payload.len() - decompressed.trailing);
+    let meta = Meta {
+        compression: Some(decompressed.kind),
+        segments: Some(decompressed.segments),
+        xor_key: found_key.clone().or_else(|| options.xor_key.clone()),
+        xor_header: Some(options.xor_header),
+        trailing: Some(trailing),
+        ..Meta::new(header)
+    };
+
+    Ok(Decoded {
+        kind: detect_payload(&decompressed.data),
+        meta,
+        data: decompressed.data,
+        found_key: found_key.is_some(),
+        trailing: decompressed.trailing,
+        damage,
+    })
+}
+
+impl Decoded {
+    pub fn compression(&self) -> Compression {
+        self.meta.compression.unwrap_or(Compression::Zlib)
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.meta.segments.as_ref().map_or(1, Vec::len)
+    }
+}
+
+/// A whole .ido file decoded to its payload.
+#[derive(Debug, Clone)]
+pub struct IdoFile {
+    pub kind: PayloadKind,
+    /// `None` for kinds stored uncompressed, whose file is the payload.
+    pub meta: Option<Meta>,
+    pub data: Vec<u8>,
+}
+
+impl IdoFile {
+    pub fn read(path: &Path, options: &DecodeOptions) -> io::Result<IdoFile> {
+        IdoFile::decode(fs::read(path)?, options)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn decode(mut bytes: Vec<u8>, options: &DecodeOptions) -> io::Result<IdoFile> {
+        let header_size = options.header_size();
+        check_length(bytes.len() as u64, header_size)?;
+
+        let mut header = bytes[..header_size].to_vec();
+        options.decode_header(&mut header);
+        if let Some(kind) = sniff_header(&header) {
+            return Ok(IdoFile {
+                kind,
+                meta: None,
+                data: bytes,
+            });
+        }
+
+        let payload = bytes.split_off(header_size);
+        let decoded = decode_payload(&header, payload, options)?;
+        Ok(IdoFile {
+            kind: decoded.kind,
+            meta: Some(decoded.meta),
+            data: decoded.data,
+        })
+    }
+
+    /// Rebuild the file bytes.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        match &self.meta {
+            Some(meta) => encode_payload(meta, &self.data),
+            None => Ok(self.data.clone()),
+        }
+    }
+}
+
+/// Compress `data` and prepend the header, as described by `meta`.
+pub fn encode_payload(meta: &Meta, data: &[u8]) -> io::Result<Vec<u8>> {
+    let compress = timings::start(Stage::Compress);
+    let mut header = meta.header.clone().unwrap_or_default();
+    let compression = meta.compression.unwrap_or(Compression::Zlib);
+
+    let mut compressed = match meta.segments.as_deref() {
+        Some(segments) if segments.len() > 1 => {
+            compression::compress_segments(data, compression, segments)?
+        }
+        _ => compression::compress(data, compression)?,
+    };
+    header::fill_sizes(
+        &header::layout()?,
+        &mut header,
+        compressed.len(),
+        data.len(),
+    );
+    compressed.extend_from_slice(meta.trailing.as_deref().unwrap_or_default());
+
+    if let Some(key) = &meta.xor_key {
+        key.apply(&mut compressed);
+        if meta.xor_header == Some(true) {
+            key.apply(&mut header);
+        }
+    }
+
+    header.extend_from_slice(&compressed);
+    compress.done(data.len());
+    Ok(header)
+}
@@ -0,0 +1,124 @@
+use crate::compression::{self, Compression};
+use crate::header;
+use crate::ido::{self, DecodeOptions};
+use crate::timings::{self, Stage};
+use crate::walk;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// One row of a directory index.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub header: String,
+    pub file_size: u64,
+    /// Size of the compressed payload; empty for uncompressed types.
+    pub compressed_size: Option<u64>,
+    pub decompressed_size: Option<u64>,
+    /// SHA-256 of the file as stored on disk.
+    pub sha256: String,
+    /// SHA-256 of the decompressed payload; empty for uncompressed types.
+    pub payload_sha256: String,
+    /// Stored checksum of each stream (`adler32:...`, `crc32:...`),
+    /// verified on decompression.
+    pub checksum: String,
+    pub error: String,
+}
+
+/// Inspect a single file. Failures are recorded in the entry rather than
+/// returned, so one bad file doesn't stop an index run.
+pub fn inspect(path: &Path, base: &Path, options: &DecodeOptions) -> Entry {
+    let mut entry = Entry {
+        path: walk::relative(path, base),
+        kind: String::new(),
+        header: String::new(),
+        file_size: 0,
+        compressed_size: None,
+        decompressed_size: None,
+        sha256: String::new(),
+        payload_sha256: String::new(),
+        checksum: String::new(),
+        error: String::new(),
+    };
+
+    timings::file(path);
+    let read = timings::start(Stage::Read);
+    let opened = match ido::open(path, options) {
+        Ok(opened) => opened,
+        Err(e) => {
+            entry.kind = "error".to_string();
+            entry.error = e.to_string();
+            return entry;
+        }
+    };
+    read.done(opened.len as usize);
+    entry.file_size = opened.len;
+    entry.sha256 = opened.sha256.clone();
+
+    if let Err(e) = ido::check_length(opened.len, options.header_size()) {
+        entry.kind = "error".to_string();
+        entry.error = e.to_string();
+        return entry;
+    }
+
+    let mut header = opened.header.clone();
+    options.decode_header(&mut header);
+    entry.header = hex::encode(&header);
+
+    if let Some(kind) = opened.stored {
+        entry.kind = kind.name().to_string();
+        return entry;
+    }
+
+    entry.compressed_size = Some(opened.payload.len() as u64);
+    let bytes = opened.bytes();
+    match ido::decode_payload(&header, opened.payload, options) {
+        Ok(decoded) => {
+            entry.kind = decoded.kind.name().to_string();
+            entry.decompressed_size = Some(decoded.data.len() as u64);
+            entry.payload_sha256 = hex::encode(Sha256::digest(&decoded.data));
+            let segments = decoded.meta.segments.as_deref().unwrap_or_default();
+            let prefix = match decoded.compression() {
+                Compression::Gzip => "crc32",
+                _ => "adler32",
+            };
+            entry.checksum = compression::checksums(decoded.compression(), &decoded.data, segments)
+                .iter()
+                .map(|sum| format!("{}:{:08x}", prefix, sum))
+                .collect::<Vec<_>>()
+                .join(" ");
+            if let Some(damage) = decoded.damage {
+                entry.error = format!(
+                    "damaged at file offset 0x{:X} ({}); decompressed size is what was recovered",
+                    options.header_size() + damage.offset,
+                    damage.error
+                );
+            }
+        }
+        Err(e) => {
+            entry.kind = "error".to_string();
+            entry.error = e.to_string();
+            if let Some(warning) =
+                header::stream_warning(&bytes, header.len(), options.xor_key.as_ref())
+            {
+                entry.error = format!("{}; {}", entry.error, warning);
+            }
+        }
+    }
+    entry
+}
+
+/// Index every .ido below `dir` as CSV.
+pub fn write<W: Write>(writer: W, dir: &Path, options: &DecodeOptions) -> io::Result<usize> {
+    let files = walk::ido_files(dir)?;
+    let mut wtr = csv::Writer::from_writer(writer);
+    for path in &files {
+        wtr.serialize(inspect(path, dir, options))?;
+    }
+    wtr.flush()?;
+    Ok(files.len())
+}
@@ -0,0 +1,176 @@
+use crate::compression::{self, Compression};
+use crate::entropy;
+use crate::header;
+use crate::ido::{self, DecodeOptions, PayloadKind};
+use encoding_rs::EUC_KR;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+/// Human-readable description of a file, as printed by `idoc info`.
+pub fn describe(path: &Path, options: &DecodeOptions) -> io::Result<String> {
+    let opened = ido::open(path, options)?;
+    ido::check_length(opened.len, options.header_size())?;
+    let mut header = opened.header.clone();
+    options.decode_header(&mut header);
+
+    let mut out = String::new();
+    let mut line = |label: &str, value: String| writeln!(out, "{:<19}{}", label, value).unwrap();
+    line("File:", path.display().to_string());
+
+    if let Some(kind) = opened.stored {
+        line("Type:", kind.description().to_string());
+        line("Header:", hex::encode(&header));
+        line("Compression:", "none (stored)".to_string());
+        line("Size:", format!("{} bytes", opened.len));
+        line("SHA-256:", opened.sha256);
+        line("Encoding:", "binary".to_string());
+        return Ok(out);
+    }
+
+    let bytes = opened.bytes();
+    let payload = opened.payload;
+    let compressed_size = payload.len();
+    let mut decoded_payload = payload.clone();
+    let decoded =
+        ido::decode_payload(&header, payload, options).map_err(
+            |e| match header::stream_warning(&bytes, header.len(), options.xor_key.as_ref()) {
+                Some(warning) => io::Error::new(e.kind(), format!("{}\n{}", e, warning)),
+                None => e,
+            },
+        )?;
+    if let Some(key) = &decoded.meta.xor_key {
+        key.apply(&mut decoded_payload);
+    }
+
+    line("Type:", decoded.kind.description().to_string());
+    line("Header:", hex::encode(&header));
+    let layout = header::layout()?;
+    if let Some(known) = header::identify(&layout, &header) {
+        line("Known header:", format!("matches {}", known.name));
+    } else if let Some(known) = header::known_for(path) {
+        let differences = header::differences(&layout, &known.header, &header);
+        let mut offsets: Vec<String> = differences
+            .iter()
+            .take(8)
+            .map(|o| format!("0x{:02X}", o))
+            .collect();
+        if differences.len() > offsets.len() {
+            offsets.push(format!("... ({} bytes)", differences.len()));
+        }
+        line(
+            "Known header:",
+            format!("differs from {} at {}", known.name, offsets.join(", ")),
+        );
+    }
+    for (name, value) in header::fields(&layout, &header) {
+        line(&format!("  {}:", name), value.to_string());
+    }
+    for (offset, what) in header::find_sizes(&header, compressed_size, decoded.data.len())
+        .into_iter()
+        .filter(|(offset, _)| !layout.fields.iter().any(|f| f.offset == *offset))
+    {
+        line(
+            &format!("  0x{:02X}:", offset),
+            format!("u32 matching the {}", what.replace('_', " ")),
+        );
+    }
+
+    let kind = decoded.compression();
+    let level = match compression::estimate_level(&decoded_payload, kind) {
+        Some((level, true)) => format!(" (level {})", level),
+        Some((level, false)) => format!(" (level ~{}, not an exact match)", level),
+        None => String::new(),
+    };
+    line("Compression:", format!("{}{}", kind.name(), level));
+    if kind == Compression::Zlib && decoded_payload.len() >= 2 {
+        let hint = ["fastest", "fast", "default", "maximum"][usize::from(decoded_payload[1] >> 6)];
+        line("Zlib header level:", hint.to_string());
+    }
+    if decoded.segment_count() > 1 {
+        line("Streams:", decoded.segment_count().to_string());
+    }
+    if let Some(key) = &decoded.meta.xor_key {
+        let found = if decoded.found_key { " (detected)" } else { "" };
+        line("XOR key:", format!("{}{}", key, found));
+    }
+    line("Compressed size:", format!("{} bytes", compressed_size));
+    line(
+        "Decompressed size:",
+        format!("{} bytes", decoded.data.len()),
+    );
+    if compressed_size > 0 {
+        line(
+            "Ratio:",
+            format!(
+                "{:.2}:1",
+                decoded.data.len() as f64 / compressed_size as f64
+            ),
+        );
+    }
+    line(
+        "Checksum:",
+        compression::check_summary(
+            kind,
+            &decoded.data,
+            decoded.meta.segments.as_deref().unwrap_or_default(),
+        ),
+    );
+    line(
+        "Payload SHA-256:",
+        hex::encode(Sha256::digest(&decoded.data)),
+    );
+    if decoded.trailing > 0 {
+        line("Trailing bytes:", decoded.trailing.to_string());
+    }
+    line("Encoding:", encoding(decoded.kind, &decoded.data));
+    if decoded.kind == PayloadKind::Unknown {
+        for hint in entropy::hints(&decoded.data) {
+            line("Hint:", hint);
+        }
+    }
+    Ok(out)
+}
+
+/// Best guess at the text encoding of a payload.
+fn encoding(kind: PayloadKind, data: &[u8]) -> String {
+    if kind != PayloadKind::Xml {
+        return "binary".to_string();
+    }
+    if data.starts_with(b"\xEF\xBB\xBF") {
+        return "UTF-8 (BOM)".to_string();
+    }
+    if data.starts_with(b"\xFF\xFE") {
+        return "UTF-16LE (BOM)".to_string();
+    }
+    if data.starts_with(b"\xFE\xFF") {
+        return "UTF-16BE (BOM)".to_string();
+    }
+
+    let declared = declared_encoding(data)
+        .map(|e| format!(", declared {}", e))
+        .unwrap_or_default();
+    let guess = if data.is_ascii() {
+        "ASCII"
+    } else if std::str::from_utf8(data).is_ok() {
+        "UTF-8"
+    } else if !EUC_KR.decode_without_bom_handling(data).1 {
+        "EUC-KR"
+    } else {
+        "unknown (not valid UTF-8 or EUC-KR)"
+    };
+    format!("{}{}", guess, declared)
+}
+
+/// The `encoding="..."` of an XML declaration.
+fn declared_encoding(data: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&data[..data.len().min(200)]);
+    let declaration = &head[head.find("<?xml")?..];
+    let declaration = &declaration[..declaration.find("?>")?];
+    let rest = &declaration[declaration.find("encoding")? + "encoding".len()..];
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &rest[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
@@ -0,0 +1,567 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use clap::ValueEnum;
+use encoding_rs::EUC_KR;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+pub mod cheader;
+pub mod diff;
+pub mod infer;
+pub mod ksy;
+pub mod schema;
+pub mod template;
+
+const SHOP_LAYOUT: &str = include_str!("layouts/shop.toml");
+const HEADER_LAYOUT: &str = include_str!("layouts/header.toml");
+
+/// Layout files parsed so far, with the modification time they had, so
+/// a long-running `idoc daemon` parses each one again only once it
+/// changes.
+static LOADED: Mutex<Option<HashMap<PathBuf, (SystemTime, Layout)>>> = Mutex::new(None);
+
+/// Whether this run reads and writes fields big-endian.
+static BIG_ENDIAN: AtomicBool = AtomicBool::new(false);
+
+/// Byte order of the integers, floats and UTF-16 text in records and
+/// headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Endian {
+    #[default]
+    Little,
+    /// As in console ports of the engine
+    Big,
+}
+
+/// Set the byte order of every field for this run; little-endian
+/// without it.
+pub fn set_endian(endian: Endian) {
+    BIG_ENDIAN.store(endian == Endian::Big, Ordering::Relaxed);
+}
+
+/// The byte order fields are read and written in.
+pub fn endian() -> Endian {
+    if BIG_ENDIAN.load(Ordering::Relaxed) {
+        Endian::Big
+    } else {
+        Endian::Little
+    }
+}
+
+/// Primitive types a layout field can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    /// Null-terminated UTF-16 string in a fixed-size buffer.
+    Utf16,
+    /// Null-terminated EUC-KR string in a fixed-size buffer.
+    Euckr,
+    /// Raw bytes, exported as hex.
+    Bytes,
+}
+
+impl FieldType {
+    /// Name as written in layout files.
+    pub fn name(self) -> &'static str {
+        match self {
+            FieldType::U8 => "u8",
+            FieldType::I8 => "i8",
+            FieldType::U16 => "u16",
+            FieldType::I16 => "i16",
+            FieldType::U32 => "u32",
+            FieldType::I32 => "i32",
+            FieldType::F32 => "f32",
+            FieldType::Utf16 => "utf16",
+            FieldType::Euckr => "euckr",
+            FieldType::Bytes => "bytes",
+        }
+    }
+
+    /// Size in bytes for fixed-width types.
+    pub fn width(self) -> Option<usize> {
+        match self {
+            FieldType::U8 | FieldType::I8 => Some(1),
+            FieldType::U16 | FieldType::I16 => Some(2),
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => Some(4),
+            FieldType::Utf16 | FieldType::Euckr | FieldType::Bytes => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Field {
+    pub name: String,
+    /// Offset from the start of the record.
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+    /// Byte length, required for string and byte fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+}
+
+impl Field {
+    pub fn size(&self) -> usize {
+        self.kind.width().or(self.length).unwrap_or(0)
+    }
+
+    pub fn end(&self) -> usize {
+        self.offset + self.size()
+    }
+
+    pub fn read(&self, record: &[u8]) -> Value {
+        match endian() {
+            Endian::Little => self.read_as::<LittleEndian>(record),
+            Endian::Big => self.read_as::<BigEndian>(record),
+        }
+    }
+
+    fn read_as<B: ByteOrder>(&self, record: &[u8]) -> Value {
+        let bytes = &record[self.offset..self.end()];
+        match self.kind {
+            FieldType::U8 => Value::Int(bytes[0].into()),
+            FieldType::I8 => Value::Int((bytes[0] as i8).into()),
+            FieldType::U16 => Value::Int(B::read_u16(bytes).into()),
+            FieldType::I16 => Value::Int(B::read_i16(bytes).into()),
+            FieldType::U32 => Value::Int(B::read_u32(bytes).into()),
+            FieldType::I32 => Value::Int(B::read_i32(bytes).into()),
+            FieldType::F32 => Value::Float(B::read_f32(bytes)),
+            FieldType::Utf16 => Value::Text(parse_utf16_string::<B>(bytes)),
+            FieldType::Euckr => {
+                let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                let (cow, _, _) = EUC_KR.decode(&bytes[..end]);
+                Value::Text(cow.trim().to_string())
+            }
+            FieldType::Bytes => Value::Bytes(bytes.to_vec()),
+        }
+    }
+
+    /// Parse a textual cell (as exported) into a value of this field's type.
+    pub fn parse(&self, text: &str) -> Result<Value, String> {
+        let text = text.trim();
+        let int = |min: i64, max: i64| {
+            let v: i64 = text
+                .parse()
+                .map_err(|_| format!("'{}' is not an integer", text))?;
+            if v < min || v > max {
+                return Err(format!("{} is out of range {}..={}", v, min, max));
+            }
+            Ok(Value::Int(v))
+        };
+        match self.kind {
+            FieldType::U8 => int(0, u8::MAX.into()),
+            FieldType::I8 => int(i8::MIN.into(), i8::MAX.into()),
+            FieldType::U16 => int(0, u16::MAX.into()),
+            FieldType::I16 => int(i16::MIN.into(), i16::MAX.into()),
+            FieldType::U32 => int(0, u32::MAX.into()),
+            FieldType::I32 => int(i32::MIN.into(), i32::MAX.into()),
+            FieldType::F32 => text
+                .parse()
+                .map(Value::Float)
+                .map_err(|_| format!("'{}' is not a number", text)),
+            FieldType::Utf16 | FieldType::Euckr => Ok(Value::Text(text.to_string())),
+            FieldType::Bytes => {
+                let bytes = hex::decode(text).map_err(|e| format!("invalid hex: {}", e))?;
+                if bytes.len() != self.size() {
+                    return Err(format!(
+                        "expected {} bytes of hex, got {}",
+                        self.size(),
+                        bytes.len()
+                    ));
+                }
+                Ok(Value::Bytes(bytes))
+            }
+        }
+    }
+
+    /// Encode a value into its slot within `record`.
+    pub fn write(&self, value: &Value, record: &mut [u8]) -> Result<(), String> {
+        match endian() {
+            Endian::Little => self.write_as::<LittleEndian>(value, record),
+            Endian::Big => self.write_as::<BigEndian>(value, record),
+        }
+    }
+
+    fn write_as<B: ByteOrder>(&self, value: &Value, record: &mut [u8]) -> Result<(), String> {
+        let size = self.size();
+        let slot = &mut record[self.offset..self.offset + size];
+        match (self.kind, value) {
+            (FieldType::U8 | FieldType::I8, Value::Int(v)) => slot[0] = *v as u8,
+            (FieldType::U16 | FieldType::I16, Value::Int(v)) => B::write_u16(slot, *v as u16),
+            (FieldType::U32 | FieldType::I32, Value::Int(v)) => B::write_u32(slot, *v as u32),
+            (FieldType::F32, Value::Float(v)) => B::write_f32(slot, *v),
+            (FieldType::Utf16, Value::Text(v)) => {
+                let units: Vec<u16> = v.encode_utf16().collect();
+                if units.len() * 2 > size {
+                    return Err(format!(
+                        "'{}' needs {} bytes but the field holds {}",
+                        v,
+                        units.len() * 2,
+                        size
+                    ));
+                }
+                slot.fill(0);
+                for (chunk, unit) in slot.chunks_exact_mut(2).zip(units) {
+                    B::write_u16(chunk, unit);
+                }
+            }
+            (FieldType::Euckr, Value::Text(v)) => {
+                let (encoded, _, unmappable) = EUC_KR.encode(v);
+                if unmappable {
+                    return Err(format!("'{}' cannot be encoded as EUC-KR", v));
+                }
+                if encoded.len() > size {
+                    return Err(format!(
+                        "'{}' needs {} bytes but the field holds {}",
+                        v,
+                        encoded.len(),
+                        size
+                    ));
+                }
+                slot.fill(0);
+                slot[..encoded.len()].copy_from_slice(&encoded);
+            }
+            (FieldType::Bytes, Value::Bytes(v)) if v.len() == size => slot.copy_from_slice(v),
+            (kind, value) => return Err(format!("cannot store {:?} in a {:?} field", value, kind)),
+        }
+        Ok(())
+    }
+}
+
+/// Fixed-size record structure of a binary database.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Layout {
+    pub name: String,
+    pub record_size: usize,
+    /// Fields that together identify a record, used to match patch rows.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key: Vec<String>,
+    pub fields: Vec<Field>,
+}
+
+/// A field or an unmapped byte range, in record order.
+#[derive(Debug, Clone, Copy)]
+pub enum Region<'a> {
+    Field(&'a Field),
+    Gap { offset: usize, size: usize },
+}
+
+impl Region<'_> {
+    /// Name used for gaps in exported definitions, e.g. `unknown_0x08`.
+    pub fn gap_name(offset: usize) -> String {
+        format!("unknown_0x{:02x}", offset)
+    }
+}
+
+impl Layout {
+    pub fn builtin(name: &str) -> Option<Layout> {
+        static SHOP: OnceLock<Layout> = OnceLock::new();
+        static HEADER: OnceLock<Layout> = OnceLock::new();
+        match name {
+            "shop" => Some(
+                SHOP.get_or_init(|| {
+                    Layout::parse(SHOP_LAYOUT).expect("built-in shop layout is valid")
+                })
+                .clone(),
+            ),
+            "header" => Some(
+                HEADER
+                    .get_or_init(|| {
+                        Layout::parse(HEADER_LAYOUT).expect("built-in header layout is valid")
+                    })
+                    .clone(),
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn builtin_names() -> &'static [&'static str] {
+        &["shop", "header"]
+    }
+
+    /// Look up a built-in layout by name, or load a layout file.
+    pub fn resolve(name_or_path: &str) -> io::Result<Layout> {
+        if let Some(layout) = Layout::builtin(name_or_path) {
+            return Ok(layout);
+        }
+        let path = Path::new(name_or_path);
+        if path.exists() {
+            return Layout::load(path);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Unknown layout '{}' (built-in: {}; or pass a path to a .toml layout file)",
+                name_or_path,
+                Layout::builtin_names().join(", ")
+            ),
+        ))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Layout> {
+        let modified = fs::metadata(path)?.modified().ok();
+        let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+        let loaded = loaded.get_or_insert_with(HashMap::new);
+        if let Some(modified) = modified
+            && let Some((parsed_at, layout)) = loaded.get(path)
+            && *parsed_at == modified
+        {
+            return Ok(layout.clone());
+        }
+        let layout = Layout::parse(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Invalid layout file {}: {}", path.display(), e),
+            )
+        })?;
+        if let Some(modified) = modified {
+            loaded.insert(path.to_path_buf(), (modified, layout.clone()));
+        }
+        Ok(layout)
+    }
+
+    pub fn parse(content: &str) -> io::Result<Layout> {
+        let mut layout: Layout = toml::from_str(content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        layout.fields.sort_by_key(|f| f.offset);
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+
+        if self.record_size == 0 {
+            return invalid("record_size must be greater than zero".to_string());
+        }
+        let mut previous: Option<&Field> = None;
+        for field in &self.fields {
+            if field.kind.width().is_none() && field.length.unwrap_or(0) == 0 {
+                return invalid(format!("field '{}' needs a non-zero length", field.name));
+            }
+            if field.end() > self.record_size {
+                return invalid(format!(
+                    "field '{}' ends at 0x{:X}, past the record size 0x{:X}",
+                    field.name,
+                    field.end(),
+                    self.record_size
+                ));
+            }
+            if let Some(prev) = previous
+                && prev.end() > field.offset
+            {
+                return invalid(format!(
+                    "fields '{}' and '{}' overlap",
+                    prev.name, field.name
+                ));
+            }
+            previous = Some(field);
+        }
+        if let Some(name) = self.key.iter().find(|k| self.field(k).is_none()) {
+            return invalid(format!("key field '{}' is not defined", name));
+        }
+        Ok(())
+    }
+
+    /// This layout with each gap between fields as a `bytes` field named
+    /// after its offset (`unknown_0x..`), so every byte of a record is
+    /// exported, and compiled back, even where nothing is known about it.
+    pub fn with_gaps(&self) -> Layout {
+        let fields = self
+            .regions()
+            .into_iter()
+            .map(|region| match region {
+                Region::Field(field) => field.clone(),
+                Region::Gap { offset, size } => Field {
+                    name: Region::gap_name(offset),
+                    offset,
+                    kind: FieldType::Bytes,
+                    length: Some(size),
+                },
+            })
+            .collect();
+        Layout {
+            fields,
+            ..self.clone()
+        }
+    }
+
+    /// All fields plus the gaps between them, covering the whole record.
+    pub fn regions(&self) -> Vec<Region<'_>> {
+        let mut regions = Vec::new();
+        let mut offset = 0;
+        for field in &self.fields {
+            if field.offset > offset {
+                regions.push(Region::Gap {
+                    offset,
+                    size: field.offset - offset,
+                });
+            }
+            regions.push(Region::Field(field));
+            offset = field.end();
+        }
+        if offset < self.record_size {
+            regions.push(Region::Gap {
+                offset,
+                size: self.record_size - offset,
+            });
+        }
+        regions
+    }
+
+    /// Decode one record. `record` must be at least `record_size` bytes.
+    pub fn read_record(&self, record: &[u8]) -> Vec<Value> {
+        self.fields.iter().map(|f| f.read(record)).collect()
+    }
+
+    /// Fail unless `len` bytes are a whole number of records.
+    pub fn check_whole(&self, len: usize) -> io::Result<()> {
+        if len.is_multiple_of(self.record_size) {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} bytes is not a whole number of 0x{:X}-byte records",
+                len, self.record_size
+            ),
+        ))
+    }
+
+    /// Fail unless every string field of `record`, the `index`th, reads to
+    /// text that writes back to the same bytes. Text stops at the first
+    /// NUL and is trimmed, and characters that don't decode are replaced,
+    /// so an export of such a field would not compile back to the record.
+    pub fn check_text(&self, record: &[u8], index: u64) -> io::Result<()> {
+        let mut rebuilt = record.to_vec();
+        for field in &self.fields {
+            if !matches!(field.kind, FieldType::Utf16 | FieldType::Euckr) {
+                continue;
+            }
+            let slot = field.offset..field.end();
+            let kept = field.write(&field.read(record), &mut rebuilt).is_ok()
+                && rebuilt[slot.clone()] == record[slot];
+            if !kept {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "record {}, field '{}': its bytes don't survive as text (bytes after the terminator, surrounding spaces or characters that don't decode); declare it as bytes in a layout to export it as hex",
+                        index, field.name
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// [`Layout::check_text`] for every whole record in `data`.
+    pub fn check_texts(&self, data: &[u8]) -> io::Result<()> {
+        for (record, index) in data.chunks_exact(self.record_size).zip(0..) {
+            self.check_text(record, index)?;
+        }
+        Ok(())
+    }
+
+    /// Decode every whole record in `data`; a trailing partial record is ignored.
+    pub fn read_records(&self, data: &[u8]) -> Vec<Vec<Value>> {
+        data.chunks_exact(self.record_size)
+            .map(|record| self.read_record(record))
+            .collect()
+    }
+
+    /// Encode one record; bytes not covered by a field are left untouched.
+    pub fn write_record(&self, values: &[Value], record: &mut [u8]) -> Result<(), String> {
+        for (field, value) in self.fields.iter().zip(values) {
+            field
+                .write(value, record)
+                .map_err(|e| format!("field '{}': {}", field.name, e))?;
+        }
+        Ok(())
+    }
+
+    /// Encode records back to back. Bytes outside the fields are written
+    /// as zero; [`Layout::with_gaps`] has none.
+    pub fn write_records(&self, records: &[Vec<Value>]) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; records.len() * self.record_size];
+        for (i, (record, chunk)) in records
+            .iter()
+            .zip(data.chunks_exact_mut(self.record_size))
+            .enumerate()
+        {
+            self.write_record(record, chunk).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("record {}: {}", i, e))
+            })?;
+        }
+        Ok(data)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn field_names(&self) -> Vec<&str> {
+        self.fields.iter().map(|f| f.name.as_str()).collect()
+    }
+}
+
+/// A decoded field value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f32),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Text(v) => write!(f, "{}", v),
+            Value::Bytes(v) => write!(f, "{}", hex::encode(v)),
+        }
+    }
+}
+
+/// Lowercase snake-case identifier starting with a letter, usable as a
+/// field or type name in every exported definition language.
+pub fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_lowercase()) {
+        id.insert_str(0, "f_");
+    }
+    id
+}
+
+fn parse_utf16_string<B: ByteOrder>(buffer: &[u8]) -> String {
+    let u16_vec: Vec<u16> = buffer
+        .chunks_exact(2)
+        .map(B::read_u16)
+        .take_while(|&c| c != 0) // Stop at null terminator
+        .collect();
+
+    String::from_utf16_lossy(&u16_vec).trim().to_string()
+}
@@ -0,0 +1,55 @@
+use super::template::members;
+use super::{FieldType, Layout, identifier};
+use std::fmt::Write;
+
+/// Render a C header with a packed struct matching one record.
+///
+/// Strings are left as fixed-size code unit arrays (`uint16_t` for
+/// UTF-16LE, `char` for EUC-KR) exactly as stored on disk.
+pub fn export(layout: &Layout) -> String {
+    let id = identifier(&layout.name);
+    let guard = format!("IDOC_{}_H", id.to_ascii_uppercase());
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "/* {} database record (generated by idoc) */",
+        layout.name
+    )
+    .unwrap();
+    writeln!(out, "#ifndef {}", guard).unwrap();
+    writeln!(out, "#define {}", guard).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#include <stdint.h>").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#pragma pack(push, 1)").unwrap();
+    writeln!(out, "typedef struct {} {{", id).unwrap();
+    out.push_str(&members(layout, |kind| match kind {
+        FieldType::U8 | FieldType::Bytes => "uint8_t",
+        FieldType::I8 => "int8_t",
+        FieldType::U16 | FieldType::Utf16 => "uint16_t",
+        FieldType::I16 => "int16_t",
+        FieldType::U32 => "uint32_t",
+        FieldType::I32 => "int32_t",
+        FieldType::F32 => "float",
+        FieldType::Euckr => "char",
+    }));
+    writeln!(out, "}} {}_t;", id).unwrap();
+    writeln!(out, "#pragma pack(pop)").unwrap();
+    writeln!(out).unwrap();
+
+    let assertion = format!(
+        "(sizeof({id}_t) == {size}, \"{id}_t must be {size} bytes\");",
+        id = id,
+        size = layout.record_size
+    );
+    writeln!(out, "#ifdef __cplusplus").unwrap();
+    writeln!(out, "static_assert{}", assertion).unwrap();
+    writeln!(out, "#else").unwrap();
+    writeln!(out, "_Static_assert{}", assertion).unwrap();
+    writeln!(out, "#endif").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#endif /* {} */", guard).unwrap();
+
+    out
+}
@@ -0,0 +1,317 @@
+//! Where fields moved between two client versions of a database, found
+//! by matching field bytes in the same records of sample files from each.
+
+use super::{Field, Layout, infer};
+use crate::ido::{DecodeOptions, IdoFile, PayloadKind};
+use crate::walk;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Share of records whose bytes must match for a field to count as found.
+const MIN_MATCH: f64 = 0.9;
+
+/// A database file and the same file from the other version.
+struct Pair {
+    name: String,
+    old: Vec<u8>,
+    new: Vec<u8>,
+}
+
+/// Where an old field ended up in the new records.
+enum Placement {
+    Found { offset: usize, constant: bool },
+    Missing,
+}
+
+/// The report printed by `idoc layout diff`. `old` and `new` are either
+/// two files or two directories whose files are paired by relative path.
+/// With `layout`, its fields describe the old records; otherwise they
+/// are inferred from the old samples.
+pub fn report(old: &Path, new: &Path, layout: Option<&Layout>) -> io::Result<String> {
+    let mut out = String::new();
+    let pairs = pairs(old, new, &mut out)?;
+    if pairs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "no database files in both {} and {}",
+                old.display(),
+                new.display()
+            ),
+        ));
+    }
+
+    let old_stride = match layout {
+        Some(layout) => layout.record_size,
+        None => stride(pairs.iter().map(|p| (&p.name, &p.old[..])), "old", &mut out)?,
+    };
+    let new_stride = stride(pairs.iter().map(|p| (&p.name, &p.new[..])), "new", &mut out)?;
+    let old_data: Vec<u8> = pairs
+        .iter()
+        .flat_map(|p| p.old.chunks_exact(old_stride))
+        .flatten()
+        .copied()
+        .collect();
+    let new_data: Vec<u8> = pairs
+        .iter()
+        .flat_map(|p| p.new.chunks_exact(new_stride))
+        .flatten()
+        .copied()
+        .collect();
+    // Records are compared in file order, so only as many as both
+    // versions of each file have.
+    let compared: Vec<(&[u8], &[u8])> = pairs
+        .iter()
+        .flat_map(|p| {
+            p.old
+                .chunks_exact(old_stride)
+                .zip(p.new.chunks_exact(new_stride))
+        })
+        .collect();
+    if compared.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no complete records to compare",
+        ));
+    }
+
+    writeln!(out, "Files compared: {}", pairs.len()).unwrap();
+    writeln!(
+        out,
+        "Old: {} records of 0x{:X} bytes",
+        old_data.len() / old_stride,
+        old_stride
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "New: {} records of 0x{:X} bytes",
+        new_data.len() / new_stride,
+        new_stride
+    )
+    .unwrap();
+    if old_stride == new_stride {
+        writeln!(out, "Record size: 0x{:X} (unchanged)", old_stride).unwrap();
+    } else {
+        writeln!(
+            out,
+            "Record size: 0x{:X} -> 0x{:X} ({:+})",
+            old_stride,
+            new_stride,
+            new_stride as i64 - old_stride as i64
+        )
+        .unwrap();
+    }
+
+    let fields: Vec<Field> = match layout {
+        Some(layout) => layout.fields.clone(),
+        None => infer::infer(&old_data, old_stride)
+            .into_iter()
+            .map(|guess| guess.field)
+            .collect(),
+    };
+    let width = fields.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    writeln!(out, "Fields (compared across {} records):", compared.len()).unwrap();
+
+    // Fields tend to move in blocks, so the shift of the last field found
+    // says where to look first for the next one.
+    let mut shift = 0i64;
+    let mut covered = vec![false; new_stride];
+    let mut moved = 0;
+    let mut missing = 0;
+    for field in &fields {
+        let expected = (field.offset as i64 + shift).max(0) as usize;
+        match place(field, &compared, new_stride, expected) {
+            Placement::Found { offset, constant } => {
+                covered[offset..offset + field.size()].fill(true);
+                shift = offset as i64 - field.offset as i64;
+                let change = if shift == 0 {
+                    "unchanged".to_string()
+                } else {
+                    moved += 1;
+                    format!("moved {:+}", shift)
+                };
+                let constant = if constant {
+                    " (same value in every record, position assumed)"
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "  {:<width$}  0x{:03X} -> 0x{:03X}  {}{}",
+                    field.name, field.offset, offset, change, constant
+                )
+                .unwrap();
+            }
+            Placement::Missing => {
+                missing += 1;
+                writeln!(
+                    out,
+                    "  {:<width$}  0x{:03X}  not found (removed, retyped or changed values)",
+                    field.name, field.offset
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    let unmatched = unmatched(&covered, &compared);
+    if !unmatched.is_empty() {
+        writeln!(out, "Data in new records no old field accounts for:").unwrap();
+        for (start, end) in unmatched {
+            writeln!(out, "  0x{:03X}-0x{:03X}", start, end).unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "{} fields: {} moved, {} not found",
+        fields.len(),
+        moved,
+        missing
+    )
+    .unwrap();
+    Ok(out)
+}
+
+/// Sample files present on both sides, decoded. Files that are not
+/// databases, or do not decode, are noted in `out` and left out.
+fn pairs(old: &Path, new: &Path, out: &mut String) -> io::Result<Vec<Pair>> {
+    let names: Vec<(String, PathBuf, PathBuf)> = match (old.is_dir(), new.is_dir()) {
+        (true, true) => walk::ido_files(old)?
+            .into_iter()
+            .map(|path| walk::relative(&path, old))
+            .filter(|name| new.join(name).is_file())
+            .map(|name| (name.clone(), old.join(&name), new.join(&name)))
+            .collect(),
+        (false, false) => vec![(
+            old.display().to_string(),
+            old.to_path_buf(),
+            new.to_path_buf(),
+        )],
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--old and --new must both be files or both be directories",
+            ));
+        }
+    };
+
+    let options = DecodeOptions::default();
+    let mut pairs = Vec::new();
+    for (name, old, new) in names {
+        let decoded =
+            IdoFile::read(&old, &options).and_then(|old| Ok((old, IdoFile::read(&new, &options)?)));
+        match decoded {
+            Ok((old, new)) if is_records(old.kind) && is_records(new.kind) => pairs.push(Pair {
+                name,
+                old: old.data,
+                new: new.data,
+            }),
+            Ok(_) => writeln!(out, "Skipping {}: not a database", name).unwrap(),
+            Err(e) => writeln!(out, "Skipping {}: {}", name, e).unwrap(),
+        }
+    }
+    Ok(pairs)
+}
+
+fn is_records(kind: PayloadKind) -> bool {
+    matches!(kind, PayloadKind::ShopDb | PayloadKind::Unknown)
+}
+
+/// The record size most samples repeat at. Samples with another stride
+/// are noted in `out`.
+fn stride<'a>(
+    samples: impl Iterator<Item = (&'a String, &'a [u8])>,
+    side: &str,
+    out: &mut String,
+) -> io::Result<usize> {
+    let strides: Vec<(&String, Option<usize>)> = samples
+        .map(|(name, data)| (name, infer::detect_stride(data)))
+        .collect();
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    for stride in strides.iter().filter_map(|&(_, stride)| stride) {
+        match counts.iter_mut().find(|(s, _)| *s == stride) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((stride, 1)),
+        }
+    }
+    let Some(&(best, _)) = counts.iter().max_by_key(|&&(_, count)| count) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no repeating record size in the {} samples", side),
+        ));
+    };
+    for (name, stride) in &strides {
+        if *stride != Some(best) {
+            let found = match stride {
+                Some(stride) => format!("0x{:X}", stride),
+                None => "none".to_string(),
+            };
+            writeln!(
+                out,
+                "Note: {} {} repeats at {}, not 0x{:X}",
+                side, name, found, best
+            )
+            .unwrap();
+        }
+    }
+    Ok(best)
+}
+
+/// The new offset whose bytes match `field` in the most records, nearest
+/// `expected` among equally good ones.
+fn place(
+    field: &Field,
+    compared: &[(&[u8], &[u8])],
+    new_stride: usize,
+    expected: usize,
+) -> Placement {
+    let size = field.size();
+    if field.end() > compared[0].0.len() || size > new_stride {
+        return Placement::Missing;
+    }
+    let old = |record: &[u8]| record[field.offset..field.end()].to_vec();
+    let constant = compared.windows(2).all(|w| old(w[0].0) == old(w[1].0));
+
+    let mut best: Option<(usize, usize)> = None;
+    for offset in 0..=new_stride - size {
+        let hits = compared
+            .iter()
+            .filter(|(o, n)| o[field.offset..field.end()] == n[offset..offset + size])
+            .count();
+        let better = match best {
+            None => true,
+            Some((best_offset, best_hits)) => {
+                hits > best_hits
+                    || (hits == best_hits
+                        && offset.abs_diff(expected) < best_offset.abs_diff(expected))
+            }
+        };
+        if better {
+            best = Some((offset, hits));
+        }
+    }
+    match best {
+        Some((offset, hits)) if hits as f64 >= MIN_MATCH * compared.len() as f64 => {
+            Placement::Found { offset, constant }
+        }
+        _ => Placement::Missing,
+    }
+}
+
+/// Byte ranges of the new records outside every placed field that are
+/// non-zero in some record.
+fn unmatched(covered: &[bool], compared: &[(&[u8], &[u8])]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (offset, _) in covered.iter().enumerate().filter(|&(_, &c)| !c) {
+        if compared.iter().all(|(_, new)| new[offset] == 0) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((_, end)) if *end == offset => *end += 1,
+            _ => ranges.push((offset, offset + 1)),
+        }
+    }
+    ranges
+}
@@ -0,0 +1,286 @@
+//! Guessing a draft layout for an unknown database from column-wise byte
+//! statistics across its records.
+
+use super::{Endian, Field, FieldType};
+use std::fmt::Write;
+
+/// Smallest and largest record size considered when detecting the stride.
+const MIN_STRIDE: usize = 4;
+const MAX_STRIDE: usize = 0x4000;
+/// Share of byte pairs a stride must repeat to count as records; random
+/// data repeats about 1 in 256.
+const MIN_REPETITION: f64 = 0.25;
+
+/// Record size with the strongest repetition between consecutive
+/// records, among sizes that divide the data evenly into at least two
+/// records. Multiples of the true stride score as well as it does, so the
+/// smallest size scoring close to the best wins.
+pub fn detect_stride(data: &[u8]) -> Option<usize> {
+    let scores: Vec<(usize, f64)> = (MIN_STRIDE..=MAX_STRIDE.min(data.len() / 2))
+        .filter(|&stride| data.len().is_multiple_of(stride))
+        .map(|stride| {
+            // Zero padding repeats at every stride, so only pairs with
+            // data in them count.
+            let (same, pairs) = data
+                .iter()
+                .zip(&data[stride..])
+                .filter(|&(&a, &b)| a != 0 || b != 0)
+                .fold((0, 0), |(same, pairs), (a, b)| {
+                    (same + usize::from(a == b), pairs + 1)
+                });
+            (stride, same as f64 / pairs.max(1) as f64)
+        })
+        .collect();
+    let best = scores.iter().map(|&(_, score)| score).fold(0.0, f64::max);
+    if best < MIN_REPETITION {
+        return None;
+    }
+    scores
+        .into_iter()
+        .find(|&(_, score)| score >= best * 0.95)
+        .map(|(stride, _)| stride)
+}
+
+/// A guessed field with a note on what it was guessed from.
+pub struct Guess {
+    pub field: Field,
+    pub note: String,
+}
+
+/// Guess fields for records of `record_size` bytes. Columns that are zero
+/// in every record are left out; they become gaps in the draft.
+pub fn infer(data: &[u8], record_size: usize) -> Vec<Guess> {
+    let records: Vec<&[u8]> = data.chunks_exact(record_size).collect();
+    let zero = |offset: usize| records.iter().all(|r| r[offset] == 0);
+
+    let mut guesses = Vec::new();
+    let mut offset = 0;
+    while offset < record_size {
+        if zero(offset) {
+            offset += 1;
+            continue;
+        }
+        let guess = utf16(&records, offset)
+            .or_else(|| euckr(&records, offset))
+            .unwrap_or_else(|| number(&records, offset));
+        // String buffers take the zero padding after the longest value.
+        let mut end = guess.end;
+        if guess.kind.width().is_none() {
+            while end < record_size && zero(end) {
+                end += 1;
+            }
+            if guess.kind == FieldType::Utf16 && (end - offset) % 2 == 1 {
+                end -= 1;
+            }
+        }
+        let length = guess.kind.width().is_none().then_some(end - offset);
+        let prefix = if length.is_some() { "text" } else { "field" };
+        guesses.push(Guess {
+            field: Field {
+                name: format!("{}_0x{:02x}", prefix, offset),
+                offset,
+                kind: guess.kind,
+                length,
+            },
+            note: guess.note,
+        });
+        offset = end;
+    }
+    guesses
+}
+
+struct Candidate {
+    kind: FieldType,
+    end: usize,
+    note: String,
+}
+
+/// Whether a UTF-16 code unit is likely part of text: printable ASCII,
+/// Hangul or CJK.
+fn is_text_unit(unit: u16) -> bool {
+    matches!(unit, 0x20..=0x7E | 0x3130..=0x318F | 0x4E00..=0x9FFF | 0xAC00..=0xD7A3)
+}
+
+/// Null-terminated runs of text starting at `offset`, one per record:
+/// `Some(length)` in units (0 for an empty string), or `None` for
+/// anything else.
+fn runs(
+    records: &[&[u8]],
+    offset: usize,
+    unit: usize,
+    text: impl Fn(&[u8]) -> bool,
+) -> Vec<Option<usize>> {
+    records
+        .iter()
+        .map(|record| {
+            for (length, chunk) in record[offset..].chunks_exact(unit).enumerate() {
+                if chunk.iter().all(|&b| b == 0) {
+                    return Some(length);
+                }
+                if !text(chunk) {
+                    return None;
+                }
+            }
+            // Running to the end of the record without a terminator.
+            None
+        })
+        .collect()
+}
+
+/// A string field if every record holds (possibly empty) text here and
+/// at least half hold `min_length` units or more.
+fn string(
+    records: &[&[u8]],
+    offset: usize,
+    unit: usize,
+    min_length: usize,
+    text: impl Fn(&[u8]) -> bool,
+) -> Option<usize> {
+    let runs: Option<Vec<usize>> = runs(records, offset, unit, text).into_iter().collect();
+    let runs = runs?;
+    let long = runs.iter().filter(|&&l| l >= min_length).count();
+    if long * 2 < records.len() {
+        return None;
+    }
+    let longest = runs.into_iter().max()?;
+    Some(offset + (longest + 1) * unit)
+}
+
+fn utf16(records: &[&[u8]], offset: usize) -> Option<Candidate> {
+    if !offset.is_multiple_of(2) {
+        return None;
+    }
+    let end = string(records, offset, 2, 2, |unit| {
+        let unit = [unit[0], unit[1]];
+        is_text_unit(match super::endian() {
+            Endian::Little => u16::from_le_bytes(unit),
+            Endian::Big => u16::from_be_bytes(unit),
+        })
+    })?;
+    Some(Candidate {
+        kind: FieldType::Utf16,
+        end,
+        note: format!("UTF-16 text, up to {} units", (end - offset) / 2 - 1),
+    })
+}
+
+fn euckr(records: &[&[u8]], offset: usize) -> Option<Candidate> {
+    let end = string(
+        records,
+        offset,
+        1,
+        3,
+        |b| matches!(b[0], 0x20..=0x7E | 0xA1..=0xFE),
+    )?;
+    Some(Candidate {
+        kind: FieldType::Euckr,
+        end,
+        note: format!("EUC-KR/ASCII text, up to {} bytes", end - offset - 1),
+    })
+}
+
+/// An integer or float at `offset`, as wide as the alignment and the
+/// bytes that follow allow.
+fn number(records: &[&[u8]], offset: usize) -> Candidate {
+    let size = records[0].len();
+    let read = |record: &[u8], width: usize| -> u64 {
+        record[offset..offset + width]
+            .iter()
+            .rev()
+            .fold(0, |value, &b| value << 8 | u64::from(b))
+    };
+
+    // A 32-bit word whose upper half is zero or the sign extension of the
+    // lower half is one field; anything else in the upper half is a
+    // second 16-bit field.
+    let word = offset.is_multiple_of(4)
+        && offset + 4 <= size
+        && records.iter().all(|r| {
+            let value = read(r, 4) as u32;
+            let upper = value >> 16;
+            upper == 0 || (upper == 0xFFFF && value & 0x8000 != 0) || is_float(value)
+        });
+    let half = offset.is_multiple_of(2) && offset + 2 <= size;
+
+    let width = if word {
+        4
+    } else if half {
+        2
+    } else {
+        1
+    };
+    let values: Vec<u64> = records.iter().map(|r| read(r, width)).collect();
+    let bits = width * 8;
+    let negative = values.iter().any(|&v| v >> (bits - 1) & 1 == 1);
+    let signed = |v: u64| -> i64 { ((v << (64 - bits)) as i64) >> (64 - bits) };
+    let shown: Vec<i64> = values
+        .iter()
+        .map(|&v| if negative { signed(v) } else { v as i64 })
+        .collect();
+    let min = shown.iter().min().copied().unwrap_or(0);
+    let max = shown.iter().max().copied().unwrap_or(0);
+    let mut distinct = shown.clone();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let range = format!("{}..={}, {} distinct", min, max, distinct.len());
+
+    if width == 4 && values.iter().all(|&v| v == 0 || is_float(v as u32)) {
+        let floats: Vec<f32> = values.iter().map(|&v| f32::from_bits(v as u32)).collect();
+        let min = floats.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = floats.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        return Candidate {
+            kind: FieldType::F32,
+            end: offset + 4,
+            note: format!("{}..={} as floats, {} distinct", min, max, distinct.len()),
+        };
+    }
+    // Small non-negative values with zeros above them read as a byte, as
+    // flags in the shop layout do.
+    let (kind, width) = match (width, negative) {
+        (4, false) if max <= 0xFF => (FieldType::U8, 1),
+        (4, true) => (FieldType::I32, 4),
+        (4, false) => (FieldType::U32, 4),
+        (2, true) => (FieldType::I16, 2),
+        (2, false) => (FieldType::U16, 2),
+        (_, true) => (FieldType::I8, 1),
+        (_, false) => (FieldType::U8, 1),
+    };
+    Candidate {
+        kind,
+        end: offset + width,
+        note: range,
+    }
+}
+
+/// Bits that read as a float in the range game data uses, and would be
+/// an implausibly large integer.
+fn is_float(bits: u32) -> bool {
+    let value = f32::from_bits(bits).abs();
+    bits & 0x7FFF_FFFF > 0x00FF_FFFF && value.is_finite() && (1e-4..=1e7).contains(&value)
+}
+
+/// A layout file for the guesses, each field preceded by its note.
+pub fn draft(name: &str, record_size: usize, count: usize, guesses: &[Guess]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Draft layout inferred by idoc from {} records; check every field.",
+        count
+    )
+    .unwrap();
+    writeln!(out, "name = \"{}\"", name).unwrap();
+    writeln!(out, "record_size = 0x{:X}", record_size).unwrap();
+    for guess in guesses {
+        let field = &guess.field;
+        writeln!(out).unwrap();
+        writeln!(out, "# {}", guess.note).unwrap();
+        writeln!(out, "[[fields]]").unwrap();
+        writeln!(out, "name = \"{}\"", field.name).unwrap();
+        writeln!(out, "offset = 0x{:02X}", field.offset).unwrap();
+        writeln!(out, "type = \"{}\"", field.kind.name()).unwrap();
+        if let Some(length) = field.length {
+            writeln!(out, "length = {}", length).unwrap();
+        }
+    }
+    out
+}
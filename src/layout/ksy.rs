@@ -0,0 +1,72 @@
+use super::{Endian, FieldType, Layout, Region, identifier};
+use std::fmt::Write;
+
+/// Render a Kaitai Struct definition for a layout.
+///
+/// The file is a sequence of records until end of stream; unmapped ranges
+/// become opaque `unknown_0x..` byte fields so offsets stay correct.
+pub fn export(layout: &Layout) -> String {
+    let id = identifier(&layout.name);
+    let mut out = String::new();
+
+    writeln!(out, "meta:").unwrap();
+    writeln!(out, "  id: {}_db", id).unwrap();
+    writeln!(out, "  title: {} database (generated by idoc)", layout.name).unwrap();
+    let endian = match super::endian() {
+        Endian::Little => "le",
+        Endian::Big => "be",
+    };
+    writeln!(out, "  endian: {}", endian).unwrap();
+    writeln!(out, "seq:").unwrap();
+    writeln!(out, "  - id: records").unwrap();
+    writeln!(out, "    type: {}", id).unwrap();
+    writeln!(out, "    repeat: eos").unwrap();
+    writeln!(out, "types:").unwrap();
+    writeln!(out, "  {}:", id).unwrap();
+    writeln!(out, "    doc: {} bytes per record", layout.record_size).unwrap();
+    writeln!(out, "    seq:").unwrap();
+
+    for region in layout.regions() {
+        match region {
+            Region::Field(field) => {
+                writeln!(out, "      - id: {}", identifier(&field.name)).unwrap();
+                match field.kind {
+                    FieldType::Utf16 | FieldType::Euckr => {
+                        let encoding = if field.kind == FieldType::Utf16 {
+                            "UTF-16LE"
+                        } else {
+                            "EUC-KR"
+                        };
+                        writeln!(out, "        type: str").unwrap();
+                        writeln!(out, "        size: {}", field.size()).unwrap();
+                        writeln!(out, "        encoding: {}", encoding).unwrap();
+                    }
+                    FieldType::Bytes => {
+                        writeln!(out, "        size: {}", field.size()).unwrap();
+                    }
+                    kind => writeln!(out, "        type: {}", primitive(kind)).unwrap(),
+                }
+                writeln!(out, "        doc: offset 0x{:02X}", field.offset).unwrap();
+            }
+            Region::Gap { offset, size } => {
+                writeln!(out, "      - id: {}", Region::gap_name(offset)).unwrap();
+                writeln!(out, "        size: {}", size).unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+fn primitive(kind: FieldType) -> &'static str {
+    match kind {
+        FieldType::U8 => "u1",
+        FieldType::I8 => "s1",
+        FieldType::U16 => "u2",
+        FieldType::I16 => "s2",
+        FieldType::U32 => "u4",
+        FieldType::I32 => "s4",
+        FieldType::F32 => "f4",
+        FieldType::Utf16 | FieldType::Euckr | FieldType::Bytes => unreachable!("not a primitive"),
+    }
+}
@@ -0,0 +1,49 @@
+use super::{FieldType, Layout};
+use serde_json::{Map, Value, json};
+
+/// JSON Schema (draft 2020-12) for a database exported with `--format json`:
+/// an array of objects with one property per layout field.
+pub fn export(layout: &Layout) -> Value {
+    let mut properties = Map::new();
+    for field in &layout.fields {
+        let mut schema = match field.kind {
+            FieldType::U8 => integer(0, u8::MAX.into()),
+            FieldType::I8 => integer(i8::MIN.into(), i8::MAX.into()),
+            FieldType::U16 => integer(0, u16::MAX.into()),
+            FieldType::I16 => integer(i16::MIN.into(), i16::MAX.into()),
+            FieldType::U32 => integer(0, u32::MAX.into()),
+            FieldType::I32 => integer(i32::MIN.into(), i32::MAX.into()),
+            FieldType::F32 => json!({ "type": "number" }),
+            // A code point takes at least one UTF-16 unit / EUC-KR byte.
+            FieldType::Utf16 => json!({ "type": "string", "maxLength": field.size() / 2 }),
+            FieldType::Euckr => json!({ "type": "string", "maxLength": field.size() }),
+            FieldType::Bytes => json!({
+                "type": "string",
+                "pattern": format!("^[0-9a-fA-F]{{{}}}$", field.size() * 2),
+            }),
+        };
+        schema["description"] = json!(format!(
+            "{} at offset 0x{:02X}",
+            field.kind.name(),
+            field.offset
+        ));
+        properties.insert(field.name.clone(), schema);
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("{} records", layout.name),
+        "description": format!("Exported by idoc; {} bytes per record", layout.record_size),
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": properties,
+            "required": layout.field_names(),
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn integer(min: i64, max: i64) -> Value {
+    json!({ "type": "integer", "minimum": min, "maximum": max })
+}
@@ -0,0 +1,133 @@
+use super::{Endian, FieldType, Layout, Region, identifier};
+use clap::ValueEnum;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Hex editor binary template dialects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TemplateFormat {
+    /// ImHex pattern language (.hexpat)
+    Hexpat,
+    /// 010 Editor binary template (.bt)
+    Bt,
+}
+
+impl TemplateFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?;
+        TemplateFormat::from_str(ext, true).ok()
+    }
+}
+
+/// Render a binary template that maps the whole file as an array of records.
+pub fn export(layout: &Layout, format: TemplateFormat) -> String {
+    match format {
+        TemplateFormat::Hexpat => hexpat(layout),
+        TemplateFormat::Bt => bt(layout),
+    }
+}
+
+fn hexpat(layout: &Layout) -> String {
+    let name = type_name(&layout.name);
+    let mut out = String::new();
+
+    writeln!(out, "// {} database (generated by idoc)", layout.name).unwrap();
+    let endian = match super::endian() {
+        Endian::Little => "little",
+        Endian::Big => "big",
+    };
+    writeln!(out, "#pragma endian {}", endian).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "import std.mem;").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "struct {} {{", name).unwrap();
+    out.push_str(&members(layout, |kind| match kind {
+        FieldType::U8 | FieldType::Bytes => "u8",
+        FieldType::I8 => "s8",
+        FieldType::U16 => "u16",
+        FieldType::I16 => "s16",
+        FieldType::U32 => "u32",
+        FieldType::I32 => "s32",
+        FieldType::F32 => "float",
+        FieldType::Utf16 => "char16",
+        FieldType::Euckr => "char",
+    }));
+    writeln!(out, "}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "{} records[while(!std::mem::eof())] @ 0x00;", name).unwrap();
+
+    out
+}
+
+fn bt(layout: &Layout) -> String {
+    let name = identifier(&layout.name).to_ascii_uppercase();
+    let mut out = String::new();
+
+    writeln!(out, "// {} database (generated by idoc)", layout.name).unwrap();
+    let endian = match super::endian() {
+        Endian::Little => "LittleEndian",
+        Endian::Big => "BigEndian",
+    };
+    writeln!(out, "{}();", endian).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "typedef struct {{").unwrap();
+    out.push_str(&members(layout, |kind| match kind {
+        FieldType::U8 | FieldType::Bytes => "uchar",
+        FieldType::I8 | FieldType::Euckr => "char",
+        FieldType::U16 => "ushort",
+        FieldType::I16 => "short",
+        FieldType::U32 => "uint",
+        FieldType::I32 => "int",
+        FieldType::F32 => "float",
+        FieldType::Utf16 => "wchar_t",
+    }));
+    writeln!(out, "}} {} <size={}>;", name, layout.record_size).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "while (!FEof()) {{").unwrap();
+    writeln!(out, "    {} records;", name).unwrap();
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+/// One C-like member declaration per region, with its offset as a comment.
+/// Strings and byte fields become arrays of their element type; gaps are
+/// declared as byte arrays.
+pub(super) fn members(layout: &Layout, element: impl Fn(FieldType) -> &'static str) -> String {
+    let mut out = String::new();
+    for region in layout.regions() {
+        let (kind, id, offset, size) = match region {
+            Region::Field(field) => (
+                field.kind,
+                identifier(&field.name),
+                field.offset,
+                field.size(),
+            ),
+            Region::Gap { offset, size } => {
+                (FieldType::Bytes, Region::gap_name(offset), offset, size)
+            }
+        };
+        let declaration = match kind {
+            FieldType::Utf16 => format!("{} {}[{}]", element(kind), id, size / 2),
+            FieldType::Euckr | FieldType::Bytes => format!("{} {}[{}]", element(kind), id, size),
+            _ => format!("{} {}", element(kind), id),
+        };
+        writeln!(out, "    {}; // 0x{:02X}", declaration, offset).unwrap();
+    }
+    out
+}
+
+/// CamelCase type name for the record struct, e.g. `item_table` -> `ItemTable`.
+fn type_name(name: &str) -> String {
+    identifier(name)
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
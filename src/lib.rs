@@ -0,0 +1,57 @@
+//! Conversion of the game's .ido files, as used by the `idoc` binary and,
+//! through [`ffi`], by programs linking the library.
+
+pub mod bindiff;
+#[cfg(feature = "tui")]
+pub mod browse;
+pub mod changelog;
+pub mod compare;
+pub mod compression;
+pub mod config;
+#[cfg(not(target_family = "wasm"))]
+pub mod daemon;
+pub mod dbpatch;
+pub mod deploy;
+pub mod entropy;
+pub mod export;
+pub mod ffi;
+pub mod golden;
+pub mod guard;
+pub mod header;
+pub mod hexdump;
+#[cfg(not(target_family = "wasm"))]
+pub mod hooks;
+pub mod ido;
+pub mod index;
+pub mod info;
+pub mod layout;
+pub mod memory;
+pub mod merge;
+pub mod meta;
+#[cfg(not(target_family = "wasm"))]
+pub mod plugin;
+pub mod pointers;
+pub mod preview;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod repair;
+pub mod script;
+pub mod selftest;
+#[cfg(not(target_family = "wasm"))]
+pub mod serve;
+pub mod signature;
+pub mod snapshot;
+pub mod source;
+pub mod stats;
+pub mod strings;
+pub mod template;
+pub mod text;
+pub mod times;
+pub mod timings;
+pub mod triage;
+pub mod walk;
+pub mod warnings;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod xml;
+pub mod xor;
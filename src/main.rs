@@ -1,12 +1,36 @@
-use byteorder::{LittleEndian, ReadBytesExt};
-use clap::{ArgGroup, Parser};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use compression::Compression;
 use encoding_rs::EUC_KR;
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use serde::Serialize;
-use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use export::Structured;
+use export::{CsvDialect, ExportOptions, Format, Quoting, SqlDialect};
+use hooks::Stage;
+use ido::{IdoFile, PayloadKind};
+use layout::infer;
+use layout::template::TemplateFormat;
+use layout::{Endian, Layout, Value};
+use meta::Meta;
+use script::Hook;
+use source::Source;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use xor::XorKey;
+
+#[cfg(feature = "tui")]
+use idoc::browse;
+use idoc::{
+    bindiff, changelog, compare, compression, config, daemon, dbpatch, deploy, entropy, export,
+    golden, guard, header, hexdump, hooks, ido, index, info, layout, memory, merge, meta, plugin,
+    pointers, preview, repair, script, selftest, serve, snapshot, source, stats, strings, template,
+    text, times, timings, triage, warnings, xml, xor,
+};
 
 #[derive(Debug, Parser, Clone)]
 #[command(
@@ -19,321 +43,2638 @@ use std::path::PathBuf;
         .required(true)
         .args(["decompile", "compile"]),
 ))]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long, help = "Decompile .ido file")]
     decompile: bool,
 
-    #[arg(short, long, help = "Compile .xml file to .ido")]
+    #[arg(
+        short,
+        long,
+        help = "Compile to .ido (XML, JSON, YAML, CSV or raw data; detected from extension and content)"
+    )]
     compile: bool,
 
-    #[arg(short, long, help = "Input .ido file")]
-    file: PathBuf,
+    #[arg(
+        short,
+        long,
+        required = true,
+        num_args = 1..,
+        help = "Input .ido file; several can follow one -f, or each have their own"
+    )]
+    file: Vec<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        help = "Files converted at once when several are given (0 for one per CPU)"
+    )]
+    jobs: usize,
+
+    #[arg(
+        short,
+        long,
+        required_unless_present = "output_dir",
+        help = "Output file path, or a template such as '{dir}/{stem}.{type_ext}'"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "output",
+        help = "Write the output into DIR, named after the input with the extension for its type"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Extension of a decompiled output given without a template: auto adds the type's when missing, keep writes the path as given, force replaces it"
+    )]
+    ext_policy: ExtPolicy,
+
+    #[arg(
+        long,
+        help = "Give outputs the modification time of their input, and mirrored directories that of theirs"
+    )]
+    preserve_times: bool,
+
+    #[arg(
+        long,
+        help = "On decompile, also write the decompressed payload, untouched, to a .raw file next to the output"
+    )]
+    keep_raw: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Bytes after the last whole record of a database on decompile"
+    )]
+    trailing_bytes: TrailingBytes,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Stream wrapper used on compile (defaults to the one recorded on decompile, or zlib)"
+    )]
+    compression: Option<Compression>,
+
+    #[arg(
+        long,
+        value_name = "HEX",
+        help = "XOR key applied to the compressed payload on read and write"
+    )]
+    xor_key: Option<XorKey>,
+
+    #[arg(
+        long,
+        requires = "xor_key",
+        help = "Also apply the XOR key to the header"
+    )]
+    xor_header: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Output format: csv, sql or arrow for databases (default csv); json, yaml, msgpack or cbor for databases and XML (default XML)"
+    )]
+    format: Option<Format>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "SQL dialect for --format sql"
+    )]
+    sql_dialect: SqlDialect,
+
+    #[arg(
+        long,
+        value_parser = export::csv::parse_delimiter,
+        default_value = ",",
+        help = "CSV field delimiter on export and import (a character, or 'tab')"
+    )]
+    delimiter: u8,
 
-    #[arg(short, long, help = "Output file path")]
-    output: PathBuf,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "CSV quoting style on export"
+    )]
+    quote: Quoting,
+
+    #[arg(long, help = "Start CSV exports with a UTF-8 BOM (for Excel)")]
+    bom: bool,
+
+    #[arg(
+        long,
+        help = "Write what decompresses from a damaged or truncated stream instead of failing"
+    )]
+    recover: bool,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = header::parse_offset,
+        global = true,
+        help = "Length of the header in front of the payload, for file families that don't use 0x5F"
+    )]
+    header_size: Option<usize>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Write outputs into a game installation (a directory matching game_dirs, or holding a game_markers entry, in idoc.toml)"
+    )]
+    allow_game_dir: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Copy files to <file>.bak before overwriting them"
+    )]
+    backup: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Wait while another process is writing the same output, instead of failing"
+    )]
+    wait_for_lock: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Exit with an error if anything was warned about"
+    )]
+    warnings_as_errors: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Report time and throughput of each stage (read, decompress, decode, encode, compress, write)"
+    )]
+    timings: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "MB",
+        help = "Memory one file may use; work that would need more fails cleanly instead of running out"
+    )]
+    max_memory: Option<u64>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t,
+        help = "Byte order of database records and headers"
+    )]
+    endian: Endian,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "rhai script transforming XML text and database records on decompile and compile; may be repeated"
+    )]
+    script: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Layout used when compiling a CSV, JSON or YAML database (name or .toml path; defaults to an idoc.toml handler's for the output, or shop)"
+    )]
+    layout: Option<String>,
+}
+
+impl Args {
+    fn csv_dialect(&self) -> CsvDialect {
+        CsvDialect {
+            delimiter: self.delimiter,
+            quoting: self.quote,
+            bom: self.bom,
+        }
+    }
+}
+
+/// Settings that shape a compile beyond its input and output paths.
+#[derive(Debug, Clone, Default)]
+struct CompileOptions {
+    compression: Option<Compression>,
+    xor_key: Option<XorKey>,
+    xor_header: bool,
+    layout: Option<String>,
+    csv: CsvDialect,
+    header_size: usize,
+}
+
+/// Settings that shape a decompile beyond its input and output paths.
+#[derive(Debug, Clone, Default)]
+struct DecompileOptions {
+    decode: ido::DecodeOptions,
+    format: Option<Format>,
+    export: ExportOptions,
+    ext_policy: ExtPolicy,
+    keep_raw: bool,
+    trailing_bytes: TrailingBytes,
+}
+
+/// What decompile does with the bytes after the last whole record of a
+/// fixed-record database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+enum TrailingBytes {
+    /// Warn and leave them out
+    #[default]
+    Warn,
+    /// Keep them as a footer in a .footer file next to the output, appended again on compile
+    Footer,
+    /// Fail
+    Error,
+}
+
+/// What decompile does to the extension of an output path given without
+/// a template, which is written exactly where it says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+enum ExtPolicy {
+    /// Add the type's extension when the path has none. Textures and
+    /// unknown payloads always get theirs, so a texture is never saved
+    /// as .xml.
+    #[default]
+    Auto,
+    /// Write to the path as given.
+    Keep,
+    /// Always replace the extension with the type's.
+    Force,
+}
+
+impl ExtPolicy {
+    /// `output` for a payload whose extension is `type_ext`; `switch` for
+    /// the types whose extension `Auto` always sets.
+    fn apply(self, output: &Path, type_ext: &str, switch: bool) -> PathBuf {
+        let replace = match self {
+            ExtPolicy::Auto => switch || output.extension().is_none(),
+            ExtPolicy::Keep => false,
+            ExtPolicy::Force => true,
+        };
+        if replace {
+            output.with_extension(type_ext)
+        } else {
+            output.to_path_buf()
+        }
+    }
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum Command {
+    /// Inspect and export record layouts
+    #[command(subcommand)]
+    Layout(LayoutCommand),
+
+    /// Write a JSON Schema describing records exported with --format json
+    Schema {
+        #[arg(help = "Built-in layout name (shop, header) or path to a layout .toml file")]
+        layout: String,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// List every .ido below a directory with its type, header, sizes and hash
+    Index {
+        #[arg(help = "Directory to scan recursively")]
+        dir: PathBuf,
+
+        #[arg(short, long, help = "Output CSV path (defaults to stdout)")]
+        output: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Report how much of damaged payloads still decompresses, and where they break"
+        )]
+        recover: bool,
+    },
+
+    /// Write a binary patch turning one .ido into another
+    Bindiff {
+        #[arg(help = "Original .ido file")]
+        old: PathBuf,
+
+        #[arg(help = "Updated .ido file")]
+        new: PathBuf,
+
+        #[arg(short, long, help = "Output .idopatch path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Apply a patch written by bindiff
+    Binpatch {
+        #[arg(help = "Original .ido file")]
+        old: PathBuf,
+
+        #[arg(help = "Patch file")]
+        patch: PathBuf,
+
+        #[arg(short, long, help = "Output .ido path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Compare the decoded payloads of two .ido files
+    Diff {
+        #[arg(help = "Old .ido file")]
+        a: PathBuf,
+
+        #[arg(help = "New .ido file")]
+        b: PathBuf,
+
+        #[arg(
+            long,
+            help = "Print a unified diff of the decompiled text (XML, or CSV for databases)"
+        )]
+        text: bool,
+
+        #[arg(
+            short = 'U',
+            long,
+            default_value_t = 3,
+            help = "Lines of context in --text output"
+        )]
+        context: usize,
+    },
+
+    /// Three-way merge of XML .ido files; conflicts go to a decompiled .conflict.xml
+    Merge3 {
+        #[arg(help = "Common ancestor .ido file")]
+        base: PathBuf,
+
+        #[arg(help = "Our modified .ido file")]
+        ours: PathBuf,
+
+        #[arg(help = "Their modified .ido file")]
+        theirs: PathBuf,
+
+        #[arg(short, long, help = "Merged .ido path")]
+        output: PathBuf,
+    },
+
+    /// Change selected records and fields of a database from a CSV patch
+    ApplyPatch {
+        #[arg(help = "Database .ido file")]
+        db: PathBuf,
+
+        #[arg(help = "CSV with the records and columns to change")]
+        patch: PathBuf,
+
+        #[arg(short, long, help = "Patched .ido path")]
+        output: PathBuf,
+
+        #[arg(
+            long,
+            default_value = "shop",
+            help = "Layout of the database (name or .toml path)"
+        )]
+        layout: String,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Fields matching patch rows to records (defaults to the layout's key)"
+        )]
+        key: Vec<String>,
+
+        #[arg(
+            long,
+            value_parser = export::csv::parse_delimiter,
+            default_value = ",",
+            help = "CSV field delimiter (a character, or 'tab')"
+        )]
+        delimiter: u8,
+    },
+
+    /// Summarize added, removed and changed files between two data directories as Markdown
+    Changelog {
+        #[arg(long, help = "Directory with the previous version")]
+        old: PathBuf,
+
+        #[arg(long, help = "Directory with the new version")]
+        new: PathBuf,
+
+        #[arg(short, long, help = "Output Markdown path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Print a file's decompiled text to stdout, for use as a git diff.textconv driver
+    Textconv {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+    },
+
+    /// Git clean filter: decompile an XML .ido on stdin to text on stdout
+    Clean,
+
+    /// Git smudge filter: compile text written by clean on stdin back to .ido on stdout
+    Smudge,
+
+    /// Save, restore and list snapshots of data directories
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+
+    /// Report identical, changed and unique .ido files of two directories by payload hash
+    Compare {
+        #[arg(help = "First directory")]
+        a: PathBuf,
+
+        #[arg(help = "Second directory")]
+        b: PathBuf,
+    },
+
+    /// Describe an .ido file without writing anything
+    Info {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+    },
+
+    /// Read or patch raw header bytes in place
+    #[command(subcommand)]
+    Header(HeaderCommand),
+
+    /// Hex dump annotated with header fields, stream boundaries and records
+    Hexdump {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(long, help = "Dump the decompressed payload instead of the file")]
+        decompressed: bool,
+
+        #[arg(
+            long,
+            default_value = "shop",
+            help = "Record layout for databases: built-in name (shop, header) or .toml path"
+        )]
+        layout: String,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Browse a database's records or an XML tree in a terminal UI
+    Browse {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            default_value = "shop",
+            help = "Record layout for databases: built-in name (shop, header) or .toml path"
+        )]
+        layout: String,
+    },
+
+    /// Entropy per block of the file and its payload, flagging text, padding and compressed regions
+    Entropy {
+        #[arg(help = "Input file")]
+        file: PathBuf,
+
+        #[arg(long, default_value_t = entropy::DEFAULT_BLOCK_SIZE, help = "Block size in bytes")]
+        block_size: usize,
+    },
+
+    /// Guess a draft layout for an unknown database from byte statistics across its records
+    InferLayout {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            help = "Record size in bytes (detected from repetition if omitted)"
+        )]
+        record_size: Option<usize>,
+
+        #[arg(long, help = "Layout name (defaults to the file name)")]
+        name: Option<String>,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Print the first records of a database
+    Head {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(short = 'n', long, default_value_t = 10, help = "Number of records")]
+        count: usize,
+
+        #[arg(
+            long,
+            default_value = "shop",
+            help = "Record layout: built-in name (shop, header) or .toml path"
+        )]
+        layout: String,
+    },
+
+    /// Print the last records of a database
+    Tail {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(short = 'n', long, default_value_t = 10, help = "Number of records")]
+        count: usize,
+
+        #[arg(
+            long,
+            default_value = "shop",
+            help = "Record layout: built-in name (shop, header) or .toml path"
+        )]
+        layout: String,
+    },
+
+    /// List readable strings in the decompressed payload, with their offsets
+    Strings {
+        #[arg(help = "Input file")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_values_t = [strings::Encoding::EucKr, strings::Encoding::Utf16Le],
+            help = "Encodings to look for"
+        )]
+        encodings: Vec<strings::Encoding>,
+
+        #[arg(long, default_value_t = strings::DEFAULT_MIN_LEN, help = "Minimum length in characters")]
+        min_len: usize,
+
+        #[arg(
+            long,
+            help = "Scan the file as stored rather than its decompressed payload"
+        )]
+        raw: bool,
+    },
+
+    /// Look for values that point at other offsets in the payload: offset tables and linked records
+    Pointers {
+        #[arg(help = "Input file")]
+        file: PathBuf,
+
+        #[arg(long, default_value_t = pointers::DEFAULT_WIDTH, value_parser = pointers::parse_width, help = "Pointer width in bytes (2, 4 or 8)")]
+        width: usize,
+    },
+
+    /// Value distributions of a database's fields, flagging constant, boolean-, enum- and ID-like columns
+    Stats {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            help = "Field or gap (e.g. unknown_0x10) to print a histogram of; repeatable"
+        )]
+        field: Vec<String>,
+
+        #[arg(
+            long,
+            default_value = "shop",
+            help = "Record layout: built-in name (shop, header) or .toml path"
+        )]
+        layout: String,
+
+        #[arg(long, default_value_t = stats::DEFAULT_TOP, help = "Most common values to show per field")]
+        top: usize,
+    },
+
+    /// Find a zlib stream shifted from its expected place and rewrite the file around it
+    Repair {
+        #[arg(help = "Damaged .ido file")]
+        file: PathBuf,
+
+        #[arg(
+            short,
+            long,
+            help = "Output file path (defaults to <file>.repaired.ido)"
+        )]
+        output: Option<PathBuf>,
+    },
+
+    /// Run every decode stage on each .ido below a directory and group the failures by cause
+    Triage {
+        #[arg(help = "Directory to scan recursively")]
+        dir: PathBuf,
+
+        #[arg(long, help = "Write the report as JSON, listing every failure")]
+        json: bool,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Round-trip every file of a sample directory and report what comes back unchanged
+    Selftest {
+        #[arg(help = "Directory to scan recursively")]
+        dir: PathBuf,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Record hashes of what idoc makes of a corpus, or check them against a later run
+    #[command(subcommand)]
+    Golden(GoldenCommand),
+
+    /// Serve decompile, compile and shop database export over HTTP
+    Serve {
+        #[arg(
+            long,
+            value_name = "ADDRESS",
+            default_value = "127.0.0.1:8080",
+            help = "Address and port to listen on"
+        )]
+        listen: String,
+    },
+
+    /// Keep running and convert files on request from editor plugins over a local socket
+    Daemon {
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Socket to listen on (defaults to idoc.sock in the runtime directory, or \\\\.\\pipe\\idoc on Windows)"
+        )]
+        socket: Option<PathBuf>,
+    },
+
+    /// List the payload plugins found, and libraries that could not be loaded
+    Plugins,
+
+    /// Copy compiled .ido files into a game installation, backing up what they replace
+    Deploy {
+        #[arg(long, help = "Game data directory to copy into")]
+        target: PathBuf,
+
+        #[arg(
+            long,
+            help = "Directory of compiled .ido files, copied to the same relative paths"
+        )]
+        from: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum LayoutCommand {
+    /// Write a Kaitai Struct (.ksy) definition of a layout
+    ExportKsy {
+        #[arg(help = "Built-in layout name (shop, header) or path to a layout .toml file")]
+        layout: String,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Write a C header with a packed struct of a layout
+    ExportC {
+        #[arg(help = "Built-in layout name (shop, header) or path to a layout .toml file")]
+        layout: String,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Write an ImHex (.hexpat) or 010 Editor (.bt) binary template of a layout
+    ExportTemplate {
+        #[arg(help = "Built-in layout name (shop, header) or path to a layout .toml file")]
+        layout: String,
+
+        #[arg(
+            long,
+            value_enum,
+            help = "Template dialect (defaults to the output extension, or hexpat)"
+        )]
+        format: Option<TemplateFormat>,
+
+        #[arg(short, long, help = "Output file path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+
+    /// Report which fields moved between two client versions of a database
+    Diff {
+        #[arg(
+            long,
+            help = "Sample file, or directory of samples, from the old version"
+        )]
+        old: PathBuf,
+
+        #[arg(long, help = "The same file, or directory, from the new version")]
+        new: PathBuf,
+
+        #[arg(
+            long,
+            help = "Layout of the old records (inferred from the samples if omitted)"
+        )]
+        layout: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum SnapshotCommand {
+    /// Store the current contents of a directory under a tag
+    Save {
+        #[arg(help = "Data directory to snapshot")]
+        dir: PathBuf,
+
+        #[arg(long, help = "Name of the snapshot")]
+        tag: String,
+
+        #[arg(long, default_value = ".idoc-store", help = "Snapshot store directory")]
+        store: PathBuf,
+    },
+
+    /// Put a directory back the way it was when a snapshot was saved
+    Restore {
+        #[arg(long, help = "Name of the snapshot")]
+        tag: String,
+
+        #[arg(
+            long,
+            help = "Directory to restore into (defaults to the one snapshotted)"
+        )]
+        into: Option<PathBuf>,
+
+        #[arg(long, help = "Also remove files that are not in the snapshot")]
+        delete: bool,
+
+        #[arg(long, default_value = ".idoc-store", help = "Snapshot store directory")]
+        store: PathBuf,
+    },
+
+    /// List snapshots, oldest first
+    List {
+        #[arg(long, default_value = ".idoc-store", help = "Snapshot store directory")]
+        store: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum GoldenCommand {
+    /// Hash the decoded payload, decompiled output and recompiled file of every .ido
+    Record {
+        #[arg(help = "Corpus directory to scan recursively")]
+        dir: PathBuf,
+
+        #[arg(
+            long,
+            help = "Hash file to write (defaults to .idoc-golden.json in the directory)"
+        )]
+        file: Option<PathBuf>,
+    },
+
+    /// Compare the corpus against recorded hashes; exits non-zero on any change
+    Check {
+        #[arg(help = "Corpus directory to scan recursively")]
+        dir: PathBuf,
+
+        #[arg(
+            long,
+            help = "Hash file to read (defaults to .idoc-golden.json in the directory)"
+        )]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum HeaderCommand {
+    /// Print header bytes as hex
+    Get {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(long, default_value = "0", value_parser = header::parse_offset, help = "Offset of the first byte (decimal or 0x hex)")]
+        offset: usize,
+
+        #[arg(long, help = "Number of bytes (defaults to the rest of the header)")]
+        length: Option<usize>,
+    },
+
+    /// Overwrite header bytes, leaving the payload as it is
+    Set {
+        #[arg(help = "Input .ido file")]
+        file: PathBuf,
+
+        #[arg(long, value_parser = header::parse_offset, help = "Offset of the first byte (decimal or 0x hex)")]
+        offset: usize,
+
+        #[arg(long, value_parser = header::parse_bytes, help = "New bytes as hex, e.g. 0a00")]
+        bytes: header::Bytes,
+
+        #[arg(
+            short,
+            long,
+            help = "Output file path (defaults to patching the input)"
+        )]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Options not on the command line, taken from their `IDOC_` variable or,
+/// failing that, from `[defaults]` in idoc.toml. They are given to clap
+/// as if typed on the command line, so they are checked the same way.
+///
+/// With a subcommand only the options that work with any command are
+/// layered; the others belong to a decompile or compile. A layered value
+/// is dropped when its option conflicts with one from a closer layer, and
+/// a switch set to false is simply not passed.
+fn parse_args() -> io::Result<Args> {
+    let cli: Vec<OsString> = env::args_os().collect();
+    let Ok(given) = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&cli)
+    else {
+        return Ok(Args::parse_from(cli));
+    };
+    let command = Args::command();
+    let layered: Vec<&clap::Arg> = command
+        .get_arguments()
+        .filter(|arg| arg.get_long().is_some())
+        .filter(|arg| !["decompile", "compile", "file"].contains(&arg.get_id().as_str()))
+        .filter(|arg| given.subcommand_name().is_none() || arg.is_global_set())
+        .collect();
+    // Conflicts are declared on one side only, so look both ways.
+    let conflicts = |arg: &clap::Arg, set: &[&str]| {
+        layered.iter().any(|other| {
+            set.contains(&other.get_id().as_str())
+                && (command.get_arg_conflicts_with(arg).contains(other)
+                    || command.get_arg_conflicts_with(other).contains(&arg))
+        })
+    };
+    let on_command_line: Vec<&str> = layered
+        .iter()
+        .map(|arg| arg.get_id().as_str())
+        .filter(|id| given.value_source(id) == Some(ValueSource::CommandLine))
+        .collect();
+
+    let defaults = &config::get().defaults;
+    if let Some(unknown) = defaults.keys().find(|key| {
+        !command
+            .get_arguments()
+            .any(|arg| arg.get_long().is_some() && arg.get_id() == key.as_str())
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("idoc.toml sets a default for unknown option '{}'", unknown),
+        ));
+    }
+
+    let mut from_env: Vec<&str> = Vec::new();
+    let mut extra: Vec<OsString> = Vec::new();
+    for arg in &layered {
+        let id = arg.get_id().as_str();
+        if on_command_line.contains(&id) || conflicts(arg, &on_command_line) {
+            continue;
+        }
+        let variable = format!("IDOC_{}", id.to_uppercase());
+        if let Some(value) = env::var_os(&variable) {
+            from_env.push(id);
+            extra.extend(layer_values(arg, &[value], &variable)?);
+        }
+    }
+    let closer: Vec<&str> = on_command_line.iter().chain(&from_env).copied().collect();
+    for arg in &layered {
+        let id = arg.get_id().as_str();
+        let Some(value) = defaults.get(id) else {
+            continue;
+        };
+        if closer.contains(&id) || conflicts(arg, &closer) {
+            continue;
+        }
+        let values: Vec<OsString> = match value {
+            toml::Value::Array(items) => items.iter().map(toml_text).collect(),
+            value => vec![toml_text(value)],
+        };
+        extra.extend(layer_values(
+            arg,
+            &values,
+            &format!("idoc.toml default '{}'", id),
+        )?);
+    }
+
+    // A subcommand's options follow its name, before any `--`.
+    let at = match given.subcommand_name() {
+        Some(_) => cli.iter().position(|a| a == "--").unwrap_or(cli.len()),
+        None => 1,
+    };
+    let mut argv = cli;
+    argv.splice(at..at, extra);
+    Ok(Args::parse_from(argv))
+}
+
+/// `values` given through `source` as arguments for `arg`: the bare flag
+/// for a switch that is on, nothing for one that is off.
+fn layer_values(arg: &clap::Arg, values: &[OsString], source: &str) -> io::Result<Vec<OsString>> {
+    let long = arg.get_long().unwrap_or_default();
+    if !matches!(arg.get_action(), ArgAction::SetTrue) {
+        return Ok(values
+            .iter()
+            .map(|value| {
+                let mut flag = OsString::from(format!("--{}=", long));
+                flag.push(value);
+                flag
+            })
+            .collect());
+    }
+    let text = values
+        .last()
+        .map(|value| value.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match text.trim() {
+        "" | "0" | "false" | "no" | "off" => Ok(Vec::new()),
+        "1" | "true" | "yes" | "on" => Ok(vec![OsString::from(format!("--{}", long))]),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is '{}'; switches take true/false, 1/0, yes/no or on/off",
+                source, other
+            ),
+        )),
+    }
+}
+
+/// A `[defaults]` value as it would be typed on the command line.
+fn toml_text(value: &toml::Value) -> OsString {
+    match value {
+        toml::Value::String(text) => OsString::from(text),
+        value => OsString::from(value.to_string()),
+    }
 }
 
 fn main() -> io::Result<()> {
-    let args = Args::parse();
+    if let Err(e) = config::init() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let scripts: Vec<PathBuf> = config::get()
+        .scripts
+        .iter()
+        .chain(&args.script)
+        .cloned()
+        .collect();
+    if let Err(e) = script::load(&scripts) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if args.timings {
+        timings::enable();
+    }
+    if let Some(megabytes) = args.max_memory {
+        memory::set(megabytes);
+    }
+    layout::set_endian(args.endian);
+    guard::init(guard::Guard {
+        allow_game_dir: args.allow_game_dir,
+        backup: args.backup,
+        wait_for_lock: args.wait_for_lock,
+    });
+    #[cfg(not(target_family = "wasm"))]
+    guard::handle_interrupts();
 
-    let result = if args.compile {
-        compile(&args.file, &args.output)
-    } else {
-        decompile(&args.file, &args.output)
+    // An output directory names each output like this template would.
+    let output = args.output.clone().or_else(|| {
+        let dir = args.output_dir.as_ref()?.to_string_lossy();
+        let dir = dir.replace('{', "{{").replace('}', "}}");
+        Some(Path::new(&dir).join("{stem}.{type_ext}"))
+    });
+    // Before the after hooks, which may copy the output elsewhere.
+    let keep_times = |input: &Path, written: PathBuf| {
+        if args.preserve_times {
+            times::copy(input, &written)?;
+        }
+        Ok(written)
+    };
+    let result = match (&args.command, &output) {
+        (Some(command), _) => run_command(command, args.header_size),
+        (None, Some(output)) if args.compile => {
+            let options = CompileOptions {
+                compression: args.compression,
+                xor_key: args.xor_key.clone(),
+                xor_header: args.xor_header,
+                layout: args.layout.clone(),
+                csv: args.csv_dialect(),
+                header_size: args.header_size.unwrap_or(ido::HEADER_SIZE),
+            };
+            convert_all(&args.file, output, args.jobs, args.preserve_times, |file| {
+                hooks::around(
+                    Stage::BeforeCompile,
+                    Stage::AfterCompile,
+                    file,
+                    output,
+                    || {
+                        let output = output_path(output, file, "ido", || output.clone())?;
+                        compile(file, &output, &options)?;
+                        keep_times(file, output)
+                    },
+                )
+            })
+        }
+        (None, Some(output)) => {
+            let options = DecompileOptions {
+                decode: ido::DecodeOptions {
+                    xor_key: args.xor_key.clone(),
+                    xor_header: args.xor_header,
+                    recover: args.recover,
+                    header_size: args.header_size,
+                },
+                format: args.format,
+                export: ExportOptions {
+                    csv: args.csv_dialect(),
+                    sql_dialect: args.sql_dialect,
+                },
+                ext_policy: args.ext_policy,
+                keep_raw: args.keep_raw,
+                trailing_bytes: args.trailing_bytes,
+            };
+            convert_all(&args.file, output, args.jobs, args.preserve_times, |file| {
+                hooks::around(
+                    Stage::BeforeDecompile,
+                    Stage::AfterDecompile,
+                    file,
+                    output,
+                    || keep_times(file, decompile(file, output, &options)?),
+                )
+            })
+        }
+        (None, None) => {
+            unreachable!("clap requires --file and --output or --output-dir without a subcommand")
+        }
     };
 
+    if let Some(report) = timings::report() {
+        eprint!("{}", report);
+    }
+    let warnings = warnings::take();
+    if !warnings.is_empty() {
+        let noun = if warnings.len() == 1 {
+            "warning"
+        } else {
+            "warnings"
+        };
+        eprintln!("\n{} {}:", warnings.len(), noun);
+        for warning in &warnings {
+            eprintln!("  {}", warning);
+        }
+    }
     if let Err(e) = result {
         eprintln!("Error: {}", e);
         std::process::exit(1);
     }
+    if args.warnings_as_errors && !warnings.is_empty() {
+        eprintln!(
+            "Error: {} warning{} with --warnings-as-errors",
+            warnings.len(),
+            if warnings.len() == 1 { "" } else { "s" }
+        );
+        std::process::exit(1);
+    }
 
     Ok(())
 }
 
-fn decompile(path: &PathBuf, output: &PathBuf) -> Result<(), io::Error> {
-    let mut file = File::open(path)?;
+/// Convert each of `files` with `convert` between the batch hooks, `jobs`
+/// at a time (0 for one per CPU). A file that fails doesn't stop the
+/// others; the run fails once they are done. With `preserve_times`, the
+/// directories an output template mirrors get their inputs' times.
+fn convert_all(
+    files: &[PathBuf],
+    output: &Path,
+    jobs: usize,
+    preserve_times: bool,
+    convert: impl Fn(&PathBuf) -> io::Result<PathBuf> + Sync,
+) -> io::Result<()> {
+    hooks::run_batch(Stage::BeforeBatch)?;
+    if let [file] = files {
+        let written = convert(file)?;
+        if preserve_times && template::is_template(output) {
+            times::copy_dirs(&[(file.clone(), written)])?;
+        }
+        return hooks::run_batch(Stage::AfterBatch);
+    }
+    if !template::is_template(output) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Several --file inputs need --output-dir, or an --output template such as '{dir}/{stem}.{type_ext}'",
+        ));
+    }
+    // A bad template fails before any file is converted.
+    template::expand(output, &files[0], "")?;
+    // Outputs are claimed as their paths are chosen, with the type
+    // extension each file turns out to have, so no output is overwritten
+    // by another file's.
+    *BATCH_OUTPUTS.lock().unwrap() = Some(HashMap::new());
 
-    let mut header = [0u8; 0x5F];
-    file.read_exact(&mut header)?;
+    let jobs = match jobs {
+        0 => thread::available_parallelism().map_or(1, usize::from),
+        jobs => jobs,
+    };
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let converted = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(files.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    println!("[{}/{}] {}", index + 1, files.len(), file.display());
+                    match convert(file) {
+                        Ok(written) => converted.lock().unwrap().push((file.clone(), written)),
+                        Err(e) => {
+                            eprintln!("Error: {}: {}", file.display(), e);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    *BATCH_OUTPUTS.lock().unwrap() = None;
+    if preserve_times {
+        times::copy_dirs(&converted.into_inner().unwrap())?;
+    }
+    match failed.into_inner() {
+        0 => hooks::run_batch(Stage::AfterBatch),
+        failed => Err(io::Error::other(format!(
+            "{} of {} files failed",
+            failed,
+            files.len()
+        ))),
+    }
+}
+
+/// Run a subcommand; `header_size` is the global `--header-size`.
+fn run_command(command: &Command, header_size: Option<usize>) -> io::Result<()> {
+    let decode = ido::DecodeOptions {
+        header_size,
+        ..Default::default()
+    };
+    let header_len = decode.header_size();
+    match command {
+        Command::Layout(LayoutCommand::ExportKsy { layout, output }) => {
+            let layout = Layout::resolve(layout)?;
+            write_output(output.as_ref(), layout::ksy::export(&layout).as_bytes())
+        }
+        Command::Schema { layout, output } => {
+            let layout = Layout::resolve(layout)?.with_gaps();
+            let schema = layout::schema::export(&layout);
+            let mut content = serde_json::to_string_pretty(&schema)?;
+            content.push('\n');
+            write_output(output.as_ref(), content.as_bytes())
+        }
+        Command::Layout(LayoutCommand::ExportC { layout, output }) => {
+            let layout = Layout::resolve(layout)?;
+            write_output(output.as_ref(), layout::cheader::export(&layout).as_bytes())
+        }
+        Command::Layout(LayoutCommand::Diff { old, new, layout }) => {
+            let layout = layout.as_deref().map(Layout::resolve).transpose()?;
+            print!("{}", layout::diff::report(old, new, layout.as_ref())?);
+            Ok(())
+        }
+        Command::Layout(LayoutCommand::ExportTemplate {
+            layout,
+            format,
+            output,
+        }) => {
+            let layout = Layout::resolve(layout)?;
+            let format = format
+                .or_else(|| output.as_deref().and_then(TemplateFormat::from_path))
+                .unwrap_or(TemplateFormat::Hexpat);
+            write_output(
+                output.as_ref(),
+                layout::template::export(&layout, format).as_bytes(),
+            )
+        }
+        Command::Index {
+            dir,
+            output,
+            recover,
+        } => {
+            let options = ido::DecodeOptions {
+                recover: *recover,
+                ..decode.clone()
+            };
+            let mut content = Vec::new();
+            let count = index::write(&mut content, dir, &options)?;
+            eprintln!("Indexed {} files", count);
+            write_output(output.as_ref(), &content)
+        }
+        Command::Bindiff { old, new, output } => {
+            let options = decode.clone();
+            let old = IdoFile::read(old, &options)?;
+            let new = IdoFile::read(new, &options)?;
+            if old.kind != new.kind {
+                warnings::warn(format!(
+                    "payload type changed from {} to {}",
+                    old.kind.description(),
+                    new.kind.description()
+                ));
+            }
+            let patch = bindiff::diff(&old, &new)?;
+            eprintln!(
+                "Patch is {} bytes for a {} byte payload",
+                patch.len(),
+                new.data.len()
+            );
+            write_output(output.as_ref(), &patch)
+        }
+        Command::Binpatch { old, patch, output } => {
+            let old = IdoFile::read(old, &decode)?;
+            let patched = bindiff::apply(&old, &fs::read(patch)?)?;
+            write_output(output.as_ref(), &patched)
+        }
+        Command::Diff {
+            a,
+            b,
+            text,
+            context,
+        } => {
+            let options = decode.clone();
+            let old = IdoFile::read(a, &options)?;
+            let new = IdoFile::read(b, &options)?;
+            // Binary payloads have no text form and fall back to the summary.
+            let rendered = match (text::render(&old), text::render(&new)) {
+                (Ok(old_text), Ok(new_text)) if *text => Some((old_text, new_text)),
+                _ => None,
+            };
+            match rendered {
+                Some((old_text, new_text)) => {
+                    let diff = text::unified_diff(
+                        &old_text,
+                        &new_text,
+                        &a.display().to_string(),
+                        &b.display().to_string(),
+                        *context,
+                    );
+                    io::stdout().write_all(diff.as_bytes())
+                }
+                None => {
+                    println!("{}", diff_summary(&old, &new));
+                    Ok(())
+                }
+            }
+        }
+        Command::Merge3 {
+            base,
+            ours,
+            theirs,
+            output,
+        } => merge3(base, ours, theirs, output, &decode),
+        Command::ApplyPatch {
+            db,
+            patch,
+            output,
+            layout,
+            key,
+            delimiter,
+        } => {
+            let layout = Layout::resolve(layout)?;
+            let key = if key.is_empty() { &layout.key } else { key };
+            let mut file = IdoFile::read(db, &decode)?;
+            if file.data.len() % layout.record_size != 0 {
+                warnings::warn(format!(
+                    "File size is not a multiple of record size ({})!",
+                    layout.record_size
+                ));
+            }
+            let dialect = CsvDialect {
+                delimiter: *delimiter,
+                ..Default::default()
+            };
+            let changed =
+                dbpatch::apply(&mut file.data, &layout, &fs::read(patch)?, &dialect, key)?;
+            guard::write(output, &file.encode()?)?;
+            println!("Changed {} records; saved to {}", changed, output.display());
+            Ok(())
+        }
+        Command::Changelog { old, new, output } => {
+            let report = changelog::write(old, new)?;
+            write_output(output.as_ref(), report.as_bytes())
+        }
+        Command::Textconv { file } => {
+            let file = IdoFile::read(file, &decode)?;
+            io::stdout().write_all(text::textconv(&file)?.as_bytes())
+        }
+        // Filters pass anything they don't understand through unchanged, so
+        // they are safe to run over every file git hands them.
+        Command::Clean => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            let text = match IdoFile::decode(input.clone(), &decode) {
+                Ok(file) if file.kind == PayloadKind::Xml => Some(text::render(&file)?),
+                _ => None,
+            };
+            match text {
+                Some(text) => io::stdout().write_all(text.as_bytes()),
+                None => io::stdout().write_all(&input),
+            }
+        }
+        Command::Smudge => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            let compiled = match std::str::from_utf8(&input) {
+                Ok(text) => text::compile_xml(text)?,
+                Err(_) => None,
+            };
+            io::stdout().write_all(compiled.as_deref().unwrap_or(&input))
+        }
+        Command::Snapshot(SnapshotCommand::Save { dir, tag, store }) => {
+            let summary = snapshot::Store::new(store).save(dir, tag)?;
+            println!(
+                "Saved snapshot '{}': {} files, {} new in the store",
+                tag, summary.files, summary.written
+            );
+            Ok(())
+        }
+        Command::Snapshot(SnapshotCommand::Restore {
+            tag,
+            into,
+            delete,
+            store,
+        }) => {
+            let summary = snapshot::Store::new(store).restore(tag, into.as_deref(), *delete)?;
+            println!(
+                "Restored snapshot '{}': {} of {} files rewritten, {} removed",
+                tag, summary.written, summary.files, summary.removed
+            );
+            Ok(())
+        }
+        Command::Snapshot(SnapshotCommand::List { store }) => {
+            for manifest in snapshot::Store::new(store).list()? {
+                let size: u64 = manifest.files.iter().map(|f| f.size).sum();
+                println!(
+                    "{}\t{} files\t{} bytes\t{}",
+                    manifest.tag,
+                    manifest.files.len(),
+                    size,
+                    manifest.source.display()
+                );
+            }
+            Ok(())
+        }
+        Command::Compare { a, b } => {
+            let report = compare::compare(a, b)?;
+            io::stdout().write_all(report.to_text(a, b).as_bytes())
+        }
+        Command::Info { file } => {
+            print!("{}", info::describe(file, &decode)?);
+            Ok(())
+        }
+        Command::Header(HeaderCommand::Get {
+            file,
+            offset,
+            length,
+        }) => {
+            let bytes = fs::read(file)?;
+            let range = header::range(&bytes, header_len, *offset, length.unwrap_or(header_len))?;
+            println!("{}", hex::encode(&bytes[range]));
+            Ok(())
+        }
+        Command::Header(HeaderCommand::Set {
+            file,
+            offset,
+            bytes: new,
+            output,
+        }) => {
+            let mut bytes = fs::read(file)?;
+            let range = header::range(&bytes, header_len, *offset, new.len())?;
+            if range.len() < new.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} bytes at 0x{:02X} run past the 0x{:02X}-byte header",
+                        new.len(),
+                        offset,
+                        header_len
+                    ),
+                ));
+            }
+            println!(
+                "0x{:02X}: {} -> {}",
+                offset,
+                hex::encode(&bytes[range.clone()]),
+                hex::encode(new)
+            );
+            bytes[range].copy_from_slice(new);
+            for warning in header::validate(&bytes[..bytes.len().min(header_len)], header_len) {
+                warnings::warn(warning);
+            }
+            let output = output.as_ref().unwrap_or(file);
+            guard::write(output, &bytes)?;
+            println!("Saved to {}", output.display());
+            Ok(())
+        }
+        Command::Hexdump {
+            file,
+            decompressed,
+            layout,
+            output,
+        } => {
+            let layout = Layout::resolve(layout)?;
+            let bytes = fs::read(file)?;
+            let options = decode.clone();
+            let dump = if *decompressed {
+                hexdump::payload(bytes, &options, &layout)?
+            } else {
+                hexdump::file(&bytes, &options, &layout)?
+            };
+            write_output(output.as_ref(), dump.as_bytes())
+        }
+        #[cfg(feature = "tui")]
+        Command::Browse { file, layout } => browse::browse(file, &decode, Layout::resolve(layout)?),
+        #[cfg(not(feature = "tui"))]
+        Command::Browse { .. } => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "idoc was built without the 'tui' feature",
+        )),
+        Command::Entropy { file, block_size } => {
+            if *block_size == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--block-size must be at least 1",
+                ));
+            }
+            let bytes = fs::read(file)?;
+            print!("{}", entropy::report(&bytes, &decode, *block_size));
+            Ok(())
+        }
+        Command::InferLayout {
+            file,
+            record_size,
+            name,
+            output,
+        } => {
+            let data = IdoFile::read(file, &decode)?.data;
+            let record_size = match record_size {
+                Some(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--record-size must be greater than zero",
+                    ));
+                }
+                Some(size) => *size,
+                None => infer::detect_stride(&data).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Could not detect a record size; pass --record-size",
+                    )
+                })?,
+            };
+            let count = data.len() / record_size;
+            if count < 2 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Need at least two records of {} bytes to compare, found {}",
+                        record_size, count
+                    ),
+                ));
+            }
+            eprintln!("Record size 0x{:X}: {} records", record_size, count);
+            if data.len() % record_size != 0 {
+                warnings::warn(format!(
+                    "{} bytes past the last record are ignored",
+                    data.len() % record_size
+                ));
+            }
 
-    if header.len() >= 8 && header[0] == 0x14 && &header[4..7] == b"_gb" {
-        println!("Detected Type: Gamebryo State Block (Binary)");
+            let name = name.clone().unwrap_or_else(|| {
+                layout::identifier(&file.file_stem().unwrap_or_default().to_string_lossy())
+            });
+            let guesses = infer::infer(&data, record_size);
+            let draft = infer::draft(&name, record_size, count, &guesses);
+            Layout::parse(&draft).expect("inferred fields do not overlap");
+            write_output(output.as_ref(), draft.as_bytes())
+        }
+        Command::Head {
+            file,
+            count,
+            layout,
+        } => preview(file, *count, layout, preview::End::Head, &decode),
+        Command::Tail {
+            file,
+            count,
+            layout,
+        } => preview(file, *count, layout, preview::End::Tail, &decode),
+        Command::Strings {
+            file,
+            encodings,
+            min_len,
+            raw,
+        } => {
+            let data = if *raw {
+                fs::read(file)?
+            } else {
+                IdoFile::read(file, &decode)?.data
+            };
+            let found = strings::scan(&data, encodings, (*min_len).max(1));
+            print!("{}", strings::report(&found));
+            Ok(())
+        }
+        Command::Pointers { file, width } => {
+            let data = IdoFile::read(file, &decode)?.data;
+            print!("{}", pointers::report(&data, *width));
+            Ok(())
+        }
+        Command::Stats {
+            file,
+            field,
+            layout,
+            top,
+        } => {
+            let layout = Layout::resolve(layout)?;
+            let decoded = IdoFile::read(file, &decode)?;
+            if decoded.kind == PayloadKind::Xml {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} holds XML, not database records", file.display()),
+                ));
+            }
+            if field.is_empty() {
+                print!("{}", stats::summary(&layout, &decoded.data));
+            }
+            for (i, name) in field.iter().enumerate() {
+                let field = stats::field(&layout, name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Layout '{}' has no field or gap '{}'", layout.name, name),
+                    )
+                })?;
+                if i > 0 {
+                    println!();
+                }
+                print!("{}", stats::histogram(&field, &layout, &decoded.data, *top));
+            }
+            Ok(())
+        }
+        Command::Repair { file, output } => {
+            let bytes = fs::read(file)?;
+            match repair::repair(file, &bytes)? {
+                repair::Outcome::Repaired(repaired) => {
+                    for note in &repaired.notes {
+                        println!("{}", note);
+                    }
+                    let output = output
+                        .clone()
+                        .unwrap_or_else(|| file.with_extension("repaired.ido"));
+                    write_output(Some(&output), &repaired.bytes)
+                }
+                repair::Outcome::Intact => {
+                    println!("The stream starts after the header and decodes; nothing to repair");
+                    Ok(())
+                }
+                repair::Outcome::Stored => {
+                    println!("Stored uncompressed; nothing to repair");
+                    Ok(())
+                }
+                repair::Outcome::NotFound => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "No zlib stream with a valid checksum within 0x{:X} bytes of the header end",
+                        repair::MAX_SHIFT
+                    ),
+                )),
+            }
+        }
+        Command::Triage { dir, json, output } => {
+            let report = triage::run(dir, &decode)?;
+            let content = if *json {
+                let mut content = serde_json::to_string_pretty(&report)?;
+                content.push('\n');
+                content
+            } else {
+                triage::text(&report)
+            };
+            write_output(output.as_ref(), content.as_bytes())
+        }
+        Command::Selftest { dir, output } => {
+            let results = selftest::run(dir)?;
+            write_output(output.as_ref(), selftest::text(&results).as_bytes())?;
+            match results
+                .iter()
+                .filter(|r| {
+                    !r.outcome.passed() && !matches!(r.outcome, selftest::Outcome::Skipped(_))
+                })
+                .count()
+            {
+                0 => Ok(()),
+                failed => Err(io::Error::other(format!(
+                    "{} files did not round-trip",
+                    failed
+                ))),
+            }
+        }
+        Command::Golden(GoldenCommand::Record { dir, file }) => {
+            let path = file.clone().unwrap_or_else(|| golden::default_path(dir));
+            let golden = golden::record(dir)?;
+            let mut content = serde_json::to_string_pretty(&golden)?;
+            content.push('\n');
+            guard::write(&path, content.as_bytes())?;
+            println!(
+                "Recorded {} files to {}",
+                golden.files.len(),
+                path.display()
+            );
+            Ok(())
+        }
+        Command::Golden(GoldenCommand::Check { dir, file }) => {
+            let path = file.clone().unwrap_or_else(|| golden::default_path(dir));
+            let golden = golden::load(&path)?;
+            let report = golden::check(dir, &golden)?;
+            print!("{}", golden::text(&report, &golden));
+            if report.passed() {
+                Ok(())
+            } else {
+                Err(io::Error::other("output differs from the recorded hashes"))
+            }
+        }
+        Command::Serve { listen } => serve::run(listen),
+        Command::Daemon { socket } => {
+            let socket = socket.clone().unwrap_or_else(daemon::default_socket);
+            daemon::run(&socket, |request| match request {
+                daemon::Request::Decompile { input, output } => hooks::around(
+                    Stage::BeforeDecompile,
+                    Stage::AfterDecompile,
+                    input,
+                    output,
+                    || {
+                        let options = DecompileOptions {
+                            decode: decode.clone(),
+                            ..DecompileOptions::default()
+                        };
+                        decompile(input, output, &options)
+                    },
+                )
+                .map(drop),
+                daemon::Request::Compile { input, output } => {
+                    let options = CompileOptions {
+                        header_size: header_len,
+                        ..CompileOptions::default()
+                    };
+                    hooks::around(
+                        Stage::BeforeCompile,
+                        Stage::AfterCompile,
+                        input,
+                        output,
+                        || {
+                            let output = output_path(output, input, "ido", || output.clone())?;
+                            compile(input, &output, &options).map(|()| output)
+                        },
+                    )
+                    .map(drop)
+                }
+                daemon::Request::Ping | daemon::Request::Shutdown => Ok(()),
+            })
+        }
+        Command::Plugins => {
+            let dirs: Vec<String> = plugin::dirs()
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect();
+            println!("Plugin directories: {}", dirs.join(", "));
+            if plugin::all().is_empty() && plugin::rejected().is_empty() {
+                println!("No plugins found");
+            }
+            for plugin in plugin::all() {
+                println!(
+                    "  {} (.{}): {}",
+                    plugin.name,
+                    plugin.extension,
+                    plugin.path.display()
+                );
+            }
+            Ok(())
+        }
+        Command::Deploy { target, from } => {
+            let summary = deploy::deploy(from, target)?;
+            println!(
+                "Deployed {} files to {}: {} replaced, {} added, {} already up to date",
+                summary.files,
+                target.display(),
+                summary.replaced,
+                summary.added,
+                summary.unchanged
+            );
+            println!(
+                "Originals are in {}; run {} to put them back",
+                target.join(deploy::DIR).join("backup").display(),
+                deploy::restore_script(target).display()
+            );
+            Ok(())
+        }
+    }
+}
 
-        let output_path = output.with_extension("gb");
-        let mut input_file = File::open(path)?;
-        let mut output_file = File::create(&output_path)?;
+fn preview(
+    file: &Path,
+    count: usize,
+    layout: &str,
+    end: preview::End,
+    options: &ido::DecodeOptions,
+) -> io::Result<()> {
+    let layout = Layout::resolve(layout)?;
+    let preview = preview::records(file, &layout, count, end, options)?;
+    print!("{}", preview::table(&layout, &preview.records));
+    eprintln!(
+        "{} of {} {} records",
+        preview.records.len(),
+        preview.total,
+        layout.name
+    );
+    Ok(())
+}
+
+fn merge3(
+    base: &PathBuf,
+    ours: &PathBuf,
+    theirs: &PathBuf,
+    output: &Path,
+    options: &ido::DecodeOptions,
+) -> io::Result<()> {
+    let base_file = IdoFile::read(base, options)?;
+    let ours_file = IdoFile::read(ours, options)?;
+    let theirs_file = IdoFile::read(theirs, options)?;
 
-        std::io::copy(&mut input_file, &mut output_file)?;
-        println!("Saved raw binary to {}", output_path.display());
+    // Only one side changed: take that file as it is. Header and
+    // compression count as changes too, so a side that only touched those
+    // still goes through the merge below.
+    let sidecar = |file: &IdoFile| file.meta.as_ref().map(Meta::to_sidecar);
+    let same = |a: &IdoFile, b: &IdoFile| a.data == b.data && sidecar(a) == sidecar(b);
+    if same(&theirs_file, &base_file) || same(&ours_file, &theirs_file) {
+        guard::copy(ours, output)?;
+        println!("Only ours changed; copied {}", ours.display());
+        return Ok(());
+    }
+    if same(&ours_file, &base_file) {
+        guard::copy(theirs, output)?;
+        println!("Only theirs changed; copied {}", theirs.display());
         return Ok(());
     }
 
-    if header.starts_with(&[0x01, 0x00, 0x01, 0x00]) {
-        println!("Detected Type: Shop Database (Binary Structs)");
+    let document = |file: &IdoFile, path: &PathBuf| {
+        if file.kind != PayloadKind::Xml {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: merge3 only merges XML, not {}",
+                    path.display(),
+                    file.kind.description()
+                ),
+            ));
+        }
+        xml::Document::parse(&EUC_KR.decode(&file.data).0)
+    };
+    let outcome = merge::merge(
+        &document(&base_file, base)?,
+        &document(&ours_file, ours)?,
+        &document(&theirs_file, theirs)?,
+    );
+
+    // Header and compression follow whichever side changed them.
+    let meta = if sidecar(&ours_file) == sidecar(&base_file) {
+        theirs_file.meta
+    } else {
+        ours_file.meta
+    }
+    .unwrap_or_default();
+
+    match outcome {
+        merge::Outcome::Clean(document) => {
+            let xml = document.to_xml();
+            let (encoded, _, _) = EUC_KR.encode(&xml);
+            guard::write(output, &ido::encode_payload(&meta, &encoded)?)?;
+            println!("Merged cleanly into {}", output.display());
+            Ok(())
+        }
+        merge::Outcome::Conflicts { text, count } => {
+            let conflict_path = output.with_extension("conflict.xml");
+            let content = format!("{}{}", text, meta.to_xml_comments());
+            guard::write(&conflict_path, content.as_bytes())?;
+            Err(io::Error::other(format!(
+                "{} conflicts; resolve them in {} and compile it to {}",
+                count,
+                conflict_path.display(),
+                output.display()
+            )))
+        }
+    }
+}
+
+/// One-line comparison of two payloads without rendering them as text.
+fn diff_summary(old: &IdoFile, new: &IdoFile) -> String {
+    if old.data == new.data {
+        return "Payloads are identical".to_string();
+    }
+    let changed = old
+        .data
+        .iter()
+        .zip(&new.data)
+        .filter(|(a, b)| a != b)
+        .count();
+    format!(
+        "Payloads differ: {} of {} bytes changed, size {} -> {}",
+        changed,
+        old.data.len().min(new.data.len()),
+        old.data.len(),
+        new.data.len()
+    )
+}
+
+/// Write to the given file, or to stdout when no path is given.
+fn write_output(path: Option<&PathBuf>, content: &[u8]) -> io::Result<()> {
+    match path {
+        Some(path) => {
+            guard::write(path, content)?;
+            eprintln!("Saved to {}", path.display());
+            Ok(())
+        }
+        None => io::stdout().write_all(content),
+    }
+}
+
+/// What an idoc.toml `[[handlers]]` rule makes of a file, instead of
+/// what it is detected as.
+#[derive(Clone, Copy)]
+enum Forced {
+    Kind(PayloadKind),
+    Plugin(&'static plugin::Plugin),
+}
 
-        return parse_shop_db(&path, &output);
+impl Forced {
+    fn resolve(rule: &config::HandlerRule) -> io::Result<Forced> {
+        if let Some(kind) = PayloadKind::from_name(&rule.kind) {
+            return Ok(Forced::Kind(kind));
+        }
+        match plugin::all().iter().find(|plugin| plugin.name == rule.kind) {
+            Some(plugin) => Ok(Forced::Plugin(plugin)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The idoc.toml handler for {} names type '{}', which is neither built in nor a signature or plugin",
+                    rule.files.join(", "),
+                    rule.kind
+                ),
+            )),
+        }
     }
+}
 
-    let header_hex = hex::encode(header);
+/// Decompile `path` to `output`, returning the path written: `output`
+/// with its template filled in, or the extension the payload needs.
+fn decompile(path: &PathBuf, output: &Path, options: &DecompileOptions) -> io::Result<PathBuf> {
+    if template::is_template(output) {
+        // A bad template fails before anything is read.
+        template::expand(output, path, "")?;
+    }
+    timings::file(path);
+    let read = timings::start(timings::Stage::Read);
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    ido::check_length(file_len, options.decode.header_size())?;
+
+    let mut header = vec![0u8; options.decode.header_size()];
+    file.read_exact(&mut header)?;
+    options.decode.decode_header(&mut header);
+
+    let rule = config::handler_for(path);
+    let forced = rule.map(Forced::resolve).transpose()?;
+    if let Some(rule) = rule {
+        println!(
+            "Type: {} (idoc.toml handler for {})",
+            rule.kind,
+            rule.files.join(", ")
+        );
+    }
+    let stored = match forced {
+        Some(Forced::Kind(kind @ (PayloadKind::Gamebryo | PayloadKind::ShopDb))) => Some(kind),
+        Some(_) => None,
+        None => ido::sniff_header(&header),
+    };
+    match stored {
+        Some(PayloadKind::Gamebryo) => {
+            println!("Detected Type: {}", PayloadKind::Gamebryo.description());
+
+            let output_path = {
+                let ext = PayloadKind::Gamebryo.output_extension();
+                output_path(output, path, ext, || {
+                    options.ext_policy.apply(output, ext, true)
+                })?
+            };
+            if options.keep_raw {
+                save_raw(&output_path, File::open(path)?)?;
+            }
+            let mut input_file = File::open(path)?;
+            let mut output_file = guard::create(&output_path)?;
+
+            std::io::copy(&mut input_file, &mut output_file)?;
+            output_file.finish()?;
+            println!("Saved raw binary to {}", output_path.display());
+            return Ok(output_path);
+        }
+        Some(kind) => {
+            println!("Detected Type: {}", kind.description());
+
+            let layout = rule.and_then(|rule| rule.layout.as_deref());
+            let layout = Layout::resolve(layout.unwrap_or("shop"))?.with_gaps();
+            return parse_shop_db(path, output, options, &layout);
+        }
+        None => {}
+    }
 
     // Decompress
-    let mut decoder = ZlibDecoder::new(file);
-    let mut decompressed_data = Vec::new();
-    decoder.read_to_end(&mut decompressed_data)?;
-
-    let extension = if decompressed_data.starts_with(b"DDS ") {
-        println!("Detected Type: DDS Texture");
-        Some("dds")
-    } else if decompressed_data.ends_with(b"TRUEVISION-XFILE.\0") {
-        println!("Detected Type: TGA Texture");
-        Some("tga")
-    } else if decompressed_data.starts_with(b"BM") {
-        println!("Detected Type: BMP Texture");
-        Some("bmp")
-    } else if decompressed_data.starts_with(b"\x89PNG") {
-        println!("Detected Type: PNG Texture");
-        Some("png")
-    } else {
-        None
+    memory::check("Reading the file", file_len)?;
+    let mut payload = Vec::new();
+    file.read_to_end(&mut payload)?;
+    read.done(header.len() + payload.len());
+    let decoded = match ido::decode_payload(&header, payload, &options.decode) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            let bytes = fs::read(path)?;
+            let xor_key = options.decode.xor_key.as_ref();
+            if let Some(warning) = header::stream_warning(&bytes, header.len(), xor_key) {
+                warnings::warn(warning);
+            }
+            return Err(e);
+        }
     };
 
-    if let Some(extension) = extension {
-        let output_path = if output.extension().is_none() {
-            let output_w_ext = output.with_extension(extension);
-            output_w_ext
-        } else {
-            output.clone()
+    if decoded.found_key
+        && let Some(key) = &decoded.meta.xor_key
+    {
+        println!("Detected XOR Key: {}", key);
+    }
+    if decoded.compression() != Compression::Zlib {
+        println!("Detected Compression: {}", decoded.compression().name());
+    }
+    if decoded.segment_count() > 1 {
+        println!("Detected {} concatenated streams", decoded.segment_count());
+    }
+    if decoded.trailing > 0 {
+        println!(
+            "Keeping {} trailing bytes after the last stream",
+            decoded.trailing
+        );
+    }
+    if decoded.damage.is_none() {
+        let compressed = fs::metadata(path)?.len() as usize - header.len() - decoded.trailing;
+        for (name, recorded, actual) in header::size_mismatches(
+            &header::layout()?,
+            &header,
+            compressed,
+            Some(decoded.data.len()),
+        ) {
+            warnings::warn(format!(
+                "Header {} is {}, but the payload's is {}.",
+                name.replace('_', " "),
+                recorded,
+                actual
+            ));
+        }
+    }
+    match &decoded.damage {
+        Some(damage) => warnings::warn(format!(
+            "Stream is damaged at file offset 0x{:X} ({}); recovered the first {} bytes.",
+            header.len() + damage.offset,
+            damage.error,
+            decoded.data.len()
+        )),
+        None => println!(
+            "Checksum: {}",
+            compression::check_summary(
+                decoded.compression(),
+                &decoded.data,
+                decoded.meta.segments.as_deref().unwrap_or_default()
+            )
+        ),
+    }
+
+    let meta = decoded.meta;
+    let decompressed_data = decoded.data;
+
+    // Plugins are asked first, so they can also take over payloads idoc
+    // would misread, such as text that isn't XML.
+    let (kind, plugin) = match forced {
+        Some(Forced::Kind(kind)) => (kind, None),
+        Some(Forced::Plugin(plugin)) => (decoded.kind, Some(plugin)),
+        None => (decoded.kind, plugin::detect(&decompressed_data)),
+    };
+    if let Some(plugin) = plugin {
+        println!(
+            "Detected Type: {} (plugin {})",
+            plugin.name,
+            plugin.path.display()
+        );
+        let ext = config::extension_for(&plugin.name).unwrap_or(&plugin.extension);
+        let output_path = output_path(output, path, ext, || {
+            options.ext_policy.apply(output, ext, false)
+        })?;
+        if options.keep_raw {
+            save_raw(&output_path, &decompressed_data[..])?;
+        }
+        let converted = plugin.decompile(&decompressed_data)?;
+        let meta = Meta {
+            plugin: Some(plugin.name.clone()),
+            ..meta
         };
+        save_with_sidecar(&output_path, &converted, &meta)?;
+        return Ok(output_path);
+    }
+
+    if kind.is_raw() {
+        println!("Detected Type: {}", kind.description());
+        if kind == PayloadKind::Unknown {
+            for hint in entropy::hints(&decompressed_data) {
+                println!("  {}", hint);
+            }
+        }
 
-        let mut output_file = File::create(&output_path)?;
-        output_file.write_all(&decompressed_data)?;
+        // Unknown payloads never keep a text extension such as .xml.
+        let ext = kind.output_extension();
+        let output_path = output_path(output, path, ext, || {
+            options
+                .ext_policy
+                .apply(output, ext, kind == PayloadKind::Unknown)
+        })?;
+        if options.keep_raw {
+            save_raw(&output_path, &decompressed_data[..])?;
+        }
 
-        // Save header to .meta file
-        let meta_path = output_path.with_extension("meta");
-        let mut meta_file = File::create(&meta_path)?;
-        meta_file.write_all(header_hex.as_bytes())?;
+        save_with_sidecar(&output_path, &decompressed_data, &meta)?;
+        return Ok(output_path);
+    }
 
-        println!("Saved as {}", output_path.display());
-        println!("Saved header to {}", meta_path.display());
-        return Ok(());
+    let type_ext = options
+        .format
+        .map_or(PayloadKind::Xml.output_extension(), Format::name);
+    let output = &output_path(output, path, type_ext, || {
+        options.ext_policy.apply(output, type_ext, false)
+    })?;
+    // Before decoding, so it is there when decoding fails.
+    if options.keep_raw {
+        save_raw(output, &decompressed_data[..])?;
     }
 
-    // Decode EUC-KR to UTF-8
+    // Decode EUC-KR to UTF-8. The payload, its text (up to half as
+    // long again) and the output built from it are held together.
+    memory::check("Decoding the payload", decompressed_data.len() as u64 * 4)?;
+    let decode = timings::start(timings::Stage::Decode);
     let (cow, _encoding_used, had_errors) = EUC_KR.decode(&decompressed_data);
 
     if had_errors {
-        println!("Warning: Some characters could not be decoded perfectly.");
+        warnings::warn("Some characters could not be decoded perfectly.");
+    }
+    let cow = script::xml(Hook::DecompileXml, cow)?;
+    if let Some(format) = options.format {
+        let document = xml::Document::parse(&cow)?;
+        let mut writer = io::BufWriter::new(guard::create(output)?);
+        export::write_document(&mut writer, &document, &meta, format)?;
+        finish(writer)?;
+        // The export writes as it goes, so writing is timed with it.
+        decode.done(decompressed_data.len());
+        println!("Saved {} to {}", format.name(), output.display());
+        return Ok(output.clone());
     }
 
-    let final_xml = format!("{}\n<!-- IDO HEADER: {} -->", cow, header_hex);
+    let final_xml = format!("{}\n{}", cow, meta.to_xml_comments());
+    decode.done(decompressed_data.len());
 
     // Save the XML
-    let mut output_file = File::create(output)?;
-    output_file.write_all(final_xml.as_bytes())?;
+    let write = timings::start(timings::Stage::Write);
+    guard::write(output, final_xml.as_bytes())?;
+    write.done(final_xml.len());
+
+    Ok(output.clone())
+}
+
+/// Outputs of the [`convert_all`] batch being run, with the input each
+/// was claimed for.
+static BATCH_OUTPUTS: Mutex<Option<HashMap<PathBuf, PathBuf>>> = Mutex::new(None);
+
+/// Where an output whose natural extension is `type_ext` goes: `output`
+/// filled in, with its directories created, when it is a template, and
+/// `untemplated` otherwise. In a batch, a path claimed for another input
+/// fails before anything is written to it.
+fn output_path(
+    output: &Path,
+    input: &Path,
+    type_ext: &str,
+    untemplated: impl FnOnce() -> PathBuf,
+) -> io::Result<PathBuf> {
+    if !template::is_template(output) {
+        return Ok(untemplated());
+    }
+    let path = template::expand(output, input, type_ext)?;
+    if let Some(outputs) = BATCH_OUTPUTS.lock().unwrap().as_mut()
+        && let other = outputs
+            .entry(path.clone())
+            .or_insert_with(|| input.to_path_buf())
+        && other != input
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} and {} would be written to the same output {}",
+                other.display(),
+                input.display(),
+                path.display()
+            ),
+        ));
+    }
+    guard::create_dirs(&path)?;
+    Ok(path)
+}
 
+/// `--keep-raw`: `data`, the payload as it was before being decoded, in a
+/// .raw file next to `output`, unless `output` is that file.
+fn save_raw(output: &Path, mut data: impl Read) -> io::Result<()> {
+    let raw_path = output.with_extension("raw");
+    if raw_path == output {
+        return Ok(());
+    }
+    let mut raw_file = guard::create(&raw_path)?;
+    io::copy(&mut data, &mut raw_file)?;
+    raw_file.finish()?;
+    println!("Saved raw payload to {}", raw_path.display());
     Ok(())
 }
 
-fn compile(input: &PathBuf, output: &PathBuf) -> Result<(), io::Error> {
+/// Flush `writer` and [finish](guard::Output::finish) the output under it.
+fn finish(writer: io::BufWriter<guard::Output>) -> io::Result<()> {
+    writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .finish()
+}
+
+/// Write a raw or plugin-converted payload, with its header and the rest
+/// of `meta` in a .meta file next to it.
+fn save_with_sidecar(output_path: &Path, data: &[u8], meta: &Meta) -> io::Result<()> {
+    let write = timings::start(timings::Stage::Write);
+    guard::write(output_path, data)?;
+    write.done(data.len());
+
+    // Save header to .meta file
+    let meta_path = output_path.with_extension("meta");
+    guard::write(&meta_path, meta.to_sidecar().as_bytes())?;
+
+    println!("Saved as {}", output_path.display());
+    println!("Saved header to {}", meta_path.display());
+    Ok(())
+}
+
+/// Fall back to the registry's header for `output` when `meta` has none.
+fn or_known_header(meta: Meta, output: &Path) -> Meta {
+    match header::known_for(output).filter(|_| meta.header.is_none()) {
+        Some(known) => {
+            println!("Using known header: {}", known.name);
+            meta.or(Meta::new(&known.header))
+        }
+        None => meta,
+    }
+}
+
+/// The layout a database is compiled to `output` with: `--layout`, the
+/// one of the idoc.toml handler for the output's name, or `shop`, with
+/// the gap columns exports carry.
+fn db_layout(options: &CompileOptions, output: &Path) -> io::Result<Layout> {
+    let name = options
+        .layout
+        .as_deref()
+        .or_else(|| config::handler_for(output).and_then(|rule| rule.layout.as_deref()))
+        .unwrap_or("shop");
+    Ok(Layout::resolve(name)?.with_gaps())
+}
+
+fn compile(input: &PathBuf, output: &Path, options: &CompileOptions) -> Result<(), io::Error> {
+    timings::file(input);
     // 1. Check for .meta file
     let meta_path = input.with_extension("meta");
-    let meta_header = if meta_path.exists() {
+    let sidecar = if meta_path.exists() {
         println!("Found .meta file: {}", meta_path.display());
-        let mut content = String::new();
-        File::open(&meta_path)?.read_to_string(&mut content)?;
-        Some(hex::decode(content.trim()).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to decode hex in .meta: {}", e),
-            )
-        })?)
+        Some(Meta::read_sidecar(&meta_path)?)
     } else {
         None
     };
+    // A plugin's output goes back through the plugin, whatever it looks
+    // like.
+    let from_plugin = sidecar.as_ref().is_some_and(|meta| meta.plugin.is_some());
+
+    if !from_plugin && source::by_extension(input) == Some(Source::Csv) {
+        let layout = db_layout(options, output)?;
+        println!(
+            "Reading {} records from {}...",
+            layout.name,
+            input.display()
+        );
+        return compile_csv_db(input, &layout, &options.csv, output);
+    }
 
-    // 2. Determine input type (XML vs Binary)
-    let is_xml = input
-        .extension()
-        .map_or(false, |s| s.eq_ignore_ascii_case("xml"));
-
-    let (header, raw_bytes) = if is_xml {
-        println!("Reading and encoding XML from {}...", input.display());
-        let mut xml_content = String::new();
-        File::open(input)?.read_to_string(&mut xml_content)?;
-
-        let header_marker = "<!-- IDO HEADER: ";
-        let end_header_marker = " -->";
-
-        // Try to find embedded header
-        let (embedded_header, content_str) =
-            if let Some(start_idx) = xml_content.rfind(header_marker) {
-                let after = &xml_content[start_idx + header_marker.len()..];
-                if let Some(end_idx) = after.find(end_header_marker) {
-                    let hex_str = &after[..end_idx];
-                    let h = hex::decode(hex_str).map_err(|e| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            format!("Failed to decode embedded header: {}", e),
-                        )
-                    })?;
-                    (Some(h), &xml_content[..start_idx])
-                } else {
-                    (None, xml_content.as_str())
+    let read = timings::start(timings::Stage::Read);
+    // The input, its encoded form and the compressed output are held
+    // together.
+    memory::check("Compiling the input", fs::metadata(input)?.len() * 3)?;
+    let content = fs::read(input)?;
+    read.done(content.len());
+    let encode = timings::start(timings::Stage::Encode);
+    let source = if from_plugin {
+        Source::Binary
+    } else {
+        source::detect(input, &content)
+    };
+
+    let structured = match source {
+        Source::Csv => unreachable!("CSV is recognized by extension and compiled above"),
+        Source::Structured(format) => {
+            let layout = db_layout(options, output)?;
+            println!("Reading {} from {}...", format.name(), input.display());
+            match export::read_structured(&content, format, &layout)? {
+                Structured::Records(records) => {
+                    println!("Found {} {} records", records.len(), layout.name);
+                    let records = script::records(Hook::ImportRecord, &layout, records, 0)?;
+                    encode.done(content.len());
+                    return compile_db(&records, &layout, &read_footer(input)?, output);
                 }
-            } else {
-                (None, xml_content.as_str())
-            };
+                Structured::Document(meta, document) => Some((*meta, document.to_xml())),
+            }
+        }
+        Source::Xml | Source::Binary => None,
+    };
 
+    // 2. XML-like sources carry their own metadata; binary needs the sidecar
+    let xml = match structured {
+        Some(xml) => Some(xml),
+        None if source == Source::Xml => {
+            println!("Reading and encoding XML from {}...", input.display());
+            let xml_content = std::str::from_utf8(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("XML input is not valid UTF-8: {}", e),
+                )
+            })?;
+            // Try to find embedded header
+            let (embedded, content_str) = Meta::split_xml(xml_content)?;
+            Some((embedded, content_str.to_string()))
+        }
+        None => None,
+    };
+
+    let (meta, raw_bytes) = if let Some((embedded, xml_content)) = xml {
         // Use meta header if available, otherwise embedded
-        let final_header = meta_header.or(embedded_header).ok_or_else(|| {
-            io::Error::new(
+        let meta = match sidecar {
+            Some(sidecar) => sidecar.or(embedded),
+            None => embedded,
+        };
+        let meta = or_known_header(meta, output);
+        if meta.header.is_none() {
+            return Err(io::Error::new(
                 io::ErrorKind::NotFound,
-                "Header not found in .meta or embedded in XML",
-            )
-        })?;
+                "Header not found in .meta, embedded in XML or among known headers",
+            ));
+        }
 
-        let clean_content = content_str.trim();
+        let xml_content = script::xml(Hook::CompileXml, xml_content.into())?;
+        let clean_content = xml_content.trim();
         let (cow, _, unmappable) = EUC_KR.encode(clean_content);
 
         if unmappable {
-            eprintln!("Warning: Some characters could not be mapped to EUC-KR.");
+            warnings::warn("Some characters could not be mapped to EUC-KR.");
         }
 
-        (final_header, cow.to_vec())
+        (meta, cow.to_vec())
     } else {
         // Binary Mode
-        let header = meta_header.ok_or_else(|| {
-            io::Error::new(
+        let meta = or_known_header(sidecar.unwrap_or_default(), output);
+        if meta.header.is_none() {
+            return Err(io::Error::new(
                 io::ErrorKind::NotFound,
                 format!(
                     "Compiling binary file requires a .meta file at {}",
                     meta_path.display()
                 ),
-            )
-        })?;
+            ));
+        }
 
         println!("Reading binary data from {}...", input.display());
-        let mut data = Vec::new();
-        File::open(input)?.read_to_end(&mut data)?;
-        (header, data)
+        match &meta.plugin {
+            Some(name) => {
+                let plugin = plugin::find(name)?;
+                println!("Compiling with plugin {}...", plugin.name);
+                let payload = plugin.compile(&content)?;
+                (meta, payload)
+            }
+            None => (meta, content),
+        }
     };
+    encode.done(raw_bytes.len());
 
-    println!("Header size: {} bytes", header.len());
-    println!("Compressing {} bytes of data...", raw_bytes.len());
+    let meta = Meta {
+        compression: Some(
+            options
+                .compression
+                .or(meta.compression)
+                .unwrap_or(Compression::Zlib),
+        ),
+        xor_key: options.xor_key.clone().or(meta.xor_key),
+        xor_header: Some(options.xor_header || meta.xor_header == Some(true)),
+        ..meta
+    };
+    let header_len = meta.header.as_ref().map_or(0, Vec::len);
+    let compression = meta.compression.unwrap_or(Compression::Zlib);
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
-    encoder.write_all(&raw_bytes)?;
-    let compressed_data = encoder.finish()?;
+    println!("Header size: {} bytes", header_len);
+    for warning in header::validate(
+        meta.header.as_deref().unwrap_or_default(),
+        options.header_size,
+    ) {
+        warnings::warn(warning);
+    }
+    println!(
+        "Compressing {} bytes of data ({})...",
+        raw_bytes.len(),
+        compression.name()
+    );
+    if let Some(segments) = meta.segments.as_ref().filter(|s| s.len() > 1) {
+        println!("Splitting into {} streams", segments.len());
+    }
+    if let Some(key) = &meta.xor_key {
+        println!("Applying XOR key {}", key);
+    }
 
-    println!("Done ({} bytes)", compressed_data.len());
+    let file = ido::encode_payload(&meta, &raw_bytes)?;
+    println!("Done ({} bytes)", file.len() - header_len);
 
     println!("Writing output file {}...", output.display());
-    let mut output_file = File::create(output)?;
-
-    output_file.write_all(&header)?;
-    output_file.write_all(&compressed_data)?;
+    let write = timings::start(timings::Stage::Write);
+    guard::write(output, &file)?;
+    write.done(file.len());
 
     println!(
         "Successfully compiled IDO file ({} bytes) to {}.",
-        header.len() + compressed_data.len(),
+        file.len(),
         output.display()
     );
 
     Ok(())
 }
 
-fn parse_shop_db(input: &PathBuf, output: &PathBuf) -> io::Result<()> {
+/// The footer kept from a database decompiled with `--trailing-bytes
+/// footer`, in a .footer file next to `input`, or nothing.
+fn read_footer(input: &Path) -> io::Result<Vec<u8>> {
+    let footer_path = input.with_extension("footer");
+    if !footer_path.is_file() {
+        return Ok(Vec::new());
+    }
+    let footer = fs::read(&footer_path)?;
+    println!(
+        "Found .footer file: {} ({} bytes)",
+        footer_path.display(),
+        footer.len()
+    );
+    Ok(footer)
+}
+
+/// Rebuild a fixed-record database from decoded records, followed by
+/// `footer`.
+fn compile_db(
+    records: &[Vec<Value>],
+    layout: &Layout,
+    footer: &[u8],
+    output: &Path,
+) -> io::Result<()> {
+    let encode = timings::start(timings::Stage::Encode);
+    let mut data = layout.write_records(records)?;
+    data.extend_from_slice(footer);
+    encode.done(data.len());
+
+    println!("Writing output file {}...", output.display());
+    let write = timings::start(timings::Stage::Write);
+    guard::write(output, &data)?;
+    write.done(data.len());
+
+    println!(
+        "Successfully compiled {} records ({} bytes) to {}.",
+        records.len(),
+        data.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Rebuild a fixed-record database from a CSV export, a record at a time.
+/// The CSV is read twice: first checking every cell, with all the errors
+/// reported at once, then writing, so a bad cell leaves an existing output
+/// alone.
+fn compile_csv_db(
+    input: &Path,
+    layout: &Layout,
+    dialect: &CsvDialect,
+    output: &Path,
+) -> io::Result<()> {
+    let footer = read_footer(input)?;
+    let mut record = vec![0u8; layout.record_size];
+    // Import scripts run on both passes; the first only checks what they
+    // return.
+    let encode_record = |index: u64, values: Vec<Value>, record: &mut [u8]| {
+        let values = script::record(Hook::ImportRecord, layout, values, index)?;
+        record.fill(0);
+        layout.write_record(&values, record).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record {}: {}", index, e),
+            )
+        })
+    };
+
+    let encode = timings::start(timings::Stage::Encode);
+    let (count, errors) =
+        export::csv::validate(File::open(input)?, layout, dialect, |index, values| {
+            encode_record(index, values, &mut record).map_err(|e| e.to_string())
+        })?;
+    if !errors.is_empty() {
+        let lines: Vec<String> = errors.iter().map(|e| format!("  {}", e)).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has {} error{}, nothing was written:\n{}",
+                input.display(),
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" },
+                lines.join("\n")
+            ),
+        ));
+    }
+    encode.done((count * layout.record_size as u64) as usize);
+
+    println!("Writing output file {}...", output.display());
+    let write = timings::start(timings::Stage::Write);
+    let mut writer = io::BufWriter::new(guard::create(output)?);
+    let mut index = 0;
+    export::csv::read_each(File::open(input)?, layout, dialect, |values| {
+        encode_record(index, values, &mut record)?;
+        index += 1;
+        writer.write_all(&record)
+    })?;
+    writer.write_all(&footer)?;
+    finish(writer)?;
+    let size = count * layout.record_size as u64 + footer.len() as u64;
+    write.done(size as usize);
+
+    println!(
+        "Successfully compiled {} records ({} bytes) to {}.",
+        count,
+        size,
+        output.display()
+    );
+    Ok(())
+}
+
+/// Records read from a stored database at a time, so it is never held
+/// whole; the export is flushed after each.
+const DB_CHUNK_RECORDS: u64 = 4000;
+
+fn parse_shop_db(
+    input: &Path,
+    output: &Path,
+    options: &DecompileOptions,
+    layout: &Layout,
+) -> io::Result<PathBuf> {
+    let format = options.format.unwrap_or(Format::Csv);
+    let type_ext = match options.format {
+        Some(format) => format.name(),
+        None => PayloadKind::ShopDb.output_extension(),
+    };
+    let output = &output_path(output, input, type_ext, || {
+        options.ext_policy.apply(output, type_ext, false)
+    })?;
     println!(
         "Parsing Shop Database: {} -> {}",
         input.display(),
         output.display()
     );
+    if options.keep_raw {
+        save_raw(output, File::open(input)?)?;
+    }
 
     let mut file = File::open(input)?;
     let file_len = file.metadata()?.len();
-    let record_size = 456; // 0x1C8 from Node.js script
+    let record_size = layout.record_size as u64;
 
-    if file_len % record_size != 0 {
-        println!(
-            "Warning: File size is not a multiple of record size ({})!",
-            record_size
-        );
+    let item_count = file_len / record_size;
+    let trailing = file_len % record_size;
+    if trailing != 0 {
+        match options.trailing_bytes {
+            TrailingBytes::Warn => warnings::warn(format!(
+                "File size is not a multiple of record size ({})! Leaving out the last {} bytes (--trailing-bytes footer keeps them).",
+                record_size, trailing
+            )),
+            TrailingBytes::Error => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} has {} bytes after its last whole {}-byte record",
+                        input.display(),
+                        trailing,
+                        record_size
+                    ),
+                ));
+            }
+            TrailingBytes::Footer => {
+                let mut footer = Vec::new();
+                file.seek(io::SeekFrom::Start(item_count * record_size))?;
+                file.read_to_end(&mut footer)?;
+                file.rewind()?;
+                let footer_path = output.with_extension("footer");
+                guard::write(&footer_path, &footer)?;
+                println!(
+                    "Saved {} trailing bytes to {}",
+                    footer.len(),
+                    footer_path.display()
+                );
+            }
+        }
     }
 
-    let item_count = file_len / record_size;
     println!("Found {} items.", item_count);
 
-    let mut items = Vec::new();
-
-    for i in 0..item_count {
-        let offset = i * record_size;
-        file.seek(SeekFrom::Start(offset))?;
-
-        // Read Fields (Offsets from Node.js script)
-        // 0x00: Category
-        let category = file.read_u16::<LittleEndian>()?;
-        // 0x02: Type ID
-        let item_type_id = file.read_u16::<LittleEndian>()?;
-        // 0x04: Variant ID
-        let variant_id = file.read_i16::<LittleEndian>()?;
-        // 0x06: Validity
-        let validity = file.read_i16::<LittleEndian>()?;
-
-        // Skip to 0x0C: Type Flag
-        file.seek(SeekFrom::Start(offset + 0x0C))?;
-        let type_flag = file.read_u8()?;
-
-        // Skip to 0x38: Set Item ID
-        file.seek(SeekFrom::Start(offset + 0x38))?;
-        let set_item_id = file.read_i32::<LittleEndian>()?;
-
-        // Skip to 0x64: Name (100 bytes / 50 wchars)
-        file.seek(SeekFrom::Start(offset + 0x64))?;
-        let mut name_buffer = [0u8; 100];
-        file.read_exact(&mut name_buffer)?;
-
-        // Parse UTF-16LE String
-        let name = parse_utf16_string(&name_buffer);
-
-        items.push(ShopItem {
-            category,
-            item_type_id,
-            variant_id,
-            validity,
-            type_flag,
-            set_item_id,
-            name,
-        });
-    }
+    let mut reader = io::BufReader::new(file);
+    if !format.streams() {
+        // The other formats serialize every record at once.
+        let held = item_count * (record_size + (layout.fields.len() * size_of::<Value>()) as u64);
+        memory::check(
+            &format!(
+                "Exporting the database as {} (CSV and SQL are written as they are read)",
+                format.name()
+            ),
+            held,
+        )?;
+        let read = timings::start(timings::Stage::Read);
+        let mut data = Vec::new();
+        (&mut reader)
+            .take(item_count * record_size)
+            .read_to_end(&mut data)?;
+        layout.check_texts(&data)?;
+        let items = script::records(Hook::ExportRecord, layout, layout.read_records(&data), 0)?;
+        read.done(data.len());
 
-    // Write to CSV
-    let mut wtr = csv::Writer::from_path(output)?;
-    for item in items {
-        wtr.serialize(item)?;
+        // The export writes as it goes, so writing is timed with it.
+        let decode = timings::start(timings::Stage::Decode);
+        let mut writer = io::BufWriter::new(guard::create(output)?);
+        export::write_records(&mut writer, layout, &items, format, &options.export)?;
+        finish(writer)?;
+        decode.done(data.len());
+    } else {
+        let mut writer = io::BufWriter::new(guard::create(output)?);
+        let mut export = export::Streamed::new(&mut writer, layout, format, &options.export)?;
+        let mut chunk = Vec::new();
+        let mut left = item_count;
+        while left > 0 {
+            let count = left.min(DB_CHUNK_RECORDS);
+            let read = timings::start(timings::Stage::Read);
+            chunk.resize((count * record_size) as usize, 0);
+            reader.read_exact(&mut chunk)?;
+            read.done(chunk.len());
+
+            // Each record is written as soon as it is decoded, and the
+            // output flushed after every chunk.
+            let decode = timings::start(timings::Stage::Decode);
+            for (record, index) in chunk
+                .chunks_exact(layout.record_size)
+                .zip(item_count - left..)
+            {
+                layout.check_text(record, index)?;
+                let values = layout.read_record(record);
+                export.write(script::record(Hook::ExportRecord, layout, values, index)?)?;
+            }
+            export.flush()?;
+            decode.done(chunk.len());
+            left -= count;
+        }
+        export.finish()?;
+        finish(writer)?;
     }
-    wtr.flush()?;
 
     println!("Success! Dumped to {}", output.display());
-    Ok(())
-}
-
-fn parse_utf16_string(buffer: &[u8]) -> String {
-    let u16_vec: Vec<u16> = buffer
-        .chunks_exact(2)
-        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-        .take_while(|&c| c != 0) // Stop at null terminator
-        .collect();
-
-    String::from_utf16_lossy(&u16_vec).trim().to_string()
-}
-
-#[derive(Debug, Serialize)]
-struct ShopItem {
-    category: u16,
-    item_type_id: u16,
-    variant_id: i16,
-    validity: i16,
-    type_flag: u8,
-    set_item_id: i32,
-    name: String,
+    Ok(output.clone())
 }
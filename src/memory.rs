@@ -0,0 +1,51 @@
+//! `--max-memory`: a budget for what one file may hold in memory. Over
+//! it, databases are converted a chunk of records at a time, and
+//! anything that has to be held whole fails with an error instead of
+//! getting the process killed.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The budget in bytes; 0 for none.
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+
+const MB: u64 = 1024 * 1024;
+
+/// Set the budget for this run, in megabytes.
+pub fn set(megabytes: u64) {
+    LIMIT.store(megabytes.saturating_mul(MB), Ordering::Relaxed);
+}
+
+/// The budget in bytes, if one was set.
+pub fn limit() -> Option<u64> {
+    match LIMIT.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Whether holding `bytes` stays within the budget.
+pub fn fits(bytes: u64) -> bool {
+    limit().is_none_or(|limit| bytes <= limit)
+}
+
+/// Fail when `what` needs more than the budget.
+pub fn check(what: &str, bytes: u64) -> io::Result<()> {
+    if fits(bytes) {
+        return Ok(());
+    }
+    Err(exceeded(what, bytes))
+}
+
+/// The error for `what` needing at least `bytes`.
+pub fn exceeded(what: &str, bytes: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::OutOfMemory,
+        format!(
+            "{} needs at least {} MB, over the --max-memory budget of {} MB",
+            what,
+            bytes.div_ceil(MB),
+            limit().unwrap_or_default() / MB
+        ),
+    )
+}
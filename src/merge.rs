@@ -0,0 +1,235 @@
+//! Three-way merge of XML documents.
+//!
+//! Children are matched by tag name plus their `id` (or `key`, `name`)
+//! attribute, falling back to their position among same-named siblings.
+//! A side that left a node as it was in `base` takes the other side's
+//! version; nodes changed differently on both sides become conflicts.
+
+use crate::xml::{self, Document, Element, Node};
+use indexmap::IndexMap;
+
+/// Attributes that identify an element among its siblings.
+const KEY_ATTRIBUTES: &[&str] = &["id", "ID", "Id", "key", "name"];
+
+pub enum Outcome {
+    Clean(Document),
+    /// XML text with `<<<<<<<` / `=======` / `>>>>>>>` conflict markers.
+    Conflicts {
+        text: String,
+        count: usize,
+    },
+}
+
+enum Merged {
+    Node(Node),
+    /// An element with conflicts somewhere below it.
+    Element {
+        name: String,
+        attributes: IndexMap<String, String>,
+        children: Vec<Merged>,
+    },
+    Conflict {
+        ours: Option<Node>,
+        theirs: Option<Node>,
+    },
+}
+
+pub fn merge(base: &Document, ours: &Document, theirs: &Document) -> Outcome {
+    let root = merge_node(
+        &Node::Element(base.root.clone()),
+        &Node::Element(ours.root.clone()),
+        &Node::Element(theirs.root.clone()),
+    );
+    let declaration = ours.declaration.clone().or(theirs.declaration.clone());
+
+    match root {
+        Merged::Node(Node::Element(root)) => Outcome::Clean(Document { declaration, root }),
+        merged => {
+            let mut text = String::new();
+            if let Some(declaration) = &declaration {
+                text.push_str(&format!("<?xml {}?>\n", declaration));
+            }
+            let count = render(&mut text, &merged, 0);
+            Outcome::Conflicts { text, count }
+        }
+    }
+}
+
+fn merge_node(base: &Node, ours: &Node, theirs: &Node) -> Merged {
+    if ours == theirs || theirs == base {
+        return Merged::Node(ours.clone());
+    }
+    if ours == base {
+        return Merged::Node(theirs.clone());
+    }
+    match (base, ours, theirs) {
+        (Node::Element(b), Node::Element(o), Node::Element(t))
+            if b.name == o.name && o.name == t.name =>
+        {
+            merge_element(b, o, t)
+        }
+        _ => conflict(Some(ours), Some(theirs)),
+    }
+}
+
+fn merge_element(base: &Element, ours: &Element, theirs: &Element) -> Merged {
+    let mut attributes = IndexMap::new();
+    let names = ours.attributes.keys().chain(theirs.attributes.keys());
+    for name in names {
+        if attributes.contains_key(name) {
+            continue;
+        }
+        let b = base.attributes.get(name);
+        let o = ours.attributes.get(name);
+        let t = theirs.attributes.get(name);
+        let value = if o == t || t == b {
+            o
+        } else if o == b {
+            t
+        } else {
+            return conflict(
+                Some(&Node::Element(ours.clone())),
+                Some(&Node::Element(theirs.clone())),
+            );
+        };
+        if let Some(value) = value {
+            attributes.insert(name.clone(), value.clone());
+        }
+    }
+
+    let children = merge_children(&base.children, &ours.children, &theirs.children);
+    if children.iter().all(|c| matches!(c, Merged::Node(_))) {
+        let children = children
+            .into_iter()
+            .filter_map(|c| match c {
+                Merged::Node(node) => Some(node),
+                _ => None,
+            })
+            .collect();
+        return Merged::Node(Node::Element(Element {
+            name: ours.name.clone(),
+            attributes,
+            children,
+        }));
+    }
+    Merged::Element {
+        name: ours.name.clone(),
+        attributes,
+        children,
+    }
+}
+
+fn merge_children(base: &[Node], ours: &[Node], theirs: &[Node]) -> Vec<Merged> {
+    let base = keyed(base);
+    let ours = keyed(ours);
+    let theirs = keyed(theirs);
+
+    // Our order, with nodes only theirs added placed after their
+    // predecessor on that side.
+    let mut order: Vec<&(String, String)> = ours.keys().collect();
+    let mut previous: Option<&(String, String)> = None;
+    for key in theirs.keys() {
+        if !order.contains(&key) {
+            let at = previous
+                .and_then(|p| order.iter().position(|k| *k == p))
+                .map_or(0, |i| i + 1);
+            order.insert(at, key);
+        }
+        previous = Some(key);
+    }
+
+    let mut merged = Vec::with_capacity(order.len());
+    for key in order {
+        let result = match (base.get(key), ours.get(key), theirs.get(key)) {
+            (Some(b), Some(o), Some(t)) => Some(merge_node(b, o, t)),
+            (None, Some(o), Some(t)) if o == t => Some(Merged::Node((*o).clone())),
+            (None, Some(o), Some(t)) => Some(conflict(Some(o), Some(t))),
+            // Deleted on one side: fine unless the other side changed it.
+            (Some(b), Some(o), None) if b == o => None,
+            (Some(_), Some(o), None) => Some(conflict(Some(o), None)),
+            (Some(b), None, Some(t)) if b == t => None,
+            (Some(_), None, Some(t)) => Some(conflict(None, Some(t))),
+            (None, Some(node), None) | (None, None, Some(node)) => {
+                Some(Merged::Node((*node).clone()))
+            }
+            (_, None, None) => None,
+        };
+        merged.extend(result);
+    }
+    merged
+}
+
+/// Nodes by (tag, identity) so the same node can be found on every side.
+/// The identity is `@` and the key attribute's value, or `#` and the
+/// position among same-named siblings without one.
+pub fn keyed(nodes: &[Node]) -> IndexMap<(String, String), &Node> {
+    let mut counts: IndexMap<String, usize> = IndexMap::new();
+    let mut keyed = IndexMap::new();
+    for node in nodes {
+        let tag = match node {
+            Node::Text(_) => "#text".to_string(),
+            Node::Comment { .. } => "#comment".to_string(),
+            Node::Element(element) => element.name.clone(),
+        };
+        let id = match node {
+            Node::Element(element) => KEY_ATTRIBUTES
+                .iter()
+                .find_map(|a| element.attributes.get(*a))
+                .map(|id| format!("@{}", id)),
+            _ => None,
+        };
+        let id = id
+            .filter(|id| !keyed.contains_key(&(tag.clone(), id.clone())))
+            .unwrap_or_else(|| {
+                let count = counts.entry(tag.clone()).or_default();
+                *count += 1;
+                format!("#{}", count)
+            });
+        keyed.insert((tag, id), node);
+    }
+    keyed
+}
+
+fn conflict(ours: Option<&Node>, theirs: Option<&Node>) -> Merged {
+    Merged::Conflict {
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    }
+}
+
+/// Write `merged` with conflict markers, returning the number of conflicts.
+fn render(out: &mut String, merged: &Merged, depth: usize) -> usize {
+    match merged {
+        Merged::Node(node) => {
+            xml::write_node(out, node, depth);
+            0
+        }
+        Merged::Element {
+            name,
+            attributes,
+            children,
+        } => {
+            let indent = "  ".repeat(depth);
+            out.push_str(&format!(
+                "{}{}>\n",
+                indent,
+                xml::start_tag(name, attributes)
+            ));
+            let count = children.iter().map(|c| render(out, c, depth + 1)).sum();
+            out.push_str(&format!("{}</{}>\n", indent, name));
+            count
+        }
+        Merged::Conflict { ours, theirs } => {
+            out.push_str("<<<<<<< ours\n");
+            if let Some(node) = ours {
+                xml::write_node(out, node, depth);
+            }
+            out.push_str("=======\n");
+            if let Some(node) = theirs {
+                xml::write_node(out, node, depth);
+            }
+            out.push_str(">>>>>>> theirs\n");
+            1
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::compression::Compression;
+use crate::xor::XorKey;
+use indexmap::IndexMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const XML_MARKER: &str = "<!-- IDO ";
+const XML_HEADER_MARKER: &str = "<!-- IDO HEADER: ";
+const XML_END_MARKER: &str = " -->";
+
+/// Everything needed to rebuild an .ido file besides the payload itself.
+///
+/// Stored either in a `.meta` sidecar (header hex on the first line,
+/// `key: value` lines after it) or as trailing `<!-- IDO KEY: value -->`
+/// comments in decompiled XML.
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+    pub header: Option<Vec<u8>>,
+    pub compression: Option<Compression>,
+    /// Decompressed sizes of back-to-back streams, when there is more than one.
+    pub segments: Option<Vec<usize>>,
+    pub xor_key: Option<XorKey>,
+    /// Whether the header was XORed as well; stored decoded.
+    pub xor_header: Option<bool>,
+    /// Plugin that decompiled the payload, and compiles it back.
+    pub plugin: Option<String>,
+    /// Bytes after the last compressed stream, stored XOR-decoded like the
+    /// stream itself.
+    pub trailing: Option<Vec<u8>>,
+}
+
+impl Meta {
+    pub fn new(header: &[u8]) -> Self {
+        Meta {
+            header: Some(header.to_vec()),
+            ..Default::default()
+        }
+    }
+
+    /// Fill in any fields missing here from `other`.
+    pub fn or(self, other: Meta) -> Meta {
+        Meta {
+            header: self.header.or(other.header),
+            compression: self.compression.or(other.compression),
+            segments: self.segments.or(other.segments),
+            xor_key: self.xor_key.or(other.xor_key),
+            xor_header: self.xor_header.or(other.xor_header),
+            plugin: self.plugin.or(other.plugin),
+            trailing: self.trailing.or(other.trailing),
+        }
+    }
+
+    /// Extra `key, value` pairs, omitted when they match the defaults.
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = Vec::new();
+        if let Some(compression) = self.compression.filter(|&c| c != Compression::Zlib) {
+            entries.push(("compression", compression.name().to_string()));
+        }
+        if let Some(segments) = self.segments.as_ref().filter(|s| s.len() > 1) {
+            let sizes: Vec<String> = segments.iter().map(usize::to_string).collect();
+            entries.push(("segments", sizes.join(",")));
+        }
+        if let Some(key) = &self.xor_key {
+            entries.push(("xor-key", key.to_string()));
+        }
+        if self.xor_header == Some(true) {
+            entries.push(("xor-header", "true".to_string()));
+        }
+        if let Some(plugin) = &self.plugin {
+            entries.push(("plugin", plugin.clone()));
+        }
+        if let Some(trailing) = self.trailing.as_ref().filter(|t| !t.is_empty()) {
+            entries.push(("trailing", hex::encode(trailing)));
+        }
+        entries
+    }
+
+    /// All metadata as ordered `key: value` pairs, header first.
+    pub fn to_map(&self) -> IndexMap<String, String> {
+        let mut map = IndexMap::new();
+        map.insert(
+            "header".to_string(),
+            hex::encode(self.header.as_deref().unwrap_or_default()),
+        );
+        for (key, value) in self.entries() {
+            map.insert(key.to_string(), value);
+        }
+        map
+    }
+
+    /// Inverse of [`Meta::to_map`], for structured exports read back in.
+    pub fn from_map(map: &IndexMap<String, String>) -> io::Result<Meta> {
+        let mut meta = Meta::default();
+        for (key, value) in map {
+            meta.set(key, value)?;
+        }
+        Ok(meta)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        match key.trim().to_ascii_lowercase().as_str() {
+            "header" => self.header = Some(decode_hex(value, "embedded header")?),
+            "compression" => {
+                self.compression = Some(Compression::from_name(value).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown compression '{}' in metadata", value.trim()),
+                    )
+                })?)
+            }
+            "segments" => {
+                let sizes = value
+                    .split(',')
+                    .map(|n| n.trim().parse::<usize>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Invalid segment sizes '{}' in metadata: {}",
+                                value.trim(),
+                                e
+                            ),
+                        )
+                    })?;
+                self.segments = Some(sizes);
+            }
+            "xor-key" => {
+                self.xor_key = Some(value.parse().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid XOR key in metadata: {}", e),
+                    )
+                })?)
+            }
+            "xor-header" => self.xor_header = Some(value.trim() == "true"),
+            "plugin" => self.plugin = Some(value.trim().to_string()),
+            "trailing" => self.trailing = Some(decode_hex(value, "trailing bytes")?),
+            // Keys written by newer versions are ignored rather than rejected.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn read_sidecar(path: &Path) -> io::Result<Meta> {
+        Meta::parse_sidecar(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse_sidecar(content: &str) -> io::Result<Meta> {
+        let mut meta = Meta::default();
+        let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+
+        if let Some(first) = lines.next() {
+            meta.header = Some(decode_hex(first, ".meta")?);
+        }
+        for line in lines {
+            if let Some((key, value)) = line.split_once(':') {
+                meta.set(key, value)?;
+            }
+        }
+        Ok(meta)
+    }
+
+    pub fn to_sidecar(&self) -> String {
+        let mut out = hex::encode(self.header.as_deref().unwrap_or_default());
+        for (key, value) in self.entries() {
+            out.push_str(&format!("\n{}: {}", key, value));
+        }
+        out
+    }
+
+    pub fn to_xml_comments(&self) -> String {
+        let mut out = format!(
+            "{}{}{}",
+            XML_HEADER_MARKER,
+            hex::encode(self.header.as_deref().unwrap_or_default()),
+            XML_END_MARKER
+        );
+        for (key, value) in self.entries() {
+            out.push_str(&format!(
+                "\n{}{}: {}{}",
+                XML_MARKER,
+                key.to_ascii_uppercase(),
+                value,
+                XML_END_MARKER
+            ));
+        }
+        out
+    }
+
+    /// Split decompiled XML into its content and embedded metadata.
+    pub fn split_xml(xml: &str) -> io::Result<(Meta, &str)> {
+        let mut meta = Meta::default();
+
+        let Some(start_idx) = xml.rfind(XML_HEADER_MARKER) else {
+            return Ok((meta, xml));
+        };
+
+        let mut rest = &xml[start_idx..];
+        while let Some(comment) = rest.trim_start().strip_prefix(XML_MARKER) {
+            let Some(end_idx) = comment.find(XML_END_MARKER) else {
+                break;
+            };
+            if let Some((key, value)) = comment[..end_idx].split_once(':') {
+                meta.set(key, value)?;
+            }
+            rest = &comment[end_idx + XML_END_MARKER.len()..];
+        }
+
+        if meta.header.is_none() {
+            return Ok((Meta::default(), xml));
+        }
+        Ok((meta, &xml[..start_idx]))
+    }
+}
+
+fn decode_hex(value: &str, what: &str) -> io::Result<Vec<u8>> {
+    hex::decode(value.trim()).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to decode hex in {}: {}", what, e),
+        )
+    })
+}
@@ -0,0 +1,266 @@
+//! Payload handlers from plugins: dynamic libraries that detect,
+//! decompile and compile payload types idoc doesn't know, so regional
+//! client variants can be covered without changing idoc. The interface
+//! is declared in `include/idoc_plugin.h`.
+//!
+//! Plugins are the `.so`, `.dll` or `.dylib` files in the `plugin_dirs`
+//! of `idoc.toml` and in `plugins` in the user's config directory,
+//! loaded at the first decompressed payload and asked about it before
+//! idoc's own detection.
+
+use crate::config;
+use crate::ffi::IdocBuffer;
+use crate::warnings;
+use libloading::Library;
+use std::ffi::{CStr, c_char, c_int};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The `IDOC_PLUGIN_API` this build speaks.
+pub const API_VERSION: u32 = 1;
+
+type Text = unsafe extern "C" fn() -> *const c_char;
+type Detect = unsafe extern "C" fn(*const u8, usize) -> c_int;
+type Convert = unsafe extern "C" fn(*const u8, usize, *mut IdocBuffer) -> c_int;
+type Free = unsafe extern "C" fn(IdocBuffer);
+
+/// A loaded plugin. The functions stay valid while `_library` is held.
+pub struct Plugin {
+    pub name: String,
+    pub extension: String,
+    pub path: PathBuf,
+    detect: Detect,
+    decompile: Convert,
+    compile: Convert,
+    free: Free,
+    last_error: Option<Text>,
+    _library: Library,
+}
+
+/// A library in a plugins directory that could not be used.
+pub struct Rejected {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+struct Loaded {
+    plugins: Vec<Plugin>,
+    rejected: Vec<Rejected>,
+}
+
+static LOADED: OnceLock<Loaded> = OnceLock::new();
+
+/// Where plugins are looked for, in order.
+pub fn dirs() -> Vec<PathBuf> {
+    let mut dirs = config::get().plugin_dirs.clone();
+    dirs.extend(config::user_dir().map(|dir| dir.join("plugins")));
+    dirs
+}
+
+fn loaded() -> &'static Loaded {
+    LOADED.get_or_init(|| {
+        let mut loaded = Loaded {
+            plugins: Vec::new(),
+            rejected: Vec::new(),
+        };
+        for path in libraries() {
+            let error = match Plugin::load(&path) {
+                Ok(plugin) if loaded.plugins.iter().any(|p| p.name == plugin.name) => {
+                    format!("another plugin is already named '{}'", plugin.name)
+                }
+                Ok(plugin) => {
+                    loaded.plugins.push(plugin);
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            };
+            warnings::warn(format!("Skipping plugin {}: {}", path.display(), error));
+            loaded.rejected.push(Rejected { path, error });
+        }
+        loaded
+    })
+}
+
+/// Dynamic libraries in the plugin directories, each directory sorted by
+/// name.
+fn libraries() -> Vec<PathBuf> {
+    let mut libraries = Vec::new();
+    for dir in dirs() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ["so", "dll", "dylib"].contains(&ext))
+            })
+            .collect();
+        found.sort();
+        libraries.extend(found);
+    }
+    libraries
+}
+
+/// Every plugin that loaded, in the order they are asked.
+pub fn all() -> &'static [Plugin] {
+    &loaded().plugins
+}
+
+/// Libraries that were found but could not be used.
+pub fn rejected() -> &'static [Rejected] {
+    &loaded().rejected
+}
+
+/// The first plugin that handles `payload`.
+pub fn detect(payload: &[u8]) -> Option<&'static Plugin> {
+    all().iter().find(|plugin| plugin.handles(payload))
+}
+
+/// The plugin called `name`, as recorded in a .meta file.
+pub fn find(name: &str) -> io::Result<&'static Plugin> {
+    all().iter().find(|plugin| plugin.name == name).ok_or_else(|| {
+        let dirs: Vec<String> = dirs().iter().map(|d| d.display().to_string()).collect();
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "The payload was decompiled by plugin '{}', which is not installed (looked in {})",
+                name,
+                dirs.join(", ")
+            ),
+        )
+    })
+}
+
+impl Plugin {
+    fn load(path: &Path) -> io::Result<Plugin> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        // SAFETY: loading runs the library's initializers; plugins are
+        // trusted like any program the user installs.
+        let library = unsafe { Library::new(path) }.map_err(|e| {
+            // The loader's own message is in the source.
+            match std::error::Error::source(&e) {
+                Some(source) => invalid(source.to_string()),
+                None => invalid(e.to_string()),
+            }
+        })?;
+        // SAFETY: the symbols are declared with these types in
+        // include/idoc_plugin.h.
+        unsafe {
+            let api = *library
+                .get::<unsafe extern "C" fn() -> u32>(b"idoc_plugin_api")
+                .map_err(|e| invalid(e.to_string()))?;
+            if api() != API_VERSION {
+                return Err(invalid(format!(
+                    "written for plugin API {}, idoc speaks {}",
+                    api(),
+                    API_VERSION
+                )));
+            }
+            let symbol = |name: &str| invalid(format!("does not export {}", name));
+            let name = *library
+                .get::<Text>(b"idoc_plugin_name")
+                .map_err(|_| symbol("idoc_plugin_name"))?;
+            let extension = *library
+                .get::<Text>(b"idoc_plugin_extension")
+                .map_err(|_| symbol("idoc_plugin_extension"))?;
+            let name = text(name()).ok_or_else(|| invalid("has no name".to_string()))?;
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(invalid(format!("has an invalid name '{}'", name)));
+            }
+            let extension = text(extension()).unwrap_or_else(|| "bin".to_string());
+            // Joined to output paths, so it must not lead out of them.
+            if extension.is_empty() || extension.contains(['/', '\\']) || extension.contains("..") {
+                return Err(invalid(format!("has an invalid extension '{}'", extension)));
+            }
+            Ok(Plugin {
+                extension,
+                detect: *library
+                    .get::<Detect>(b"idoc_plugin_detect")
+                    .map_err(|_| symbol("idoc_plugin_detect"))?,
+                decompile: *library
+                    .get::<Convert>(b"idoc_plugin_decompile")
+                    .map_err(|_| symbol("idoc_plugin_decompile"))?,
+                compile: *library
+                    .get::<Convert>(b"idoc_plugin_compile")
+                    .map_err(|_| symbol("idoc_plugin_compile"))?,
+                free: *library
+                    .get::<Free>(b"idoc_plugin_free")
+                    .map_err(|_| symbol("idoc_plugin_free"))?,
+                last_error: library
+                    .get::<Text>(b"idoc_plugin_last_error")
+                    .ok()
+                    .map(|f| *f),
+                name,
+                path: path.to_path_buf(),
+                _library: library,
+            })
+        }
+    }
+
+    pub fn handles(&self, payload: &[u8]) -> bool {
+        // SAFETY: `payload` is valid for its length during the call.
+        unsafe { (self.detect)(payload.as_ptr(), payload.len()) != 0 }
+    }
+
+    /// The payload in the plugin's decompiled form.
+    pub fn decompile(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        self.convert(self.decompile, "decompile", payload)
+    }
+
+    /// The decompiled form back to the payload.
+    pub fn compile(&self, input: &[u8]) -> io::Result<Vec<u8>> {
+        self.convert(self.compile, "compile", input)
+    }
+
+    fn convert(&self, convert: Convert, what: &str, input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = IdocBuffer {
+            data: std::ptr::null_mut(),
+            len: 0,
+        };
+        // SAFETY: `input` is valid for its length and `out` is writable;
+        // a buffer the plugin fills is copied, then handed back to it.
+        unsafe {
+            if convert(input.as_ptr(), input.len(), &mut out) != 0 {
+                let reason = self
+                    .last_error
+                    .and_then(|last_error| text(last_error()))
+                    .unwrap_or_else(|| "no reason given".to_string());
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Plugin '{}' could not {}: {}", self.name, what, reason),
+                ));
+            }
+            let output = if out.data.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(out.data, out.len).to_vec()
+            };
+            (self.free)(out);
+            Ok(output)
+        }
+    }
+}
+
+/// A string returned by a plugin, if it returned one.
+///
+/// # Safety
+///
+/// `text` must be null or point to a NUL-terminated string.
+unsafe fn text(text: *const c_char) -> Option<String> {
+    if text.is_null() {
+        return None;
+    }
+    // SAFETY: guaranteed by the caller.
+    Some(
+        unsafe { CStr::from_ptr(text) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
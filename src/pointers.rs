@@ -0,0 +1,227 @@
+//! Values in a payload that look like offsets into the same payload:
+//! offset tables, and record fields pointing at other records.
+
+use crate::layout::infer;
+use std::fmt::Write;
+
+pub const DEFAULT_WIDTH: usize = 4;
+/// Fewest consecutive entries reported as an offset table.
+const MIN_ENTRIES: usize = 4;
+/// Share of a column's non-zero values that must point at a record.
+const MIN_POINTERS: f64 = 0.9;
+
+/// Parse a `--width` value: 2, 4 or 8 bytes.
+pub fn parse_width(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(width @ (2 | 4 | 8)) => Ok(width),
+        _ => Err(format!("'{}' is not 2, 4 or 8", s)),
+    }
+}
+
+fn read(data: &[u8], offset: usize, width: usize) -> u64 {
+    data[offset..offset + width]
+        .iter()
+        .rev()
+        .fold(0, |value, &b| value << 8 | u64::from(b))
+}
+
+/// A run of aligned words whose values rise through the payload.
+pub struct Table {
+    pub start: usize,
+    pub entries: usize,
+    pub targets: Vec<usize>,
+}
+
+impl Table {
+    pub fn end(&self, width: usize) -> usize {
+        self.start + self.entries * width
+    }
+}
+
+/// Runs of at least [`MIN_ENTRIES`] aligned words that are in bounds,
+/// never decrease, point outside the run itself and are on average
+/// at least a word apart, which counters and IDs rarely are.
+pub fn tables(data: &[u8], width: usize) -> Vec<Table> {
+    let mut tables = Vec::new();
+    let mut run: Vec<usize> = Vec::new();
+    let mut start = 0;
+    let words = data.len() / width;
+    for index in 0..=words {
+        let offset = index * width;
+        let value = (index < words).then(|| read(data, offset, width));
+        let continues = value.is_some_and(|v| {
+            let v = v as usize;
+            v < data.len() && run.last().is_none_or(|&last| v >= last) && (v != 0 || run.is_empty())
+        });
+        if continues {
+            if run.is_empty() {
+                start = offset;
+            }
+            run.push(value.unwrap_or_default() as usize);
+            continue;
+        }
+        let table = Table {
+            start,
+            entries: run.len(),
+            targets: std::mem::take(&mut run),
+        };
+        if is_table(&table, width) {
+            tables.push(table);
+        }
+        // The word that broke the run may start the next one.
+        if let Some(v) = value
+            && (v as usize) < data.len()
+        {
+            start = offset;
+            run.push(v as usize);
+        }
+    }
+    tables
+}
+
+fn is_table(table: &Table, width: usize) -> bool {
+    let targets = &table.targets;
+    let (Some(&first), Some(&last)) = (targets.first(), targets.last()) else {
+        return false;
+    };
+    let end = table.end(width);
+    targets.len() >= MIN_ENTRIES
+        && targets.iter().all(|&t| t < table.start || t >= end)
+        && (last - first) >= width * (targets.len() - 1)
+}
+
+/// A field that holds offsets of other records.
+pub struct Column {
+    pub offset: usize,
+    pub pointers: usize,
+    /// Whether the values point at the same field of the other record
+    /// rather than its start.
+    pub same_field: bool,
+}
+
+/// Aligned columns of `stride`-byte records whose non-zero values land
+/// on record starts, or on the same column of another record.
+pub fn record_columns(data: &[u8], stride: usize, width: usize) -> Vec<Column> {
+    let records: Vec<&[u8]> = data.chunks_exact(stride).collect();
+    let mut columns = Vec::new();
+    for offset in (0..stride.saturating_sub(width - 1)).step_by(width) {
+        let values: Vec<usize> = records
+            .iter()
+            .map(|r| read(r, offset, width) as usize)
+            .filter(|&v| v != 0)
+            .collect();
+        let mut distinct = values.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() < 2 {
+            continue;
+        }
+        let landing = |column: usize| {
+            values
+                .iter()
+                .filter(|&&v| v < data.len() && v % stride == column)
+                .count()
+        };
+        let enough = |hits: usize| hits as f64 >= MIN_POINTERS * values.len() as f64;
+        let (starts, same) = (landing(0), landing(offset));
+        if offset != 0 && enough(same) {
+            columns.push(Column {
+                offset,
+                pointers: same,
+                same_field: true,
+            });
+        } else if enough(starts) {
+            columns.push(Column {
+                offset,
+                pointers: starts,
+                same_field: false,
+            });
+        }
+    }
+    columns
+}
+
+/// Whether `target` starts a string: text after a NUL or the start.
+fn starts_string(data: &[u8], target: usize) -> bool {
+    let text = |b: u8| (0x20..=0x7E).contains(&b) || b >= 0xA1;
+    data.get(target).is_some_and(|&b| text(b)) && (target == 0 || data[target - 1] == 0)
+}
+
+/// The report printed by `idoc pointers`.
+pub fn report(data: &[u8], width: usize) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Payload: {} bytes, scanning {}-byte little-endian words",
+        data.len(),
+        width
+    )
+    .unwrap();
+    let stride = infer::detect_stride(data);
+
+    let tables = tables(data, width);
+    if tables.is_empty() {
+        writeln!(out, "No offset tables").unwrap();
+    } else {
+        writeln!(out, "Offset tables:").unwrap();
+    }
+    for table in &tables {
+        let strings = table
+            .targets
+            .iter()
+            .filter(|&&t| starts_string(data, t))
+            .count();
+        let mut lands = vec![format!("{} on string starts", strings)];
+        if let Some(stride) = stride {
+            let records = table.targets.iter().filter(|&&t| t % stride == 0).count();
+            lands.push(format!("{} on record starts", records));
+        }
+        writeln!(
+            out,
+            "  0x{:08X}-0x{:08X}  {} entries -> 0x{:X}..0x{:X}; {}",
+            table.start,
+            table.end(width),
+            table.entries,
+            table.targets[0],
+            table.targets[table.entries - 1],
+            lands.join(", ")
+        )
+        .unwrap();
+    }
+
+    match stride {
+        Some(stride) => {
+            let columns = record_columns(data, stride, width);
+            if columns.is_empty() {
+                writeln!(
+                    out,
+                    "Records of 0x{:X} bytes: no fields point at other records",
+                    stride
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "Records of 0x{:X} bytes, fields pointing at records:",
+                    stride
+                )
+                .unwrap();
+            }
+            for column in columns {
+                let target = if column.same_field {
+                    "the same field of another record (linked list?)"
+                } else {
+                    "record starts"
+                };
+                writeln!(
+                    out,
+                    "  +0x{:03X}  {} values -> {}",
+                    column.offset, column.pointers, target
+                )
+                .unwrap();
+            }
+        }
+        None => writeln!(out, "No repeating record size").unwrap(),
+    }
+    out
+}
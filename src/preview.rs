@@ -0,0 +1,114 @@
+//! `idoc head` / `idoc tail`: the first or last records of a database,
+//! read and parsed without touching the rest.
+
+use crate::ido::{self, DecodeOptions, IdoFile, PayloadKind};
+use crate::layout::{Layout, Value};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub enum End {
+    Head,
+    Tail,
+}
+
+pub struct Preview {
+    /// Records with their indices in the database.
+    pub records: Vec<(u64, Vec<Value>)>,
+    pub total: u64,
+}
+
+/// Up to `count` records from one end of the database.
+pub fn records(
+    path: &Path,
+    layout: &Layout,
+    count: usize,
+    end: End,
+    options: &DecodeOptions,
+) -> io::Result<Preview> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; ido::HEADER_SIZE];
+    let stored = match file.read_exact(&mut header) {
+        Ok(()) => {
+            options.decode_header(&mut header);
+            ido::sniff_header(&header) == Some(PayloadKind::ShopDb)
+        }
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+
+    // Stored databases are read straight from the file; anything else has
+    // to be decompressed first.
+    let (data, total, first) = if stored {
+        let record_size = layout.record_size as u64;
+        let total = file.metadata()?.len() / record_size;
+        let count = (count as u64).min(total);
+        let first = match end {
+            End::Head => 0,
+            End::Tail => total - count,
+        };
+        file.seek(SeekFrom::Start(first * record_size))?;
+        let mut data = vec![0u8; count as usize * layout.record_size];
+        file.read_exact(&mut data)?;
+        (data, total, first)
+    } else {
+        let decoded = IdoFile::read(path, options)?;
+        if decoded.kind == PayloadKind::Xml {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} holds XML, not database records", path.display()),
+            ));
+        }
+        let total = decoded.data.len() / layout.record_size;
+        let count = count.min(total);
+        let first = match end {
+            End::Head => 0,
+            End::Tail => total - count,
+        };
+        let data =
+            decoded.data[first * layout.record_size..(first + count) * layout.record_size].to_vec();
+        (data, total as u64, first as u64)
+    };
+
+    let records = layout
+        .read_records(&data)
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| (first + i as u64, record))
+        .collect();
+    Ok(Preview { records, total })
+}
+
+/// Records as an aligned table with a leading `#` index column.
+pub fn table(layout: &Layout, records: &[(u64, Vec<Value>)]) -> String {
+    let mut rows: Vec<Vec<String>> = vec![
+        std::iter::once("#".to_string())
+            .chain(layout.fields.iter().map(|f| f.name.clone()))
+            .collect(),
+    ];
+    rows.extend(records.iter().map(|(index, record)| {
+        std::iter::once(index.to_string())
+            .chain(record.iter().map(Value::to_string))
+            .collect()
+    }));
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end()).unwrap();
+    }
+    out
+}
@@ -0,0 +1,74 @@
+//! The `idoc` Python module, mirroring the C functions in [`crate::ffi`]
+//! with Python types. Built with maturin and the `python` feature.
+
+use crate::ido::PayloadKind;
+use crate::layout::{Layout, Value};
+use crate::text;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList, PyString};
+use std::io;
+
+/// Malformed input raises `ValueError`, anything else `OSError`.
+fn error(e: io::Error) -> PyErr {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => {
+            PyValueError::new_err(e.to_string())
+        }
+        _ => PyOSError::new_err(e.to_string()),
+    }
+}
+
+/// Decompile a whole .ido file: `str` XML with the metadata comments that
+/// compile it back, `str` CSV for shop databases, and the raw payload as
+/// `bytes` for anything else.
+#[pyfunction]
+fn decompile<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyAny>> {
+    match text::decompile(data.to_vec()).map_err(error)? {
+        (PayloadKind::Xml | PayloadKind::ShopDb, text) => {
+            let text = String::from_utf8(text).expect("decompiled text is UTF-8");
+            Ok(PyString::new(py, &text).into_any())
+        }
+        (_, payload) => Ok(PyBytes::new(py, &payload).into_any()),
+    }
+}
+
+/// Compile decompiled XML, with its metadata comments, back into a .ido
+/// file.
+#[pyfunction]
+fn compile<'py>(py: Python<'py>, xml: &str) -> PyResult<Bound<'py, PyBytes>> {
+    let file = text::compile(xml).map_err(error)?;
+    Ok(PyBytes::new(py, &file))
+}
+
+/// Parse a stored shop database into one dict per record, keyed by field
+/// name.
+#[pyfunction]
+fn parse_shopdb<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyList>> {
+    let layout = Layout::builtin("shop").expect("shop layout is built in");
+    layout.check_whole(data.len()).map_err(error)?;
+    let records = PyList::empty(py);
+    for record in data.chunks_exact(layout.record_size) {
+        let dict = PyDict::new(py);
+        for (field, value) in layout.fields.iter().zip(layout.read_record(record)) {
+            let name = field.name.as_str();
+            match value {
+                Value::Int(v) => dict.set_item(name, v)?,
+                Value::Float(v) => dict.set_item(name, v)?,
+                Value::Text(v) => dict.set_item(name, v)?,
+                Value::Bytes(v) => dict.set_item(name, PyBytes::new(py, &v))?,
+            }
+        }
+        records.append(dict)?;
+    }
+    Ok(records)
+}
+
+#[pymodule]
+#[pyo3(name = "idoc")]
+fn idoc_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(decompile, m)?)?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_shopdb, m)?)?;
+    Ok(())
+}
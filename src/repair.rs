@@ -0,0 +1,128 @@
+//! `idoc repair`: find the zlib stream of a file whose header was shifted
+//! by a few bytes, and rebuild the file around it.
+
+use crate::compression::{self, Compression, Decompressed};
+use crate::header;
+use crate::ido::{self, HEADER_SIZE};
+use std::io;
+use std::path::Path;
+
+/// How far from the expected header end a stream start is looked for.
+pub const MAX_SHIFT: usize = 0x1000;
+
+/// A cleaned file and what was done to get it.
+pub struct Repair {
+    pub bytes: Vec<u8>,
+    pub notes: Vec<String>,
+}
+
+/// Why a file was left alone.
+pub enum Outcome {
+    Repaired(Repair),
+    /// The stream starts where it should and decodes, with whichever
+    /// wrapper it uses.
+    Intact,
+    /// Stored uncompressed; nothing to look for.
+    Stored,
+    NotFound,
+}
+
+/// Look for the stream nearest the expected header end whose zlib
+/// checksum verifies, and rebuild the file with a full header in front of
+/// it and nothing after it.
+pub fn repair(path: &Path, bytes: &[u8]) -> io::Result<Outcome> {
+    if bytes.len() >= HEADER_SIZE && ido::sniff_header(&bytes[..HEADER_SIZE]).is_some() {
+        return Ok(Outcome::Stored);
+    }
+    if bytes.len() > HEADER_SIZE
+        && let Ok(decompressed) = compression::decompress(&bytes[HEADER_SIZE..])
+        && decompressed.trailing == 0
+    {
+        return Ok(Outcome::Intact);
+    }
+
+    let end = bytes.len().min(HEADER_SIZE + MAX_SHIFT);
+    let mut starts: Vec<usize> = (HEADER_SIZE.saturating_sub(MAX_SHIFT)..end)
+        .filter(|&start| compression::is_zlib_header(&bytes[start..]))
+        .collect();
+    starts.sort_by_key(|&start| start.abs_diff(HEADER_SIZE));
+    let Some((start, stream)) = starts.into_iter().find_map(|start| {
+        compression::decompress_with(&bytes[start..], Compression::Zlib)
+            .ok()
+            .map(|stream| (start, stream))
+    }) else {
+        return Ok(Outcome::NotFound);
+    };
+    rebuild(path, bytes, start, stream).map(Outcome::Repaired)
+}
+
+fn rebuild(path: &Path, bytes: &[u8], start: usize, stream: Decompressed) -> io::Result<Repair> {
+    let layout = header::layout()?;
+    let mut notes = vec![format!(
+        "Found a zlib stream at 0x{:X} ({:+} bytes from the expected 0x{:X}): {} streams, {} bytes decompressed, checksums verified",
+        start,
+        start as i64 - HEADER_SIZE as i64,
+        HEADER_SIZE,
+        stream.segments.len(),
+        stream.data.len()
+    )];
+
+    let known = header::known_for(path);
+    let mut header = if start >= HEADER_SIZE {
+        // Either junk was prepended, or inserted between header and
+        // stream. A known header for the file settles which; otherwise
+        // prepended bytes are the more common damage.
+        let before = &bytes[start - HEADER_SIZE..start];
+        let at_start = &bytes[..HEADER_SIZE];
+        let inserted = known.is_some_and(|known| {
+            header::differences(&layout, &known.header, at_start).len()
+                < header::differences(&layout, &known.header, before).len()
+        });
+        let extra = start - HEADER_SIZE;
+        if inserted {
+            notes.push(format!(
+                "Header: keeping the one at the start; dropping {} bytes between it and the stream",
+                extra
+            ));
+            at_start.to_vec()
+        } else {
+            if extra > 0 {
+                notes.push(format!(
+                    "Header: taking the 0x{:X} bytes before the stream; dropping {} bytes in front of it",
+                    HEADER_SIZE, extra
+                ));
+            }
+            before.to_vec()
+        }
+    } else if let Some(known) = known {
+        notes.push(format!(
+            "Header: only {} bytes before the stream; using known header '{}'",
+            start, known.name
+        ));
+        known.header.clone()
+    } else {
+        notes.push(format!(
+            "Header: only {} bytes before the stream; padded with {} zero bytes, check the fields",
+            start,
+            HEADER_SIZE - start
+        ));
+        let mut header = bytes[..start].to_vec();
+        header.resize(HEADER_SIZE, 0);
+        header
+    };
+
+    let stream_end = bytes.len() - stream.trailing;
+    if stream.trailing > 0 {
+        notes.push(format!(
+            "Dropping {} trailing bytes after the stream",
+            stream.trailing
+        ));
+    }
+    let compressed = &bytes[start..stream_end];
+    header::fill_sizes(&layout, &mut header, compressed.len(), stream.data.len());
+    header.extend_from_slice(compressed);
+    Ok(Repair {
+        bytes: header,
+        notes,
+    })
+}
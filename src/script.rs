@@ -0,0 +1,288 @@
+//! `--script`: rhai scripts that transform what passes through decompile
+//! and compile, for one-off edits such as adjusting every price. A
+//! script defines any of these functions, each returning what it was
+//! given, changed:
+//!
+//! - `decompile_xml(text)`: the XML text decompile is about to write
+//! - `compile_xml(text)`: the XML text compile is about to encode
+//! - `export_record(record)`: each database record being exported
+//! - `import_record(record)`: each database record being compiled
+//!
+//! Records are maps of field name to value. Several scripts run in the
+//! order given, each on the previous one's result. Needs the `scripting`
+//! feature.
+
+use crate::layout::{Layout, Value};
+use std::borrow::Cow;
+use std::io;
+use std::path::PathBuf;
+
+/// Where in a conversion a script function is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    DecompileXml,
+    CompileXml,
+    ExportRecord,
+    ImportRecord,
+}
+
+impl Hook {
+    /// Name of the script function called.
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::DecompileXml => "decompile_xml",
+            Hook::CompileXml => "compile_xml",
+            Hook::ExportRecord => "export_record",
+            Hook::ImportRecord => "import_record",
+        }
+    }
+}
+
+/// The scripts compiled by [`load`].
+#[cfg(feature = "scripting")]
+mod engine {
+    use super::Hook;
+    use crate::layout::{Layout, Value};
+    use rhai::{AST, Dynamic, Engine, Map, Scope};
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+
+    struct Script {
+        path: PathBuf,
+        ast: AST,
+    }
+
+    struct Scripts {
+        engine: Engine,
+        scripts: Vec<Script>,
+    }
+
+    static SCRIPTS: OnceLock<Scripts> = OnceLock::new();
+
+    pub fn load(paths: &[PathBuf]) -> io::Result<()> {
+        let engine = Engine::new();
+        let scripts = paths
+            .iter()
+            .map(|path| {
+                let ast = engine.compile_file(path.clone()).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid script {}: {}", path.display(), e),
+                    )
+                })?;
+                Ok(Script {
+                    path: path.clone(),
+                    ast,
+                })
+            })
+            .collect::<io::Result<Vec<Script>>>()?;
+        let _ = SCRIPTS.set(Scripts { engine, scripts });
+        Ok(())
+    }
+
+    pub fn defines(hook: Hook) -> bool {
+        SCRIPTS
+            .get()
+            .is_some_and(|scripts| scripts.scripts.iter().any(|script| script.defines(hook)))
+    }
+
+    /// Run `value` through each script defining `hook`, in order.
+    fn call(hook: Hook, mut value: Dynamic) -> io::Result<Dynamic> {
+        let Some(scripts) = SCRIPTS.get() else {
+            return Ok(value);
+        };
+        for script in scripts.scripts.iter().filter(|script| script.defines(hook)) {
+            value = scripts
+                .engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &script.ast, hook.name(), (value,))
+                .map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} in {}: {}", hook.name(), script.path.display(), e),
+                    )
+                })?;
+        }
+        Ok(value)
+    }
+
+    pub fn xml(hook: Hook, text: String) -> io::Result<String> {
+        let returned = call(hook, Dynamic::from(text))?;
+        let type_name = returned.type_name();
+        returned.into_string().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} returned {} instead of text", hook.name(), type_name),
+            )
+        })
+    }
+
+    pub fn record(hook: Hook, layout: &Layout, values: Vec<Value>) -> Result<Vec<Value>, String> {
+        let returned = call(hook, to_map(layout, values.clone())).map_err(|e| e.to_string())?;
+        from_map(layout, values, returned).map_err(|e| format!("{} {}", hook.name(), e))
+    }
+
+    impl Script {
+        fn defines(&self, hook: Hook) -> bool {
+            self.ast
+                .iter_functions()
+                .any(|f| f.name == hook.name() && f.params.len() == 1)
+        }
+    }
+
+    fn to_map(layout: &Layout, values: Vec<Value>) -> Dynamic {
+        let map: Map = layout
+            .fields
+            .iter()
+            .zip(values)
+            .map(|(field, value)| {
+                let value = match value {
+                    Value::Int(v) => Dynamic::from(v),
+                    Value::Float(v) => Dynamic::from(v as f64),
+                    Value::Text(v) => Dynamic::from(v),
+                    Value::Bytes(v) => Dynamic::from_blob(v),
+                };
+                (field.name.as_str().into(), value)
+            })
+            .collect();
+        Dynamic::from_map(map)
+    }
+
+    /// The record a script returned, each field converted to the kind
+    /// of value it had. Fields the script left out keep their value.
+    fn from_map(
+        layout: &Layout,
+        mut values: Vec<Value>,
+        returned: Dynamic,
+    ) -> Result<Vec<Value>, String> {
+        let type_name = returned.type_name();
+        let mut map = returned
+            .try_cast::<Map>()
+            .ok_or_else(|| format!("returned {} instead of a record map", type_name))?;
+        for (field, value) in layout.fields.iter().zip(values.iter_mut()) {
+            let Some(new) = map.remove(field.name.as_str()) else {
+                continue;
+            };
+            let type_name = new.type_name();
+            let converted = match value {
+                Value::Int(_) => new
+                    .as_int()
+                    .ok()
+                    .or_else(|| {
+                        new.as_float()
+                            .ok()
+                            .filter(|f| f.is_finite())
+                            .map(|f| f.round() as i64)
+                    })
+                    .map(Value::Int),
+                Value::Float(_) => new
+                    .as_float()
+                    .ok()
+                    .or_else(|| new.as_int().ok().map(|i| i as f64))
+                    .map(|f| Value::Float(f as f32)),
+                Value::Text(_) => new.into_string().ok().map(Value::Text),
+                Value::Bytes(_) => new.into_blob().ok().map(Value::Bytes),
+            };
+            *value = converted.ok_or_else(|| {
+                format!(
+                    "set {} ({}) to {}",
+                    field.name,
+                    field.kind.name(),
+                    type_name
+                )
+            })?;
+        }
+        if let Some(unknown) = map.keys().next() {
+            return Err(format!(
+                "set {}, which {} records do not have",
+                unknown, layout.name
+            ));
+        }
+        Ok(values)
+    }
+}
+
+/// The scripts compiled by [`load`]; a stand-in that has none in builds
+/// without the `scripting` feature.
+#[cfg(not(feature = "scripting"))]
+mod engine {
+    use super::Hook;
+    use crate::layout::{Layout, Value};
+    use std::io;
+    use std::path::PathBuf;
+
+    pub fn load(_: &[PathBuf]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--script needs idoc built with the 'scripting' feature",
+        ))
+    }
+
+    pub fn defines(_: Hook) -> bool {
+        false
+    }
+
+    pub fn xml(_: Hook, text: String) -> io::Result<String> {
+        Ok(text)
+    }
+
+    pub fn record(_: Hook, _: &Layout, values: Vec<Value>) -> Result<Vec<Value>, String> {
+        Ok(values)
+    }
+}
+
+/// Compile the scripts to run for this process. Call once at startup.
+pub fn load(paths: &[PathBuf]) -> io::Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    engine::load(paths)
+}
+
+/// Whether a loaded script defines `hook`.
+pub fn defines(hook: Hook) -> bool {
+    engine::defines(hook)
+}
+
+/// `text` as the scripts defining `hook` leave it.
+pub fn xml(hook: Hook, text: Cow<'_, str>) -> io::Result<Cow<'_, str>> {
+    if !defines(hook) {
+        return Ok(text);
+    }
+    engine::xml(hook, text.into_owned()).map(Cow::Owned)
+}
+
+/// Record `index` of `layout` as the scripts defining `hook` leave it.
+pub fn record(
+    hook: Hook,
+    layout: &Layout,
+    values: Vec<Value>,
+    index: u64,
+) -> io::Result<Vec<Value>> {
+    if !defines(hook) {
+        return Ok(values);
+    }
+    engine::record(hook, layout, values).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("record {}: {}", index, e),
+        )
+    })
+}
+
+/// [`record`] for consecutive records, the first of them `first_index`.
+pub fn records(
+    hook: Hook,
+    layout: &Layout,
+    records: Vec<Vec<Value>>,
+    first_index: u64,
+) -> io::Result<Vec<Vec<Value>>> {
+    if !defines(hook) {
+        return Ok(records);
+    }
+    records
+        .into_iter()
+        .zip(first_index..)
+        .map(|(values, index)| record(hook, layout, values, index))
+        .collect()
+}
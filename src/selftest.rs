@@ -0,0 +1,200 @@
+//! `idoc selftest`: round-trip every file of a sample directory the way a
+//! user would edit it, to show whether idoc is safe on a client version.
+
+use crate::export::{self, CsvDialect};
+use crate::ido::{self, DecodeOptions, IdoFile, PayloadKind};
+use crate::meta::Meta;
+use crate::text;
+use crate::walk;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How a file came back from its round trip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Rebuilt byte for byte.
+    Identical,
+    /// The payload came back, but recompressing it gave other bytes.
+    SamePayload,
+    Differs(String),
+    /// A step of the round trip failed.
+    Failed(String),
+    /// Not decodable in the first place, so nothing to round-trip.
+    Skipped(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Identical | Outcome::SamePayload)
+    }
+}
+
+pub struct Result {
+    pub path: String,
+    pub kind: &'static str,
+    pub outcome: Outcome,
+}
+
+/// Round-trip every .ido below `dir`.
+pub fn run(dir: &Path) -> io::Result<Vec<Result>> {
+    Ok(walk::ido_files(dir)?
+        .iter()
+        .map(|path| {
+            let (kind, outcome) = round_trip(path);
+            Result {
+                path: walk::relative(path, dir),
+                kind,
+                outcome,
+            }
+        })
+        .collect())
+}
+
+/// Decompile and compile one file through the form decompile would write
+/// it in: XML text, CSV for databases, or raw data with a `.meta`.
+pub fn round_trip(path: &Path) -> (&'static str, Outcome) {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return ("error", Outcome::Skipped(e.to_string())),
+    };
+    let file = match IdoFile::decode(bytes.clone(), &DecodeOptions::default()) {
+        Ok(file) => file,
+        Err(e) => return ("error", Outcome::Skipped(e.to_string())),
+    };
+    let outcome = match rebuild(&file) {
+        Ok(rebuilt) if rebuilt == bytes => Outcome::Identical,
+        Ok(rebuilt) => compare(&file, &bytes, &rebuilt),
+        Err(e) => Outcome::Failed(e.to_string()),
+    };
+    (file.kind.name(), outcome)
+}
+
+/// The file compiled back from what decompile writes for it.
+pub fn rebuild(file: &IdoFile) -> io::Result<Vec<u8>> {
+    match file.kind {
+        PayloadKind::Xml => rebuild_xml(file),
+        PayloadKind::ShopDb => rebuild_db(file),
+        kind if kind.is_raw() => rebuild_raw(file),
+        _ => file.encode(),
+    }
+}
+
+fn rebuild_xml(file: &IdoFile) -> io::Result<Vec<u8>> {
+    let xml = text::render(file)?;
+    text::compile_xml(&xml)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "decompiled XML lost its header comment",
+        )
+    })
+}
+
+fn rebuild_db(file: &IdoFile) -> io::Result<Vec<u8>> {
+    let layout = text::shop_layout();
+    let csv = text::render(file)?;
+    let records = export::csv::read(csv.as_bytes(), &layout, &CsvDialect::default())?;
+    layout.write_records(&records)
+}
+
+fn rebuild_raw(file: &IdoFile) -> io::Result<Vec<u8>> {
+    let sidecar = file.meta.as_ref().map(Meta::to_sidecar).unwrap_or_default();
+    let meta = Meta::parse_sidecar(&sidecar)?;
+    ido::encode_payload(&meta, &file.data)
+}
+
+/// Why a rebuilt file differs from the original.
+fn compare(original: &IdoFile, bytes: &[u8], rebuilt: &[u8]) -> Outcome {
+    // Stored files have no compression that could explain a difference.
+    if original.meta.is_some() {
+        match IdoFile::decode(rebuilt.to_vec(), &DecodeOptions::default()) {
+            Ok(again) if again.data == original.data => return Outcome::SamePayload,
+            Ok(_) => {}
+            Err(e) => return Outcome::Differs(format!("rebuilt file does not decode: {}", e)),
+        }
+    }
+    let first = bytes
+        .iter()
+        .zip(rebuilt)
+        .position(|(a, b)| a != b)
+        .unwrap_or(bytes.len().min(rebuilt.len()));
+    let mut detail = format!("first difference at file offset 0x{:X}", first);
+    if bytes.len() != rebuilt.len() {
+        write!(detail, ", size {} -> {}", bytes.len(), rebuilt.len()).unwrap();
+    }
+    if original.kind == PayloadKind::ShopDb {
+        let layout = text::shop_layout();
+        if bytes.len().is_multiple_of(layout.record_size) {
+            let offset = first % layout.record_size;
+            if let Some(field) = layout.fields.iter().find(|f| f.end() > offset) {
+                write!(
+                    detail,
+                    " (record {}, field {})",
+                    first / layout.record_size,
+                    field.name
+                )
+                .unwrap();
+            }
+        } else {
+            detail.push_str("; a partial record at the end is not exported");
+        }
+    }
+    Outcome::Differs(detail)
+}
+
+/// Pass/fail counts per file type, then every file that did not pass.
+pub fn text(results: &[Result]) -> String {
+    let mut kinds: BTreeMap<&str, [usize; 5]> = BTreeMap::new();
+    for result in results {
+        let column = match result.outcome {
+            Outcome::Identical => 0,
+            Outcome::SamePayload => 1,
+            Outcome::Differs(_) => 2,
+            Outcome::Failed(_) => 3,
+            Outcome::Skipped(_) => 4,
+        };
+        kinds.entry(result.kind).or_default()[column] += 1;
+    }
+
+    let mut out = String::new();
+    let width = kinds.keys().map(|k| k.len()).max().unwrap_or(0).max(4);
+    writeln!(
+        out,
+        "{:<width$}  {:>9}  {:>12}  {:>7}  {:>6}  {:>7}",
+        "type", "identical", "same payload", "differs", "failed", "skipped"
+    )
+    .unwrap();
+    for (kind, counts) in &kinds {
+        writeln!(
+            out,
+            "{:<width$}  {:>9}  {:>12}  {:>7}  {:>6}  {:>7}",
+            kind, counts[0], counts[1], counts[2], counts[3], counts[4]
+        )
+        .unwrap();
+    }
+
+    let failures: Vec<&Result> = results
+        .iter()
+        .filter(|r| !r.outcome.passed() && !matches!(r.outcome, Outcome::Skipped(_)))
+        .collect();
+    let passed = results.iter().filter(|r| r.outcome.passed()).count();
+    writeln!(
+        out,
+        "\n{} of {} files round-trip ({} failed)",
+        passed,
+        results.len(),
+        failures.len()
+    )
+    .unwrap();
+    for result in failures {
+        let detail = match &result.outcome {
+            Outcome::Differs(detail) => format!("differs: {}", detail),
+            Outcome::Failed(error) => format!("failed: {}", error),
+            _ => unreachable!("only failures are listed"),
+        };
+        writeln!(out, "  {}: {}", result.path, detail).unwrap();
+    }
+    out
+}
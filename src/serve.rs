@@ -0,0 +1,211 @@
+//! `idoc serve`: the conversions over HTTP, for web apps and CI jobs that
+//! want them without wrapping the CLI. Every endpoint takes the file as
+//! the request body and answers with the converted bytes:
+//!
+//! - `POST /decompile`: a .ido file in, XML (with its metadata comments),
+//!   CSV for shop databases, or the raw payload out
+//! - `POST /compile`: decompiled UTF-8 XML in, the .ido file out
+//! - `POST /shopdb/export?format=json`: a stored shop database in, its
+//!   records out in any `--format` of `idoc shopdb`
+//!
+//! Requests are handled one at a time, so the warnings a conversion
+//! prints can be handed back with it in `X-Idoc-Warning` headers.
+
+use crate::export::Format;
+use crate::ido::PayloadKind;
+use crate::memory;
+use crate::text;
+use crate::warnings;
+use clap::ValueEnum;
+use std::io::{self, Read};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest upload taken, in megabytes, whatever `--max-memory` allows.
+const MAX_BODY_MB: u64 = 256;
+const MAX_BODY: u64 = MAX_BODY_MB * 1024 * 1024;
+
+const USAGE: &str = "idoc serve\n\n\
+    POST /decompile                  .ido file -> XML, CSV or raw payload\n\
+    POST /compile                    decompiled XML -> .ido file\n\
+    POST /shopdb/export?format=json  stored shop database -> records\n";
+
+/// Why a request got no conversion: an HTTP status and the message sent
+/// as the body.
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Failure {
+        Failure {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Failure {
+        let status = match e.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => 400,
+            io::ErrorKind::OutOfMemory => 413,
+            // A compression or export feature this build lacks.
+            io::ErrorKind::Unsupported => 501,
+            _ => 500,
+        };
+        Failure::new(status, e.to_string())
+    }
+}
+
+/// A response body and its content type.
+struct Reply {
+    content_type: &'static str,
+    body: Vec<u8>,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl Reply {
+    fn new(content_type: &'static str, body: Vec<u8>) -> Reply {
+        Reply {
+            content_type,
+            body,
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Listen on `address` and answer requests until the process is stopped.
+pub fn run(address: &str) -> io::Result<()> {
+    let server = Server::http(address).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("Cannot listen on {}: {}", address, e),
+        )
+    })?;
+    eprintln!("Listening on http://{}", server.server_addr());
+    for mut request in server.incoming_requests() {
+        let method = request.method().clone();
+        let url = request.url().to_string();
+        let (status, mut reply) = match handle(&mut request) {
+            Ok(reply) => (200, reply),
+            Err(failure) => (
+                failure.status,
+                Reply::new(
+                    "text/plain; charset=utf-8",
+                    format!("{}\n", failure.message).into_bytes(),
+                ),
+            ),
+        };
+        reply.headers.extend(
+            warnings::take()
+                .into_iter()
+                .map(|warning| ("X-Idoc-Warning", warning)),
+        );
+        eprintln!("{} {} {} ({} bytes)", method, url, status, reply.body.len());
+        let mut response = Response::from_data(reply.body).with_status_code(status);
+        let headers =
+            std::iter::once(("Content-Type", reply.content_type.to_string())).chain(reply.headers);
+        for (name, value) in headers {
+            // Values that are not plain ASCII are left out rather than
+            // sent garbled.
+            if let Ok(header) = Header::from_bytes(name, value.as_bytes())
+                && value.is_ascii()
+            {
+                response.add_header(header);
+            }
+        }
+        if let Err(e) = request.respond(response) {
+            eprintln!("Warning: could not answer {} {}: {}", method, url, e);
+        }
+    }
+    Ok(())
+}
+
+fn handle(request: &mut Request) -> Result<Reply, Failure> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let post = *request.method() == Method::Post;
+    match path {
+        "/" if *request.method() == Method::Get => {
+            Ok(Reply::new("text/plain; charset=utf-8", USAGE.into()))
+        }
+        "/decompile" if post => {
+            let (kind, output) = text::decompile(body(request)?)?;
+            let content_type = match kind {
+                PayloadKind::Xml => "application/xml; charset=utf-8",
+                PayloadKind::ShopDb => "text/csv; charset=utf-8",
+                _ => "application/octet-stream",
+            };
+            let mut reply = Reply::new(content_type, output);
+            reply.headers.push(("X-Idoc-Kind", kind.name().to_string()));
+            reply
+                .headers
+                .push(("X-Idoc-Extension", kind.extension().to_string()));
+            Ok(reply)
+        }
+        "/compile" if post => {
+            let xml = String::from_utf8(body(request)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(Reply::new("application/octet-stream", text::compile(&xml)?))
+        }
+        "/shopdb/export" if post => {
+            let format = match parameter(query, "format") {
+                None => Format::Json,
+                Some(name) => Format::from_str(name, true)
+                    .map_err(|_| Failure::new(400, format!("Unknown format '{}'", name)))?,
+            };
+            let records = text::shopdb_export(&body(request)?, format)?;
+            Ok(Reply::new(content_type(format), records))
+        }
+        "/" | "/decompile" | "/compile" | "/shopdb/export" => Err(Failure::new(
+            405,
+            format!("{} is not allowed on {}", request.method(), path),
+        )),
+        _ => Err(Failure::new(404, format!("No endpoint {}", path))),
+    }
+}
+
+/// The request body, refused unread when its declared length is too
+/// large, and cut off once it gets too large otherwise.
+fn body(request: &mut Request) -> io::Result<Vec<u8>> {
+    if let Some(len) = request.body_length() {
+        check_upload(len as u64)?;
+    }
+    let limit = memory::limit().unwrap_or(MAX_BODY).min(MAX_BODY);
+    let mut body = Vec::new();
+    request.as_reader().take(limit + 1).read_to_end(&mut body)?;
+    check_upload(body.len() as u64)?;
+    Ok(body)
+}
+
+fn check_upload(len: u64) -> io::Result<()> {
+    if len > MAX_BODY {
+        return Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!("The upload is over the {} MB idoc serve takes", MAX_BODY_MB),
+        ));
+    }
+    memory::check("The upload", len)
+}
+
+/// The value of `name` in a query string, undecoded.
+fn parameter<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|&(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+fn content_type(format: Format) -> &'static str {
+    match format {
+        Format::Csv => "text/csv; charset=utf-8",
+        Format::Sql => "application/sql; charset=utf-8",
+        Format::Json => "application/json",
+        Format::Yaml => "application/yaml",
+        Format::Msgpack => "application/msgpack",
+        Format::Cbor => "application/cbor",
+        Format::Arrow => "application/vnd.apache.arrow.file",
+    }
+}
@@ -0,0 +1,76 @@
+//! Payload signatures: magic bytes at an offset and/or a footer, mapped
+//! to a payload type. The built-in ones live in `signatures.toml`; an
+//! idoc.toml can add more with `[[signatures]]`.
+
+use crate::config;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+const BUILT_IN: &str = include_str!("signatures.toml");
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Signature {
+    /// Short identifier used in reports.
+    pub name: String,
+    pub description: String,
+    /// Extension of the decompiled output.
+    pub extension: String,
+    #[serde(default, deserialize_with = "config::from_hex")]
+    pub magic: Vec<u8>,
+    /// Where `magic` starts in the payload.
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default, deserialize_with = "config::from_hex")]
+    pub footer: Vec<u8>,
+    #[serde(default)]
+    pub handler: Handler,
+}
+
+/// How a payload of this type is decompiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Handler {
+    /// Saved as-is, with a .meta sidecar.
+    #[default]
+    Raw,
+    /// Decoded from EUC-KR and saved as XML text.
+    Xml,
+}
+
+impl Signature {
+    /// Whether the signature says anything at all; empty ones would
+    /// match every payload.
+    pub fn is_valid(&self) -> bool {
+        !self.magic.is_empty() || !self.footer.is_empty()
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.is_valid()
+            && data
+                .get(self.offset..)
+                .is_some_and(|rest| rest.starts_with(&self.magic))
+            && data.ends_with(&self.footer)
+    }
+}
+
+#[derive(Deserialize)]
+struct Table {
+    signatures: Vec<Signature>,
+}
+
+/// All signatures, those from the config file first.
+pub fn all() -> impl Iterator<Item = &'static Signature> {
+    static TABLE: OnceLock<Vec<Signature>> = OnceLock::new();
+    let built_in = TABLE.get_or_init(|| {
+        toml::from_str::<Table>(BUILT_IN)
+            .expect("built-in signature table is valid")
+            .signatures
+    });
+    config::get().signatures.iter().chain(built_in)
+}
+
+/// The first signature `data` matches.
+pub fn detect(data: &[u8]) -> Option<&'static Signature> {
+    all().find(|s| s.matches(data))
+}
@@ -0,0 +1,202 @@
+//! Content-addressed snapshots of data directories.
+//!
+//! Files are stored once under `objects/<first two hex digits>/<sha256>`,
+//! however many snapshots contain them; each snapshot is a JSON manifest
+//! in `snapshots/<tag>.json` mapping relative paths to hashes.
+
+use crate::guard::write_atomic;
+use crate::walk;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub tag: String,
+    /// Directory the snapshot was taken of, restored to by default.
+    pub source: PathBuf,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub files: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+pub struct Store {
+    root: PathBuf,
+}
+
+/// What a save or restore did.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub files: usize,
+    /// Files written: new objects on save, changed files on restore.
+    pub written: usize,
+    pub removed: usize,
+}
+
+impl Store {
+    pub fn new(root: &Path) -> Store {
+        Store {
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn save(&self, dir: &Path, tag: &str) -> io::Result<Summary> {
+        check_tag(tag)?;
+        let manifest_path = self.manifest_path(tag);
+        if manifest_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Snapshot '{}' already exists", tag),
+            ));
+        }
+
+        // Never snapshot the store itself when it lives inside `dir`.
+        fs::create_dir_all(&self.root)?;
+        let store = fs::canonicalize(&self.root)?;
+        let dir = fs::canonicalize(dir)?;
+        let files = walk::files(&dir, |path| !path.starts_with(&store))?;
+
+        let mut summary = Summary::default();
+        let mut entries = Vec::with_capacity(files.len());
+        for path in &files {
+            let content = fs::read(path)?;
+            let sha256 = hex::encode(Sha256::digest(&content));
+            let object = self.object_path(&sha256);
+            if !object.exists() {
+                write_atomic(&object, &content)?;
+                summary.written += 1;
+            }
+            entries.push(Entry {
+                path: walk::relative(path, &dir),
+                sha256,
+                size: content.len() as u64,
+            });
+        }
+        summary.files = entries.len();
+
+        let manifest = Manifest {
+            tag: tag.to_string(),
+            source: dir,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            files: entries,
+        };
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        write_atomic(&manifest_path, &json)?;
+        Ok(summary)
+    }
+
+    /// Restore `tag` into `into` (or where it was taken), rewriting only
+    /// files whose content differs. With `delete`, files not in the
+    /// snapshot are removed.
+    pub fn restore(&self, tag: &str, into: Option<&Path>, delete: bool) -> io::Result<Summary> {
+        let manifest = self.manifest(tag)?;
+        let dir = into.unwrap_or(&manifest.source);
+
+        let mut summary = Summary {
+            files: manifest.files.len(),
+            ..Default::default()
+        };
+        for entry in &manifest.files {
+            let target = dir.join(&entry.path);
+            let current = fs::read(&target).ok();
+            if current.is_some_and(|c| hex::encode(Sha256::digest(&c)) == entry.sha256) {
+                continue;
+            }
+            let content = fs::read(self.object_path(&entry.sha256)).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Missing object for {} in the store: {}", entry.path, e),
+                )
+            })?;
+            write_atomic(&target, &content)?;
+            summary.written += 1;
+        }
+
+        if delete {
+            let keep: Vec<&str> = manifest.files.iter().map(|e| e.path.as_str()).collect();
+            let store = fs::canonicalize(&self.root)?;
+            let dir = fs::canonicalize(dir)?;
+            for path in walk::files(&dir, |path| !path.starts_with(&store))? {
+                if !keep.contains(&walk::relative(&path, &dir).as_str()) {
+                    fs::remove_file(&path)?;
+                    summary.removed += 1;
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// All snapshots, oldest first.
+    pub fn list(&self) -> io::Result<Vec<Manifest>> {
+        let dir = self.root.join("snapshots");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut manifests = Vec::new();
+        for path in walk::files(&dir, |p| p.extension().is_some_and(|e| e == "json"))? {
+            manifests.push(read_manifest(&path)?);
+        }
+        manifests.sort_by_key(|m| m.created);
+        Ok(manifests)
+    }
+
+    fn manifest(&self, tag: &str) -> io::Result<Manifest> {
+        check_tag(tag)?;
+        let path = self.manifest_path(tag);
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No snapshot '{}' in {}", tag, self.root.display()),
+            ));
+        }
+        read_manifest(&path)
+    }
+
+    fn manifest_path(&self, tag: &str) -> PathBuf {
+        self.root.join("snapshots").join(format!("{}.json", tag))
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("objects").join(&sha256[..2]).join(sha256)
+    }
+}
+
+fn read_manifest(path: &Path) -> io::Result<Manifest> {
+    serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Corrupt snapshot {}: {}", path.display(), e),
+        )
+    })
+}
+
+fn check_tag(tag: &str) -> io::Result<()> {
+    let valid = !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !tag.starts_with('.');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid tag '{}': use letters, digits, '-', '_' and '.'",
+                tag
+            ),
+        ))
+    }
+}
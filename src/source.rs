@@ -0,0 +1,53 @@
+use crate::export::Format;
+use std::path::Path;
+
+/// What a compile input is, and so which pipeline rebuilds it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Xml,
+    /// A database export, read with the compile layout.
+    Csv,
+    /// A json, yaml, msgpack or cbor export of a database or document.
+    Structured(Format),
+    /// Raw payload bytes, rebuilt with a .meta sidecar.
+    Binary,
+}
+
+/// Decide by extension when it is one we write, otherwise by content.
+pub fn detect(path: &Path, content: &[u8]) -> Source {
+    by_extension(path).unwrap_or_else(|| sniff(content))
+}
+
+/// The source an extension we write stands for. CSV is only ever
+/// recognized this way.
+pub fn by_extension(path: &Path) -> Option<Source> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())?;
+    match ext.as_str() {
+        "xml" => Some(Source::Xml),
+        ext => match Format::from_extension(ext)? {
+            Format::Csv => Some(Source::Csv),
+            format @ (Format::Json | Format::Yaml | Format::Msgpack | Format::Cbor) => {
+                Some(Source::Structured(format))
+            }
+            Format::Sql | Format::Arrow => None,
+        },
+    }
+}
+
+fn sniff(content: &[u8]) -> Source {
+    let Ok(text) = std::str::from_utf8(content) else {
+        return Source::Binary;
+    };
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.starts_with('<') {
+        Source::Xml
+    } else if text.starts_with('{') || text.starts_with('[') {
+        Source::Structured(Format::Json)
+    } else if text.starts_with("ido:") || text.starts_with("---") || text.starts_with("- ") {
+        Source::Structured(Format::Yaml)
+    } else {
+        Source::Binary
+    }
+}
@@ -0,0 +1,277 @@
+//! Value distributions of database fields, for working out what unknown
+//! fields mean.
+
+use crate::layout::{Field, FieldType, Layout, Region, Value};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub const DEFAULT_TOP: usize = 20;
+/// Most distinct values a column can have and still look like an enum.
+const MAX_ENUM_VALUES: usize = 16;
+const BAR_WIDTH: usize = 40;
+
+/// The field called `name`: a layout field, or a gap by its exported name
+/// (`unknown_0x08`). A gap name may also point inside a gap, reading the
+/// widest integer that fits before the next field.
+pub fn field(layout: &Layout, name: &str) -> Option<Field> {
+    if let Some(field) = layout.field(name) {
+        return Some(field.clone());
+    }
+    let offset = name
+        .strip_prefix("unknown_0x")
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())?;
+    layout
+        .regions()
+        .into_iter()
+        .find_map(|region| match region {
+            Region::Gap {
+                offset: start,
+                size,
+            } if (start..start + size).contains(&offset) => {
+                let left = start + size - offset;
+                let (kind, length) = match left {
+                    _ if offset == start && size > 4 => (FieldType::Bytes, Some(size)),
+                    4.. => (FieldType::U32, None),
+                    2 | 3 => (FieldType::U16, None),
+                    _ => (FieldType::U8, None),
+                };
+                Some(Field {
+                    name: name.to_string(),
+                    offset,
+                    kind,
+                    length,
+                })
+            }
+            _ => None,
+        })
+}
+
+/// What a column's values suggest it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Constant,
+    Boolean,
+    Enum,
+    Id,
+    Varied,
+}
+
+impl Shape {
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Constant => "constant",
+            Shape::Boolean => "boolean-like",
+            Shape::Enum => "enum-like",
+            Shape::Id => "ID-like",
+            Shape::Varied => "varied",
+        }
+    }
+}
+
+/// Distinct values with how often each occurs, most common first.
+pub struct Distribution {
+    pub records: usize,
+    pub counts: Vec<(Value, usize)>,
+}
+
+impl Distribution {
+    pub fn new(values: Vec<Value>) -> Distribution {
+        let records = values.len();
+        let mut counts: HashMap<String, (Value, usize)> = HashMap::new();
+        for value in values {
+            counts.entry(key(&value)).or_insert_with(|| (value, 0)).1 += 1;
+        }
+        let mut counts: Vec<(Value, usize)> = counts.into_values().collect();
+        counts.sort_by(|(a, ca), (b, cb)| cb.cmp(ca).then_with(|| order(a, b)));
+        Distribution { records, counts }
+    }
+
+    pub fn shape(&self) -> Shape {
+        let distinct = self.counts.len();
+        let zero = self.counts.iter().any(|(v, _)| is_zero(v));
+        if distinct <= 1 {
+            Shape::Constant
+        } else if distinct == 2 && zero {
+            Shape::Boolean
+        } else if distinct == self.records {
+            Shape::Id
+        } else if distinct <= MAX_ENUM_VALUES && distinct * 2 <= self.records {
+            Shape::Enum
+        } else {
+            Shape::Varied
+        }
+    }
+
+    /// One-line description of the shape, e.g. whether IDs are sequential.
+    pub fn describe(&self) -> String {
+        let shape = self.shape();
+        match shape {
+            Shape::Constant => match self.counts.first() {
+                Some((value, _)) => format!("constant ({})", show(value)),
+                None => "no records".to_string(),
+            },
+            Shape::Id => {
+                let mut ints: Vec<i64> = self
+                    .counts
+                    .iter()
+                    .filter_map(|(v, _)| match v {
+                        Value::Int(i) => Some(*i),
+                        _ => None,
+                    })
+                    .collect();
+                ints.sort_unstable();
+                let sequential =
+                    ints.len() == self.counts.len() && ints.windows(2).all(|w| w[1] == w[0] + 1);
+                if sequential {
+                    format!(
+                        "ID-like (unique, sequential {}..={})",
+                        ints[0],
+                        ints[ints.len() - 1]
+                    )
+                } else {
+                    "ID-like (unique per record)".to_string()
+                }
+            }
+            _ => shape.name().to_string(),
+        }
+    }
+}
+
+/// Grouping key; floats compare by bits so NaN groups with itself.
+fn key(value: &Value) -> String {
+    match value {
+        Value::Float(f) => format!("f{:08x}", f.to_bits()),
+        Value::Int(i) => format!("i{}", i),
+        Value::Text(t) => format!("t{}", t),
+        Value::Bytes(b) => format!("b{}", hex::encode(b)),
+    }
+}
+
+fn order(a: &Value, b: &Value) -> std::cmp::Ordering {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+        _ => key(a).cmp(&key(b)),
+    }
+}
+
+fn is_zero(value: &Value) -> bool {
+    match value {
+        Value::Int(i) => *i == 0,
+        Value::Float(f) => *f == 0.0,
+        Value::Text(t) => t.is_empty(),
+        Value::Bytes(b) => b.iter().all(|&b| b == 0),
+    }
+}
+
+/// Bytes shown in full before a value is cut short.
+const MAX_SHOWN_BYTES: usize = 16;
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::Text(text) => format!("{:?}", text),
+        Value::Bytes(bytes) if bytes.iter().all(|&b| b == 0) => {
+            format!("{} zero bytes", bytes.len())
+        }
+        Value::Bytes(bytes) if bytes.len() > MAX_SHOWN_BYTES => format!(
+            "{}... ({} bytes)",
+            hex::encode(&bytes[..MAX_SHOWN_BYTES]),
+            bytes.len()
+        ),
+        value => value.to_string(),
+    }
+}
+
+fn values(field: &Field, data: &[u8], record_size: usize) -> Vec<Value> {
+    data.chunks_exact(record_size)
+        .map(|record| field.read(record))
+        .collect()
+}
+
+/// One line per field and gap: type, distinct values and shape.
+pub fn summary(layout: &Layout, data: &[u8]) -> String {
+    let regions: Vec<Field> = layout
+        .regions()
+        .into_iter()
+        .map(|region| match region {
+            Region::Field(field) => field.clone(),
+            Region::Gap { offset, .. } => {
+                field(layout, &Region::gap_name(offset)).expect("gap names resolve to their gap")
+            }
+        })
+        .collect();
+    let width = regions.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} records of {}",
+        data.len() / layout.record_size,
+        layout.name
+    )
+    .unwrap();
+    for field in &regions {
+        let distribution = Distribution::new(values(field, data, layout.record_size));
+        writeln!(
+            out,
+            "  {:<width$}  0x{:03X}  {:<5}  {:>6} distinct  {}",
+            field.name,
+            field.offset,
+            field.kind.name(),
+            distribution.counts.len(),
+            distribution.describe()
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Histogram of one field's values, the `top` most common first.
+pub fn histogram(field: &Field, layout: &Layout, data: &[u8], top: usize) -> String {
+    let distribution = Distribution::new(values(field, data, layout.record_size));
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{}: {} at 0x{:02X}, {} records, {} distinct values",
+        field.name,
+        field.kind.name(),
+        field.offset,
+        distribution.records,
+        distribution.counts.len()
+    )
+    .unwrap();
+    writeln!(out, "Looks {}", distribution.describe()).unwrap();
+
+    let shown: Vec<(String, usize)> = distribution
+        .counts
+        .iter()
+        .take(top)
+        .map(|(value, count)| (show(value), *count))
+        .collect();
+    let width = shown
+        .iter()
+        .map(|(v, _)| v.chars().count())
+        .max()
+        .unwrap_or(0);
+    let most = shown.first().map(|&(_, count)| count).unwrap_or(1);
+    for (value, count) in &shown {
+        let share = *count as f64 * 100.0 / distribution.records as f64;
+        writeln!(
+            out,
+            "  {:<width$}  {:>6}  {:>5.1}%  {}",
+            value,
+            count,
+            share,
+            "#".repeat((count * BAR_WIDTH).div_ceil(most))
+        )
+        .unwrap();
+    }
+    if distribution.counts.len() > shown.len() {
+        writeln!(
+            out,
+            "  ... {} more values",
+            distribution.counts.len() - shown.len()
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,173 @@
+//! Readable strings in binary payloads, in the encodings the game uses.
+//! Like GNU strings, but EUC-KR and UTF-16 aware.
+
+use clap::ValueEnum;
+use encoding_rs::{EUC_KR, UTF_16BE, UTF_16LE};
+
+pub const DEFAULT_MIN_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    Ascii,
+    /// ASCII plus two-byte Korean characters
+    EucKr,
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Ascii => "ascii",
+            Encoding::EucKr => "euc-kr",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        }
+    }
+}
+
+/// A string found at `offset`, `len` bytes long.
+pub struct Found {
+    pub offset: usize,
+    pub len: usize,
+    pub encoding: Encoding,
+    pub text: String,
+}
+
+/// Strings of at least `min_len` characters in any of `encodings`, in
+/// offset order. Where readings overlap (EUC-KR text also reads as
+/// UTF-16 one byte off, say) the longest wins, then the encoding listed
+/// first.
+pub fn scan(data: &[u8], encodings: &[Encoding], min_len: usize) -> Vec<Found> {
+    let mut found = Vec::new();
+    for &encoding in encodings {
+        // EUC-KR runs already cover the ASCII ones.
+        if encoding == Encoding::Ascii && encodings.contains(&Encoding::EucKr) {
+            continue;
+        }
+        match encoding {
+            Encoding::Ascii | Encoding::EucKr => {
+                found.extend(bytes(data, encoding, min_len));
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                for start in 0..2 {
+                    found.extend(units(data, start, encoding, min_len));
+                }
+            }
+        }
+    }
+
+    found.sort_by_key(|f| std::cmp::Reverse(f.len));
+    let mut kept: Vec<Found> = Vec::new();
+    for candidate in found {
+        let end = candidate.offset + candidate.len;
+        if kept
+            .iter()
+            .all(|k| end <= k.offset || k.offset + k.len <= candidate.offset)
+        {
+            kept.push(candidate);
+        }
+    }
+    kept.sort_by_key(|f| f.offset);
+    kept
+}
+
+/// Runs of printable ASCII, and with EUC-KR also lead/trail byte pairs.
+fn bytes(data: &[u8], encoding: Encoding, min_len: usize) -> Vec<Found> {
+    let korean = encoding == Encoding::EucKr;
+    let mut found = Vec::new();
+    let mut start = 0;
+    let mut chars = 0;
+    let mut i = 0;
+    while i <= data.len() {
+        let width = match data.get(i..) {
+            Some([b, ..]) if is_printable(*b) => 1,
+            Some([lead, trail, ..])
+                if korean && (0xA1..=0xFE).contains(lead) && (0xA1..=0xFE).contains(trail) =>
+            {
+                2
+            }
+            _ => 0,
+        };
+        if width == 0 {
+            if chars >= min_len {
+                let (text, _) = EUC_KR.decode_without_bom_handling(&data[start..i]);
+                found.push(Found {
+                    offset: start,
+                    len: i - start,
+                    encoding,
+                    text: text.into_owned(),
+                });
+            }
+            i += 1;
+            start = i;
+            chars = 0;
+        } else {
+            i += width;
+            chars += 1;
+        }
+    }
+    found
+}
+
+/// Runs of text code units starting at byte `start` (0 or 1).
+fn units(data: &[u8], start: usize, encoding: Encoding, min_len: usize) -> Vec<Found> {
+    let read = |pair: &[u8]| match encoding {
+        Encoding::Utf16Be => u16::from_be_bytes([pair[0], pair[1]]),
+        _ => u16::from_le_bytes([pair[0], pair[1]]),
+    };
+    let decoder = match encoding {
+        Encoding::Utf16Be => UTF_16BE,
+        _ => UTF_16LE,
+    };
+    let mut found = Vec::new();
+    let mut run_start = start;
+    let mut chars = 0;
+    let chunks = data.get(start..).unwrap_or_default().chunks_exact(2);
+    for (index, pair) in chunks.map(Some).chain([None]).enumerate() {
+        let offset = start + index * 2;
+        if pair.is_some_and(|pair| is_text_unit(read(pair))) {
+            chars += 1;
+            continue;
+        }
+        if chars >= min_len {
+            let (text, _) = decoder.decode_without_bom_handling(&data[run_start..offset]);
+            found.push(Found {
+                offset: run_start,
+                len: offset - run_start,
+                encoding,
+                text: text.into_owned(),
+            });
+        }
+        run_start = offset + 2;
+        chars = 0;
+    }
+    found
+}
+
+fn is_printable(b: u8) -> bool {
+    (0x20..=0x7E).contains(&b) || b == b'\t'
+}
+
+/// Printable ASCII, Hangul, CJK punctuation and fullwidth forms. Hanja
+/// are left out: two ASCII bytes often read as one.
+fn is_text_unit(unit: u16) -> bool {
+    matches!(unit, 0x09 | 0x20..=0x7E | 0x1100..=0x11FF | 0x3000..=0x318F | 0xAC00..=0xD7A3 | 0xFF01..=0xFF5E)
+}
+
+/// The listing printed by `idoc strings`.
+pub fn report(found: &[Found]) -> String {
+    found
+        .iter()
+        .map(|f| {
+            format!(
+                "0x{:08X}  {:<8}  {}\n",
+                f.offset,
+                f.encoding.name(),
+                f.text.replace('\t', "\\t")
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,89 @@
+//! Output path templates, such as `--output '{dir}/{stem}.{type_ext}'`,
+//! which name each output after its input and what it turned out to be:
+//!
+//! - `{dir}`: the input's directory
+//! - `{stem}`: the input's file name without its extension
+//! - `{type_ext}`: the natural extension of the output, e.g. `xml`, or
+//!   `csv` for shop databases (`ido` when compiling)
+//! - `{date}`: today's date (UTC) as `YYYY-MM-DD`
+//!
+//! `{{` and `}}` stand for literal braces. A templated output is written
+//! exactly where the template says, with no extension added or changed.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Whether `output` has placeholders to fill in.
+pub fn is_template(output: &Path) -> bool {
+    output.to_string_lossy().contains('{')
+}
+
+/// `template` filled in for `input`, whose output's natural extension is
+/// `type_ext`.
+pub fn expand(template: &Path, input: &Path, type_ext: &str) -> io::Result<PathBuf> {
+    let template = template.to_string_lossy();
+    let mut expanded = String::new();
+    let mut rest = template.as_ref();
+    while let Some(start) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..start]);
+        let brace = &rest[start..];
+        if let Some(after) = brace
+            .strip_prefix("{{")
+            .or_else(|| brace.strip_prefix("}}"))
+        {
+            expanded.push_str(&brace[..1]);
+            rest = after;
+            continue;
+        }
+        let end = brace
+            .find('}')
+            .filter(|_| brace.starts_with('{'))
+            .ok_or_else(|| invalid(format!("Unmatched brace in output template '{}'", template)))?;
+        let name = &brace[1..end];
+        let value = match name {
+            "dir" => match input.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+                _ => ".".to_string(),
+            },
+            "stem" => input
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            "type_ext" => type_ext.to_string(),
+            "date" => today(),
+            _ => {
+                return Err(invalid(format!(
+                    "Unknown placeholder {{{}}} in output template '{}' (use dir, stem, type_ext or date)",
+                    name, template
+                )));
+            }
+        };
+        expanded.push_str(&value);
+        rest = &brace[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Today's UTC date as `YYYY-MM-DD`.
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86_400) as i64;
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's
+    // days_from_civil inverse.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
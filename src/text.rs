@@ -0,0 +1,130 @@
+use crate::export::{self, CsvDialect};
+use crate::ido::{self, IdoFile, PayloadKind};
+use crate::layout::Layout;
+use crate::meta::Meta;
+use crate::warnings;
+use encoding_rs::EUC_KR;
+use sha2::{Digest, Sha256};
+use std::io;
+
+/// Text form of a decoded file, as decompile writes it: XML with its
+/// metadata comments, or databases as CSV.
+pub fn render(file: &IdoFile) -> io::Result<String> {
+    match file.kind {
+        PayloadKind::Xml => {
+            let (xml, _, _) = EUC_KR.decode(&file.data);
+            Ok(match &file.meta {
+                Some(meta) => format!("{}\n{}\n", xml, meta.to_xml_comments()),
+                None => xml.into_owned(),
+            })
+        }
+        PayloadKind::ShopDb => {
+            let layout = shop_layout();
+            layout.check_texts(&file.data)?;
+            let records = layout.read_records(&file.data);
+            let mut out = Vec::new();
+            export::csv::write(&mut out, &layout, &records, &CsvDialect::default())?;
+            String::from_utf8(out).map_err(io::Error::other)
+        }
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no text form", kind.description()),
+        )),
+    }
+}
+
+/// Inverse of [`render`] for XML: rebuild the file from decompiled text
+/// and its metadata comments. `None` when the text carries no header.
+pub fn compile_xml(xml: &str) -> io::Result<Option<Vec<u8>>> {
+    let (meta, content) = Meta::split_xml(xml)?;
+    if meta.header.is_none() {
+        return Ok(None);
+    }
+    // Only the line break [`render`] puts before the comments; the
+    // payload's own whitespace is kept, so the file compiles back as it was.
+    let content = content.strip_suffix('\n').unwrap_or(content);
+    let (encoded, _, unmappable) = EUC_KR.encode(content);
+    if unmappable {
+        warnings::warn("Some characters could not be mapped to EUC-KR.");
+    }
+    ido::encode_payload(&meta, &encoded).map(Some)
+}
+
+/// A whole file as the library bindings hand it back: [`render`]ed text
+/// for XML and databases, and the payload for anything else.
+pub fn decompile(bytes: Vec<u8>) -> io::Result<(PayloadKind, Vec<u8>)> {
+    let file = IdoFile::decode(bytes, &ido::DecodeOptions::default())?;
+    match file.kind {
+        PayloadKind::Xml | PayloadKind::ShopDb => Ok((file.kind, render(&file)?.into_bytes())),
+        kind => Ok((kind, file.data)),
+    }
+}
+
+/// [`compile_xml`] for the bindings, where XML without a header is an
+/// error.
+pub fn compile(xml: &str) -> io::Result<Vec<u8>> {
+    compile_xml(xml)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the XML has no idoc header comments; decompile the original file first",
+        )
+    })
+}
+
+/// A stored shop database as a JSON array of records, for the bindings.
+pub fn shopdb_json(data: &[u8]) -> io::Result<Vec<u8>> {
+    shopdb_export(data, export::Format::Json)
+}
+
+/// A stored shop database exported in `format`, with default options.
+pub fn shopdb_export(data: &[u8], format: export::Format) -> io::Result<Vec<u8>> {
+    let layout = shop_layout();
+    layout.check_whole(data.len())?;
+    layout.check_texts(data)?;
+    let mut out = Vec::new();
+    export::write_records(
+        &mut out,
+        &layout,
+        &layout.read_records(data),
+        format,
+        &export::ExportOptions::default(),
+    )?;
+    Ok(out)
+}
+
+/// The shop layout as databases are exported: with the bytes between its
+/// fields, so the export compiles back to the same file.
+pub fn shop_layout() -> Layout {
+    Layout::builtin("shop")
+        .expect("shop layout is built in")
+        .with_gaps()
+}
+
+/// Like [`render`], but binary payloads become a one-line placeholder
+/// with their size and hash, so any change still shows up in a diff.
+pub fn textconv(file: &IdoFile) -> io::Result<String> {
+    match file.kind {
+        PayloadKind::Xml | PayloadKind::ShopDb => render(file),
+        kind => Ok(format!(
+            "{}: {} bytes, sha256 {}\n",
+            kind.description(),
+            file.data.len(),
+            hex::encode(Sha256::digest(&file.data))
+        )),
+    }
+}
+
+/// Unified diff of two texts; empty when they are equal.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(context)
+        .header(old_name, new_name)
+        .to_string()
+}
@@ -0,0 +1,85 @@
+//! `--preserve-times`: outputs carry the modification and access times of
+//! the file they were made from. Patchers and launchers compare mtimes,
+//! and fetch again every file idoc rewrote with the time of the run.
+
+use std::collections::HashMap;
+use std::fs::{self, File, FileTimes};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Give `to` the times of `from`.
+pub fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    let times = FileTimes::new()
+        .set_modified(metadata.modified()?)
+        .set_accessed(metadata.accessed()?);
+    open(to)?.set_times(times).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Cannot set the times of {}: {}", to.display(), e),
+        )
+    })
+}
+
+/// Give the directories outputs were written into the times of the
+/// directories their inputs came from, once every output is written.
+///
+/// `converted` pairs each input with its output. An output directory is
+/// matched to its input's, and so are the directories above both while
+/// they have the same names, as in a mirror such as `out/{dir}`. One
+/// that holds outputs from several input directories is left alone.
+pub fn copy_dirs(converted: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    let mut sources: HashMap<PathBuf, Option<PathBuf>> = HashMap::new();
+    for (input, output) in converted {
+        let (Some(mut from), Some(mut to)) = (directory(input), directory(output)) else {
+            continue;
+        };
+        loop {
+            let source = sources
+                .entry(to.clone())
+                .or_insert_with(|| Some(from.clone()));
+            if source.as_ref() != Some(&from) {
+                *source = None;
+            }
+            if from.file_name().is_none() || from.file_name() != to.file_name() {
+                break;
+            }
+            match (directory(&from), directory(&to)) {
+                (Some(up_from), Some(up_to)) => (from, to) = (up_from, up_to),
+                _ => break,
+            }
+        }
+    }
+    for (to, from) in sources {
+        if let Some(from) = from {
+            copy(&from, &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// The directory holding `path`, `.` for a bare file name.
+fn directory(path: &Path) -> Option<PathBuf> {
+    match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Some(PathBuf::from(".")),
+        parent => parent.map(Path::to_path_buf),
+    }
+}
+
+/// `path`, file or directory, opened so its times can be set.
+#[cfg(windows)]
+fn open(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
+
+/// `path`, file or directory, opened so its times can be set.
+#[cfg(not(windows))]
+fn open(path: &Path) -> io::Result<File> {
+    File::open(path)
+}
@@ -0,0 +1,169 @@
+//! `--timings`: wall time and throughput of each stage of a run, per file
+//! and summed over all of them, to tell which stage is the slow one.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static FILES: Mutex<Vec<File>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Read,
+    Decompress,
+    /// Text decoding and export of the payload.
+    Decode,
+    /// Parsing and text encoding of the input.
+    Encode,
+    Compress,
+    Write,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Decompress => "decompress",
+            Stage::Decode => "decode",
+            Stage::Encode => "encode",
+            Stage::Compress => "compress",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// Time and bytes spent per stage on one file.
+struct File {
+    name: String,
+    /// The thread converting it; with `--jobs`, several files are timed
+    /// at once.
+    thread: ThreadId,
+    stages: Vec<(Stage, Duration, u64)>,
+}
+
+impl File {
+    fn add(&mut self, stage: Stage, time: Duration, bytes: u64) {
+        match self.stages.iter_mut().find(|(s, _, _)| *s == stage) {
+            Some((_, total, total_bytes)) => {
+                *total += time;
+                *total_bytes += bytes;
+            }
+            None => self.stages.push((stage, time, bytes)),
+        }
+    }
+}
+
+fn files() -> std::sync::MutexGuard<'static, Vec<File>> {
+    FILES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Attribute the stages timed from now on to `path`.
+pub fn file(path: &Path) {
+    if enabled() {
+        files().push(File {
+            name: path.display().to_string(),
+            thread: thread::current().id(),
+            stages: Vec::new(),
+        });
+    }
+}
+
+/// A stage being timed; see [`Timer::done`].
+pub struct Timer {
+    stage: Stage,
+    /// Unset unless enabled: the clock isn't free, and in a browser
+    /// there is none.
+    start: Option<Instant>,
+}
+
+pub fn start(stage: Stage) -> Timer {
+    Timer {
+        stage,
+        start: enabled().then(Instant::now),
+    }
+}
+
+impl Timer {
+    /// Record the stage as having processed `bytes` of input.
+    pub fn done(self, bytes: usize) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let time = start.elapsed();
+        let thread = thread::current().id();
+        let mut files = files();
+        if !files.iter().any(|file| file.thread == thread) {
+            files.push(File {
+                name: "(run)".to_string(),
+                thread,
+                stages: Vec::new(),
+            });
+        }
+        files
+            .iter_mut()
+            .rev()
+            .find(|file| file.thread == thread)
+            .expect("a file was just added")
+            .add(self.stage, time, bytes as u64);
+    }
+}
+
+fn row(out: &mut String, name: &str, stage: Stage, time: Duration, bytes: u64) {
+    let seconds = time.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        format!("{:.1} MB/s", bytes as f64 / seconds / 1_000_000.0)
+    } else {
+        "-".to_string()
+    };
+    writeln!(
+        out,
+        "  {:<32}  {:<10}  {:>10.3} ms  {:>12} bytes  {:>12}",
+        name,
+        stage.name(),
+        seconds * 1000.0,
+        bytes,
+        rate
+    )
+    .unwrap();
+}
+
+/// The timings table, or `None` when `--timings` is off.
+pub fn report() -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+    let files = files();
+    let mut out = String::from("Timings:\n");
+    let mut total = File {
+        name: format!("total ({} files)", files.len()),
+        thread: thread::current().id(),
+        stages: Vec::new(),
+    };
+    for file in files.iter() {
+        let mut stages = file.stages.clone();
+        stages.sort_by_key(|&(stage, _, _)| stage);
+        for (stage, time, bytes) in stages {
+            row(&mut out, &file.name, stage, time, bytes);
+            total.add(stage, time, bytes);
+        }
+    }
+    if files.len() > 1 {
+        total.stages.sort_by_key(|&(stage, _, _)| stage);
+        for &(stage, time, bytes) in &total.stages {
+            row(&mut out, &total.name, stage, time, bytes);
+        }
+    }
+    Some(out)
+}
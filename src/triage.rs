@@ -0,0 +1,269 @@
+//! `idoc triage`: run every stage of the decode pipeline on each file of
+//! a directory and group the failures by what went wrong.
+
+use crate::compression;
+use crate::header;
+use crate::ido::{self, DecodeOptions, PayloadKind};
+use crate::layout::Layout;
+use crate::repair;
+use crate::walk;
+use crate::xml;
+use crate::xor;
+use encoding_rs::EUC_KR;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+/// Failures listed per category before the rest are only counted.
+const MAX_LISTED: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    Unreadable,
+    BadHeader,
+    SizeMismatch,
+    NotCompressed,
+    Damaged,
+    /// Needs a compression feature this build lacks.
+    Unsupported,
+    DecodeError,
+}
+
+impl Category {
+    pub fn title(self) -> &'static str {
+        match self {
+            Category::Unreadable => "Unreadable",
+            Category::BadHeader => "Bad header",
+            Category::SizeMismatch => "Size mismatch",
+            Category::NotCompressed => "Not a compressed stream",
+            Category::Damaged => "Damaged stream",
+            Category::Unsupported => "Unsupported compression",
+            Category::DecodeError => "Payload does not decode",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Category::Unreadable => "The file could not be read.",
+            Category::BadHeader => "The file is shorter than a header.",
+            Category::SizeMismatch => {
+                "Sizes disagree: a database that isn't whole records, or header size fields that don't match the payload."
+            }
+            Category::NotCompressed => {
+                "The bytes after the header start no zlib, gzip, zstd or lz4 stream, and no single-byte XOR key makes them."
+            }
+            Category::Damaged => "A stream starts but does not decompress to its end.",
+            Category::Unsupported => {
+                "The stream needs a compression feature idoc was built without."
+            }
+            Category::DecodeError => {
+                "The payload decompresses but its text or XML does not decode."
+            }
+        }
+    }
+}
+
+/// The first stage a file failed at, and what later stages still found.
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub path: String,
+    pub category: Category,
+    pub error: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub total: usize,
+    pub ok: usize,
+    pub failures: Vec<Failure>,
+}
+
+/// Triage every .ido below `dir`.
+pub fn run(dir: &Path, options: &DecodeOptions) -> io::Result<Report> {
+    let files = walk::ido_files(dir)?;
+    let failures: Vec<Failure> = files
+        .iter()
+        .filter_map(|path| inspect(path, dir, options))
+        .collect();
+    Ok(Report {
+        total: files.len(),
+        ok: files.len() - failures.len(),
+        failures,
+    })
+}
+
+/// Run the stages on one file; `None` when it passes them all.
+pub fn inspect(path: &Path, base: &Path, options: &DecodeOptions) -> Option<Failure> {
+    let failure = |category: Category, error: String, notes: Vec<String>| {
+        Some(Failure {
+            path: walk::relative(path, base),
+            category,
+            error,
+            notes,
+        })
+    };
+
+    let opened = match ido::open(path, options) {
+        Ok(opened) => opened,
+        Err(e) => return failure(Category::Unreadable, e.to_string(), Vec::new()),
+    };
+    if let Err(e) = ido::check_length(opened.len, options.header_size()) {
+        return failure(Category::BadHeader, e.to_string(), Vec::new());
+    }
+    let mut header = opened.header.clone();
+    options.decode_header(&mut header);
+
+    if opened.stored == Some(PayloadKind::ShopDb) {
+        let layout = Layout::builtin("shop").expect("shop layout is built in");
+        let rest = opened.len % layout.record_size as u64;
+        if rest != 0 {
+            return failure(
+                Category::SizeMismatch,
+                format!(
+                    "{} bytes is not a whole number of 0x{:X}-byte records ({} left over)",
+                    opened.len, layout.record_size, rest
+                ),
+                Vec::new(),
+            );
+        }
+        return None;
+    }
+    if opened.stored.is_some() {
+        return None;
+    }
+    let bytes = opened.bytes();
+
+    let layout = match header::layout() {
+        Ok(layout) => layout,
+        Err(e) => return failure(Category::BadHeader, e.to_string(), Vec::new()),
+    };
+    let mut payload = bytes[options.header_size()..].to_vec();
+    if let Some(key) = &options.xor_key {
+        key.apply(&mut payload);
+    }
+    let brute_forced = || {
+        options
+            .xor_key
+            .is_none()
+            .then(|| xor::brute_force(&payload))
+            .flatten()
+    };
+    let decompressed = match compression::decompress(&payload) {
+        Ok(decompressed) => decompressed,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            return failure(Category::Unsupported, e.to_string(), Vec::new());
+        }
+        Err(e) => match brute_forced() {
+            Some((_, decompressed)) => decompressed,
+            None => {
+                let (category, notes) = undecodable(path, &bytes, &header, &payload, &layout);
+                return failure(category, e.to_string(), notes);
+            }
+        },
+    };
+
+    let mismatches = header::size_mismatches(
+        &layout,
+        &header,
+        payload.len() - decompressed.trailing,
+        Some(decompressed.data.len()),
+    );
+    if let Some(&(name, recorded, actual)) = mismatches.first() {
+        return failure(
+            Category::SizeMismatch,
+            format!("header {} is {}, actual size is {}", name, recorded, actual),
+            Vec::new(),
+        );
+    }
+
+    if ido::detect_payload(&decompressed.data) == PayloadKind::Xml {
+        let (text, _, had_errors) = EUC_KR.decode(&decompressed.data);
+        if let Err(e) = xml::Document::parse(&text) {
+            let notes = if had_errors {
+                vec!["text has bytes that are not EUC-KR".to_string()]
+            } else {
+                Vec::new()
+            };
+            return failure(Category::DecodeError, e.to_string(), notes);
+        }
+    }
+    None
+}
+
+/// Why a payload that does not decompress fails: a stream that breaks
+/// off, or no stream where one should start.
+fn undecodable(
+    path: &Path,
+    bytes: &[u8],
+    header: &[u8],
+    payload: &[u8],
+    layout: &Layout,
+) -> (Category, Vec<String>) {
+    let mut notes: Vec<String> = header::size_mismatches(layout, header, payload.len(), None)
+        .into_iter()
+        .map(|(name, recorded, actual)| {
+            format!(
+                "header {} is {}, payload is {} bytes",
+                name, recorded, actual
+            )
+        })
+        .collect();
+    if compression::starts_any_stream(payload) {
+        if let Some((_, damage)) = compression::recover(payload) {
+            notes.push(format!(
+                "decodes up to file offset 0x{:X}",
+                header.len() + damage.offset
+            ));
+        }
+        return (Category::Damaged, notes);
+    }
+    if let Ok(repair::Outcome::Repaired(repaired)) = repair::repair(path, bytes) {
+        notes.push(format!("idoc repair can fix it: {}", repaired.notes[0]));
+    } else {
+        let head: Vec<String> = payload
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        notes.push(format!("starts with {}", head.join(" ")));
+    }
+    (Category::NotCompressed, notes)
+}
+
+/// Failures grouped by category, as text.
+pub fn text(report: &Report) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Triaged {} files: {} ok, {} failed",
+        report.total,
+        report.ok,
+        report.failures.len()
+    )
+    .unwrap();
+
+    let mut groups: BTreeMap<Category, Vec<&Failure>> = BTreeMap::new();
+    for failure in &report.failures {
+        groups.entry(failure.category).or_default().push(failure);
+    }
+    for (category, failures) in groups {
+        writeln!(out, "\n## {} ({})", category.title(), failures.len()).unwrap();
+        writeln!(out, "{}", category.description()).unwrap();
+        for failure in failures.iter().take(MAX_LISTED) {
+            write!(out, "  {}: {}", failure.path, failure.error).unwrap();
+            if !failure.notes.is_empty() {
+                write!(out, " ({})", failure.notes.join("; ")).unwrap();
+            }
+            out.push('\n');
+        }
+        if failures.len() > MAX_LISTED {
+            writeln!(out, "  ... and {} more", failures.len() - MAX_LISTED).unwrap();
+        }
+    }
+    out
+}
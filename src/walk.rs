@@ -0,0 +1,67 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Gitignore-style file excluding paths from [`ido_files`], read in the
+/// scanned directory, the directories above it and those below it.
+pub const IGNORE_FILE: &str = ".idocignore";
+
+/// Every `.ido` file below `dir` not excluded by an [`IGNORE_FILE`],
+/// sorted so reports are stable. Symlinked directories are not followed.
+pub fn ido_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .parents(true)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .build();
+    for entry in walker {
+        let entry = entry.map_err(|e| {
+            let message = e.to_string();
+            e.into_io_error()
+                .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidData, message))
+        })?;
+        if entry.file_type().is_some_and(|t| !t.is_dir()) && is_ido(entry.path()) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Every file below `dir` accepted by `filter`, sorted.
+/// Symlinked directories are not followed.
+pub fn files(dir: &Path, filter: impl Fn(&Path) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if filter(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+pub fn is_ido(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ido"))
+}
+
+/// `path` relative to `base`, with forward slashes on every platform.
+pub fn relative(path: &Path, base: &Path) -> String {
+    let rel = path.strip_prefix(base).unwrap_or(path);
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,21 @@
+//! Warnings emitted during a run, kept so they can be summed up at its
+//! end instead of scrolling away in batch output.
+
+use std::sync::Mutex;
+
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Print a warning and remember it for [`take`].
+pub fn warn(message: impl Into<String>) {
+    let message = message.into();
+    eprintln!("Warning: {}", message);
+    WARNINGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(message);
+}
+
+/// The warnings emitted so far, in order, clearing the list.
+pub fn take() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap_or_else(|e| e.into_inner()))
+}
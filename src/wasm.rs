@@ -0,0 +1,64 @@
+//! Browser bindings, for converting files client-side on a static page
+//! (see `web/`). Built for wasm32-unknown-unknown with the `wasm`
+//! feature and wasm-bindgen; mirrors the C functions in [`crate::ffi`].
+
+use crate::ido::PayloadKind;
+use crate::text;
+use std::io;
+use wasm_bindgen::prelude::*;
+
+fn error(e: io::Error) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// A decompiled file and what it held, so the page can name the
+/// download.
+#[wasm_bindgen]
+pub struct Decompiled {
+    kind: PayloadKind,
+    output: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Decompiled {
+    /// `xml`, `shopdb`, or the kind of raw payload.
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.name().to_string()
+    }
+
+    /// Extension for the output: `xml`, `csv`, or the payload's own.
+    #[wasm_bindgen(getter)]
+    pub fn extension(&self) -> String {
+        self.kind.extension().to_string()
+    }
+
+    /// UTF-8 text for XML and databases, the payload otherwise.
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> Vec<u8> {
+        self.output.clone()
+    }
+}
+
+/// Decompile a whole .ido file: XML with the metadata comments that
+/// compile it back, shop databases as CSV, anything else as the raw
+/// payload.
+#[wasm_bindgen]
+pub fn decompile(data: &[u8]) -> Result<Decompiled, JsError> {
+    let (kind, output) = text::decompile(data.to_vec()).map_err(error)?;
+    Ok(Decompiled { kind, output })
+}
+
+/// Compile decompiled XML, with its metadata comments, back into a .ido
+/// file.
+#[wasm_bindgen]
+pub fn compile(xml: &str) -> Result<Vec<u8>, JsError> {
+    text::compile(xml).map_err(error)
+}
+
+/// Parse a stored shop database into a JSON array of records.
+#[wasm_bindgen(js_name = parseShopDb)]
+pub fn parse_shopdb(data: &[u8]) -> Result<String, JsError> {
+    let json = text::shopdb_json(data).map_err(error)?;
+    Ok(String::from_utf8(json).expect("JSON is UTF-8"))
+}
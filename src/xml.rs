@@ -0,0 +1,239 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Structured form of a decompiled XML payload, as written to YAML and
+/// other serde formats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    /// Contents of the `<?xml ... ?>` declaration, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declaration: Option<String>,
+    pub root: Element,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Element {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub attributes: IndexMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Node>,
+}
+
+/// Child nodes serialize untagged: text as a plain string, comments as
+/// `{comment: ...}` and elements as their own mapping.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Node {
+    Text(String),
+    Comment { comment: String },
+    Element(Element),
+}
+
+impl Document {
+    /// Parse XML text. Whitespace-only text between elements is dropped
+    /// and other text beside elements is trimmed; an element holding only
+    /// text keeps it as it is. Names keep their namespace prefixes, and
+    /// `xmlns` declarations stay among the attributes.
+    ///
+    /// A DOCTYPE, processing instructions and CDATA sections have no
+    /// place in the structure, so documents holding them are refused
+    /// rather than changed.
+    pub fn parse(xml: &str) -> io::Result<Document> {
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let doc = roxmltree::Document::parse_with_options(xml, options).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse XML: {}", e),
+            )
+        })?;
+
+        let comments: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.is_comment())
+            .map(|n| n.range())
+            .collect();
+        let outside_comments = |at: usize| !comments.iter().any(|c| c.contains(&at));
+        let unkept = if xml[..doc.root_element().range().start].contains("<!DOCTYPE") {
+            Some("a DOCTYPE")
+        } else if doc.descendants().any(|n| n.is_pi()) {
+            Some("a processing instruction")
+        } else if xml
+            .match_indices("<![CDATA[")
+            .any(|(at, _)| outside_comments(at))
+        {
+            Some("a CDATA section")
+        } else {
+            None
+        };
+        if let Some(unkept) = unkept {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "XML holds {}, which structured formats can't keep; decompile it to XML instead",
+                    unkept
+                ),
+            ));
+        }
+
+        Ok(Document {
+            declaration: declaration(xml),
+            root: element(xml, doc.root_element()),
+        })
+    }
+}
+
+fn declaration(xml: &str) -> Option<String> {
+    let rest = xml
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .strip_prefix("<?xml")?;
+    let end = rest.find("?>")?;
+    Some(rest[..end].trim().to_string())
+}
+
+/// The qualified name and attributes of the start tag `node` begins with,
+/// `xmlns` declarations included, in source order.
+fn start_tag_parts(xml: &str, node: roxmltree::Node) -> (String, IndexMap<String, String>) {
+    let tag = &xml[node.range()];
+    let name_end = tag[1..]
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .map_or(tag.len(), |i| i + 1);
+    let name = tag[1..name_end].to_string();
+
+    // roxmltree lists attributes in source order but leaves out
+    // namespace declarations, which are read back from the namespaces.
+    let mut values = node.attributes();
+    let mut attributes = IndexMap::new();
+    let mut rest = &tag[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with(['/', '>']) {
+            break;
+        }
+        let Some(eq) = rest.find('=') else { break };
+        let qname = rest[..eq].trim_end();
+        let value_part = rest[eq + 1..].trim_start();
+        let Some(quote) = value_part.chars().next() else {
+            break;
+        };
+        let Some(close) = value_part[1..].find(quote) else {
+            break;
+        };
+        rest = &value_part[close + 2..];
+        let value = if qname == "xmlns" || qname.starts_with("xmlns:") {
+            let prefix = qname.strip_prefix("xmlns:");
+            node.namespaces()
+                .find(|ns| ns.name() == prefix)
+                .map(|ns| ns.uri().to_string())
+        } else {
+            values.next().map(|a| a.value().to_string())
+        };
+        attributes.insert(qname.to_string(), value.unwrap_or_default());
+    }
+    (name, attributes)
+}
+
+fn element(xml: &str, node: roxmltree::Node) -> Element {
+    let (name, attributes) = start_tag_parts(xml, node);
+    let text_only = node.children().all(|child| child.is_text());
+
+    let children = node
+        .children()
+        .filter_map(|child| {
+            if child.is_element() {
+                Some(Node::Element(element(xml, child)))
+            } else if child.is_comment() {
+                child.text().map(|c| Node::Comment {
+                    comment: c.trim().to_string(),
+                })
+            } else if child.is_text() && text_only {
+                child.text().map(|t| Node::Text(t.to_string()))
+            } else if child.is_text() {
+                child
+                    .text()
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(|t| Node::Text(t.to_string()))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    Element {
+        name,
+        attributes,
+        children,
+    }
+}
+
+impl Document {
+    /// Serialize back to XML text, one element per line indented by two
+    /// spaces. Elements holding a single text node stay on one line.
+    pub fn to_xml(&self) -> String {
+        let mut out = String::new();
+        if let Some(declaration) = &self.declaration {
+            out.push_str(&format!("<?xml {}?>\n", declaration));
+        }
+        write_element(&mut out, &self.root, 0);
+        out
+    }
+}
+
+/// Append `node` at the given nesting depth.
+pub fn write_node(out: &mut String, node: &Node, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match node {
+        Node::Text(text) => out.push_str(&format!("{}{}\n", indent, escape(text, false))),
+        Node::Comment { comment } => out.push_str(&format!("{}<!-- {} -->\n", indent, comment)),
+        Node::Element(element) => write_element(out, element, depth),
+    }
+}
+
+/// `<name attr="value"`, without the closing `>`.
+pub fn start_tag(name: &str, attributes: &IndexMap<String, String>) -> String {
+    let mut out = format!("<{}", name);
+    for (name, value) in attributes {
+        out.push_str(&format!(" {}=\"{}\"", name, escape(value, true)));
+    }
+    out
+}
+
+fn write_element(out: &mut String, element: &Element, depth: usize) {
+    let indent = "  ".repeat(depth);
+    out.push_str(&indent);
+    out.push_str(&start_tag(&element.name, &element.attributes));
+
+    match element.children.as_slice() {
+        [] => out.push_str("/>\n"),
+        [Node::Text(text)] => {
+            out.push_str(&format!(">{}</{}>\n", escape(text, false), element.name));
+        }
+        children => {
+            out.push_str(">\n");
+            for child in children {
+                write_node(out, child, depth + 1);
+            }
+            out.push_str(&format!("{}</{}>\n", indent, element.name));
+        }
+    }
+}
+
+fn escape(text: &str, attribute: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,68 @@
+use crate::compression::{self, Compression, Decompressed};
+use std::fmt;
+use std::str::FromStr;
+
+/// Repeating XOR key some client versions apply to the stored bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorKey(pub Vec<u8>);
+
+impl FromStr for XorKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let digits = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        let key = hex::decode(digits).map_err(|e| format!("invalid hex key: {}", e))?;
+        if key.is_empty() {
+            return Err("XOR key must not be empty".to_string());
+        }
+        Ok(XorKey(key))
+    }
+}
+
+impl fmt::Display for XorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl XorKey {
+    /// XOR `data` in place, starting at the first byte of the key.
+    pub fn apply(&self, data: &mut [u8]) {
+        for (byte, k) in data.iter_mut().zip(self.0.iter().cycle()) {
+            *byte ^= k;
+        }
+    }
+}
+
+/// Try every single-byte key until the payload decompresses.
+///
+/// Only keys that turn the first bytes into a zlib or gzip header are
+/// attempted; raw deflate has no header to check against.
+pub fn brute_force(payload: &[u8]) -> Option<(XorKey, Decompressed)> {
+    for k in 1..=u8::MAX {
+        let key = XorKey(vec![k]);
+        let mut head = payload[..payload.len().min(2)].to_vec();
+        key.apply(&mut head);
+
+        let kind = if compression::is_gzip_header(&head) {
+            Compression::Gzip
+        } else if compression::is_zlib_header(&head) {
+            Compression::Zlib
+        } else {
+            continue;
+        };
+
+        let mut data = payload.to_vec();
+        key.apply(&mut data);
+        if let Ok(decompressed) = compression::decompress_with(&data, kind)
+            && !decompressed.data.is_empty()
+        {
+            return Some((key, decompressed));
+        }
+    }
+    None
+}
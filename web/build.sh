@@ -0,0 +1,9 @@
+#!/bin/sh
+# Build the browser converter into web/pkg; serve web/ with any static
+# file server. Needs the wasm32-unknown-unknown target and a
+# wasm-bindgen CLI matching the wasm-bindgen version in Cargo.lock.
+set -e
+cd "$(dirname "$0")/.."
+cargo build --lib --release --target wasm32-unknown-unknown --no-default-features --features wasm
+wasm-bindgen --target web --no-typescript --out-dir web/pkg \
+    target/wasm32-unknown-unknown/release/idoc.wasm